@@ -0,0 +1,38 @@
+-- CreateTable
+CREATE TABLE "Identity" (
+    "id" TEXT NOT NULL,
+    "userId" TEXT NOT NULL,
+    "provider" TEXT NOT NULL,
+    "subject" TEXT NOT NULL,
+    "email" TEXT,
+    "lastLoginAt" TIMESTAMP(3),
+    "createdAt" TIMESTAMP(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "Identity_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateTable
+CREATE TABLE "OidcLoginState" (
+    "id" TEXT NOT NULL,
+    "stateHash" TEXT NOT NULL,
+    "provider" TEXT NOT NULL,
+    "codeVerifier" TEXT NOT NULL,
+    "nonce" TEXT NOT NULL,
+    "linkUserId" TEXT,
+    "expiresAt" TIMESTAMP(3) NOT NULL,
+    "createdAt" TIMESTAMP(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "OidcLoginState_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "Identity_provider_subject_key" ON "Identity"("provider", "subject");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "Identity_userId_provider_key" ON "Identity"("userId", "provider");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "OidcLoginState_stateHash_key" ON "OidcLoginState"("stateHash");
+
+-- AddForeignKey
+ALTER TABLE "Identity" ADD CONSTRAINT "Identity_userId_fkey" FOREIGN KEY ("userId") REFERENCES "User"("id") ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,10 @@
+-- AlterTable
+ALTER TABLE "AuditLog" ADD COLUMN     "after" JSONB,
+ADD COLUMN     "before" JSONB,
+ADD COLUMN     "changes" JSONB;
+
+-- CreateIndex
+CREATE INDEX "AuditLog_actorId_idx" ON "AuditLog"("actorId");
+
+-- CreateIndex
+CREATE INDEX "AuditLog_action_idx" ON "AuditLog"("action");
@@ -0,0 +1,9 @@
+-- AlterTable
+ALTER TABLE "Job" ADD COLUMN     "uniqueKey" TEXT;
+
+-- AlterTable
+ALTER TABLE "Product" ADD COLUMN     "lowStockSince" TIMESTAMP(3),
+ADD COLUMN     "lowStockThreshold" INTEGER NOT NULL DEFAULT 5;
+
+-- CreateIndex
+CREATE UNIQUE INDEX "Job_uniqueKey_key" ON "Job"("uniqueKey");
@@ -0,0 +1,32 @@
+-- CreateEnum
+CREATE TYPE "MovementType" AS ENUM ('sale', 'restock', 'adjustment', 'return', 'reservation');
+
+-- CreateTable
+CREATE TABLE "InventoryMovement" (
+    "id" TEXT NOT NULL,
+    "productId" TEXT NOT NULL,
+    "kind" "MovementType" NOT NULL,
+    "quantity" INTEGER NOT NULL,
+    "stockAfter" INTEGER NOT NULL,
+    "reference" TEXT,
+    "actorId" TEXT,
+    "note" TEXT,
+    "createdAt" TIMESTAMP(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "InventoryMovement_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE INDEX "InventoryMovement_productId_createdAt_idx" ON "InventoryMovement"("productId", "createdAt");
+
+-- CreateIndex
+CREATE INDEX "InventoryMovement_reference_idx" ON "InventoryMovement"("reference");
+
+-- AddForeignKey
+ALTER TABLE "InventoryMovement" ADD CONSTRAINT "InventoryMovement_productId_fkey" FOREIGN KEY ("productId") REFERENCES "Product"("id") ON DELETE CASCADE ON UPDATE CASCADE;
+
+-- Opening balances, so the ledger of every existing product sums to its current stock.
+INSERT INTO "InventoryMovement" ("id", "productId", "kind", "quantity", "stockAfter", "note")
+SELECT gen_random_uuid()::TEXT, "id", 'adjustment', "stock", "stock", 'Opening balance'
+FROM "Product"
+WHERE "stock" <> 0;
@@ -0,0 +1,61 @@
+-- AlterTable
+ALTER TABLE "Order" ADD COLUMN     "warehouseId" TEXT;
+
+-- AlterTable
+ALTER TABLE "InventoryMovement" ADD COLUMN     "warehouseId" TEXT;
+
+-- CreateTable
+CREATE TABLE "Warehouse" (
+    "id" TEXT NOT NULL,
+    "code" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "address" TEXT,
+    "isDefault" BOOLEAN NOT NULL DEFAULT false,
+    "active" BOOLEAN NOT NULL DEFAULT true,
+    "createdAt" TIMESTAMP(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updatedAt" TIMESTAMP(3) NOT NULL,
+
+    CONSTRAINT "Warehouse_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateTable
+CREATE TABLE "StockLevel" (
+    "id" TEXT NOT NULL,
+    "productId" TEXT NOT NULL,
+    "warehouseId" TEXT NOT NULL,
+    "quantity" INTEGER NOT NULL DEFAULT 0,
+    "updatedAt" TIMESTAMP(3) NOT NULL,
+
+    CONSTRAINT "StockLevel_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "Warehouse_code_key" ON "Warehouse"("code");
+
+-- CreateIndex
+CREATE INDEX "StockLevel_warehouseId_idx" ON "StockLevel"("warehouseId");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "StockLevel_productId_warehouseId_key" ON "StockLevel"("productId", "warehouseId");
+
+-- AddForeignKey
+ALTER TABLE "Order" ADD CONSTRAINT "Order_warehouseId_fkey" FOREIGN KEY ("warehouseId") REFERENCES "Warehouse"("id") ON DELETE SET NULL ON UPDATE CASCADE;
+
+-- AddForeignKey
+ALTER TABLE "InventoryMovement" ADD CONSTRAINT "InventoryMovement_warehouseId_fkey" FOREIGN KEY ("warehouseId") REFERENCES "Warehouse"("id") ON DELETE SET NULL ON UPDATE CASCADE;
+
+-- AddForeignKey
+ALTER TABLE "StockLevel" ADD CONSTRAINT "StockLevel_productId_fkey" FOREIGN KEY ("productId") REFERENCES "Product"("id") ON DELETE CASCADE ON UPDATE CASCADE;
+
+-- AddForeignKey
+ALTER TABLE "StockLevel" ADD CONSTRAINT "StockLevel_warehouseId_fkey" FOREIGN KEY ("warehouseId") REFERENCES "Warehouse"("id") ON DELETE RESTRICT ON UPDATE CASCADE;
+
+-- Everything in stock so far sits in one default warehouse, so stock levels add up to
+-- `Product.stock` from the start.
+INSERT INTO "Warehouse" ("id", "code", "name", "isDefault", "updatedAt")
+VALUES (gen_random_uuid()::TEXT, 'MAIN', 'Main warehouse', true, CURRENT_TIMESTAMP);
+
+INSERT INTO "StockLevel" ("id", "productId", "warehouseId", "quantity", "updatedAt")
+SELECT gen_random_uuid()::TEXT, p."id", w."id", p."stock", CURRENT_TIMESTAMP
+FROM "Product" p, "Warehouse" w
+WHERE w."code" = 'MAIN' AND p."stock" <> 0;
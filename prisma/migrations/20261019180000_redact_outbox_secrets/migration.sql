@@ -0,0 +1,5 @@
+-- Blank one-time links in messages that were already delivered or given up on.
+UPDATE "OutboxMessage"
+SET "body" = '[redacted]'
+WHERE "template" IN ('welcome', 'verify_email', 'password_reset')
+  AND "status" IN ('sent', 'failed');
@@ -0,0 +1,5 @@
+-- Products created before SKUs existed get one derived from their id, so the catalog export
+-- never writes a row the import would reject.
+UPDATE "Product"
+SET "sku" = 'P-' || "id"
+WHERE "sku" IS NULL;
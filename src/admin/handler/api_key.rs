@@ -0,0 +1,172 @@
+use crate::admin::model::{ApiKeyPayload, ApiKeyResponse};
+use crate::audit::AuditEvent;
+use crate::auth::api_key as keys;
+use crate::auth::model::Claims;
+use crate::auth::token::hash_token;
+use crate::prisma::{api_key, PrismaClient};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use std::sync::Arc;
+
+fn api_key_response(key_record: api_key::Data) -> ApiKeyResponse {
+    ApiKeyResponse {
+        id: key_record.id,
+        name: key_record.name,
+        prefix: key_record.prefix,
+        scopes: key_record.scopes,
+        created_by_id: key_record.created_by_id,
+        expires_at: key_record.expires_at.map(|t| t.to_rfc3339()),
+        last_used_at: key_record.last_used_at.map(|t| t.to_rfc3339()),
+        revoked_at: key_record.revoked_at.map(|t| t.to_rfc3339()),
+        created_at: key_record.created_at.to_rfc3339(),
+    }
+}
+
+/// Only a signed-in admin may manage keys; a key can't mint or revoke other keys.
+fn human_admin(req: &HttpRequest) -> Option<String> {
+    req.extensions()
+        .get::<Claims>()
+        .filter(|claims| claims.is_admin && claims.api_key_id.is_none())
+        .map(|claims| claims.sub.clone())
+}
+
+pub async fn create_api_key(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    payload: web::Json<ApiKeyPayload>,
+) -> impl Responder {
+    let admin_id = match human_admin(&req) {
+        Some(admin_id) => admin_id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."})),
+    };
+
+    if payload.name.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "Name is required"}));
+    }
+    if payload.scopes.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "At least one scope is required"}));
+    }
+    let invalid = payload
+        .scopes
+        .iter()
+        .filter(|scope| !keys::is_valid_scope(scope))
+        .collect::<Vec<_>>();
+    if !invalid.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "Scopes must look like <resource>:read, <resource>:write or *",
+            "invalid_scopes": invalid,
+        }));
+    }
+    if payload.expires_in_days.map_or(false, |days| days <= 0) {
+        return HttpResponse::BadRequest()
+            .json(json!({"error": "expires_in_days must be positive"}));
+    }
+
+    let (prefix, key) = keys::generate();
+    let expires_at = payload
+        .expires_in_days
+        .map(|days| (Utc::now() + Duration::days(days)).fixed_offset());
+
+    match prisma_client
+        .api_key()
+        .create(
+            payload.name.trim().to_string(),
+            prefix,
+            hash_token(&key),
+            admin_id,
+            vec![
+                api_key::scopes::set(payload.scopes.clone()),
+                api_key::expires_at::set(expires_at),
+            ],
+        )
+        .exec()
+        .await
+    {
+        // The key itself is only ever shown here.
+        Ok(key_record) => {
+            AuditEvent::new("api_key.created", "api_key")
+                .by(&req)
+                .entity(&key_record.id)
+                .after(json!({
+                    "name": key_record.name,
+                    "prefix": key_record.prefix,
+                    "scopes": key_record.scopes,
+                    "expiresAt": key_record.expires_at,
+                }))
+                .record_or_log(&prisma_client)
+                .await;
+
+            HttpResponse::Created().json(json!({
+                "key": key,
+                "api_key": api_key_response(key_record),
+            }))
+        }
+        Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+    }
+}
+
+pub async fn get_api_keys(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    if human_admin(&req).is_none() {
+        return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}));
+    }
+
+    match prisma_client
+        .api_key()
+        .find_many(vec![])
+        .order_by(api_key::created_at::order(
+            prisma_client_rust::Direction::Desc,
+        ))
+        .exec()
+        .await
+    {
+        Ok(key_records) => HttpResponse::Ok().json(
+            key_records
+                .into_iter()
+                .map(api_key_response)
+                .collect::<Vec<_>>(),
+        ),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+    }
+}
+
+pub async fn revoke_api_key(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    key_id: web::Path<String>,
+) -> impl Responder {
+    if human_admin(&req).is_none() {
+        return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}));
+    }
+
+    let key_id = key_id.into_inner();
+    match prisma_client
+        .api_key()
+        .update_many(
+            vec![
+                api_key::id::equals(key_id.clone()),
+                api_key::revoked_at::equals(None),
+            ],
+            vec![api_key::revoked_at::set(Some(Utc::now().fixed_offset()))],
+        )
+        .exec()
+        .await
+    {
+        Ok(0) => {
+            HttpResponse::NotFound().json(json!({"error": "API key not found or already revoked"}))
+        }
+        Ok(_) => {
+            AuditEvent::new("api_key.revoked", "api_key")
+                .by(&req)
+                .entity(&key_id)
+                .record_or_log(&prisma_client)
+                .await;
+
+            HttpResponse::Ok().json(json!({"message": "API key revoked"}))
+        }
+        Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+    }
+}
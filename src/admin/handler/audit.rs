@@ -0,0 +1,209 @@
+use crate::admin::model::AuditQuery;
+use crate::auth::model::Claims;
+use crate::prisma::{audit_log, PrismaClient};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::{NaiveDate, TimeZone, Utc};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Upper bound on rows in one CSV export.
+const EXPORT_LIMIT: i64 = 10_000;
+
+fn parse_date(value: &str, field: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("{} must be a date in YYYY-MM-DD format", field))
+}
+
+fn filters(query: &AuditQuery) -> Result<Vec<audit_log::WhereParam>, String> {
+    let mut filters = vec![];
+    if let Some(actor_id) = &query.actor_id {
+        filters.push(audit_log::actor_id::equals(Some(actor_id.clone())));
+    }
+    if let Some(action) = &query.action {
+        filters.push(audit_log::action::equals(action.clone()));
+    }
+    if let Some(entity_type) = &query.entity_type {
+        filters.push(audit_log::entity_type::equals(entity_type.clone()));
+    }
+    if let Some(entity_id) = &query.entity_id {
+        filters.push(audit_log::entity_id::equals(Some(entity_id.clone())));
+    }
+    if let Some(from) = &query.from {
+        let from = parse_date(from, "from")?.and_hms_opt(0, 0, 0).unwrap();
+        filters.push(audit_log::created_at::gte(
+            Utc.from_utc_datetime(&from).fixed_offset(),
+        ));
+    }
+    if let Some(to) = &query.to {
+        let to = parse_date(to, "to")?.and_hms_opt(23, 59, 59).unwrap();
+        filters.push(audit_log::created_at::lte(
+            Utc.from_utc_datetime(&to).fixed_offset(),
+        ));
+    }
+    Ok(filters)
+}
+
+fn entry_json(entry: &audit_log::Data) -> Value {
+    json!({
+        "id": entry.id,
+        "actorId": entry.actor_id,
+        "action": entry.action,
+        "entityType": entry.entity_type,
+        "entityId": entry.entity_id,
+        "ipAddress": entry.ip_address,
+        "before": entry.before,
+        "after": entry.after,
+        "changes": entry.changes,
+        "metadata": entry.metadata,
+        "createdAt": entry.created_at,
+    })
+}
+
+pub async fn get_audit_logs(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    query: web::Query<AuditQuery>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let page = query.page.unwrap_or(1).max(1);
+        let limit = query.limit.unwrap_or(10).clamp(1, 100);
+        let where_params = match filters(&query) {
+            Ok(where_params) => where_params,
+            Err(err) => return HttpResponse::BadRequest().json(json!({"error": err})),
+        };
+        let count_params = match filters(&query) {
+            Ok(count_params) => count_params,
+            Err(err) => return HttpResponse::BadRequest().json(json!({"error": err})),
+        };
+
+        let (total_items, entries) = match prisma_client
+            ._batch((
+                prisma_client.audit_log().count(count_params),
+                prisma_client
+                    .audit_log()
+                    .find_many(where_params)
+                    .order_by(audit_log::created_at::order(
+                        prisma_client_rust::Direction::Desc,
+                    ))
+                    .skip((page - 1) * limit)
+                    .take(limit),
+            ))
+            .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+
+        HttpResponse::Ok().json(json!({
+            "entries": entries.iter().map(entry_json).collect::<Vec<_>>(),
+            "pagination": {
+                "currentPage": page,
+                "totalPages": (total_items as f64 / limit as f64).ceil() as i64,
+                "totalItems": total_items,
+                "limit": limit,
+            }
+        }))
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+fn json_cell(value: &Option<Value>) -> String {
+    value
+        .as_ref()
+        .map(|value| value.to_string())
+        .unwrap_or_default()
+}
+
+/// Same filters as `get_audit_logs`, without paging, as a CSV download.
+pub async fn export_audit_logs(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    query: web::Query<AuditQuery>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let where_params = match filters(&query) {
+            Ok(where_params) => where_params,
+            Err(err) => return HttpResponse::BadRequest().json(json!({"error": err})),
+        };
+        let entries = match prisma_client
+            .audit_log()
+            .find_many(where_params)
+            .order_by(audit_log::created_at::order(
+                prisma_client_rust::Direction::Desc,
+            ))
+            .take(EXPORT_LIMIT)
+            .exec()
+            .await
+        {
+            Ok(entries) => entries,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        let header = [
+            "id",
+            "created_at",
+            "actor_id",
+            "action",
+            "entity_type",
+            "entity_id",
+            "ip_address",
+            "changes",
+            "before",
+            "after",
+            "metadata",
+        ];
+        let mut written = writer.write_record(header);
+        for entry in &entries {
+            if written.is_err() {
+                break;
+            }
+            written = writer.write_record([
+                entry.id.clone(),
+                entry.created_at.to_rfc3339(),
+                entry.actor_id.clone().unwrap_or_default(),
+                entry.action.clone(),
+                entry.entity_type.clone(),
+                entry.entity_id.clone().unwrap_or_default(),
+                entry.ip_address.clone().unwrap_or_default(),
+                json_cell(&entry.changes),
+                json_cell(&entry.before),
+                json_cell(&entry.after),
+                json_cell(&entry.metadata),
+            ]);
+        }
+        let body = match written
+            .map_err(|e| e.to_string())
+            .and_then(|_| writer.into_inner().map_err(|e| e.to_string()))
+        {
+            Ok(body) => body,
+            Err(_) => {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Failed to write CSV"}))
+            }
+        };
+
+        HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"audit-log.csv\"",
+            ))
+            .body(body)
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
@@ -0,0 +1,682 @@
+use crate::admin::handler::product::{keep_deleted_links, validate_fields};
+use crate::admin::model::{CatalogExportQuery, CatalogImportQuery, FieldError};
+use crate::audit::{self, AuditEvent};
+use crate::auth::model::Claims;
+use crate::auth::token::client_ip;
+use crate::inventory::{self, Movement};
+use crate::jobs::queue;
+use crate::prisma::{category, product, MovementType, PrismaClient};
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use prisma_client_rust::QueryError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Rows whose existing products are looked up together.
+const IMPORT_BATCH_SIZE: usize = 200;
+/// Upper bound on rows in one import file.
+const IMPORT_ROW_LIMIT: usize = 10_000;
+/// Upper bound on the size of an uploaded import file.
+pub const IMPORT_MAX_BYTES: usize = 10 * 1024 * 1024;
+/// Job kind for imports queued with `background=true`.
+pub const IMPORT_JOB: &str = "catalog.import";
+/// Separates category names in the CSV `categories` column.
+const CATEGORY_SEPARATOR: &str = "|";
+
+#[derive(Clone, Deserialize)]
+struct ImportRow {
+    sku: String,
+    name: String,
+    #[serde(default)]
+    description: String,
+    price: f64,
+    stock: i32,
+    /// Required for new products; left untouched on existing ones when missing.
+    #[serde(default)]
+    imageurl: Option<String>,
+    /// Category names, matched case-insensitively.
+    #[serde(default)]
+    categories: Vec<String>,
+}
+
+/// CSV has no lists, so categories arrive as one `|`-separated cell.
+#[derive(Deserialize)]
+struct CsvRow {
+    sku: String,
+    name: String,
+    #[serde(default)]
+    description: String,
+    price: f64,
+    stock: i32,
+    #[serde(default)]
+    imageurl: Option<String>,
+    #[serde(default)]
+    categories: String,
+}
+
+impl From<CsvRow> for ImportRow {
+    fn from(row: CsvRow) -> Self {
+        ImportRow {
+            sku: row.sku,
+            name: row.name,
+            description: row.description,
+            price: row.price,
+            stock: row.stock,
+            imageurl: row.imageurl.filter(|imageurl| !imageurl.is_empty()),
+            categories: row
+                .categories
+                .split(CATEGORY_SEPARATOR)
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RowError {
+    /// 1-based position among the data rows, not counting the CSV header.
+    row: usize,
+    sku: Option<String>,
+    errors: Vec<FieldError>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Csv,
+    Json,
+}
+
+/// `format` wins over the Content-Type of the upload.
+fn format_of(req: &HttpRequest, format: Option<&str>) -> Result<Format, String> {
+    match format {
+        Some("csv") => Ok(Format::Csv),
+        Some("json") => Ok(Format::Json),
+        Some(other) => Err(format!("Unsupported format {}.", other)),
+        None => {
+            let content_type = req
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("");
+            if content_type.starts_with("text/csv") {
+                Ok(Format::Csv)
+            } else if content_type.starts_with("application/json") {
+                Ok(Format::Json)
+            } else {
+                Err("Send text/csv or application/json, or pass format=csv|json.".to_string())
+            }
+        }
+    }
+}
+
+/// Splits the upload into numbered rows. A row that doesn't parse becomes an error for that
+/// row only; only an unreadable JSON document fails the whole file.
+fn parse_rows(
+    format: Format,
+    body: &[u8],
+) -> Result<Vec<(usize, Result<ImportRow, String>)>, String> {
+    match format {
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(body);
+            Ok(reader
+                .deserialize::<CsvRow>()
+                .enumerate()
+                .map(|(i, row)| (i + 1, row.map(ImportRow::from).map_err(|e| e.to_string())))
+                .collect())
+        }
+        Format::Json => {
+            let rows: Vec<Value> =
+                serde_json::from_slice(body).map_err(|e| format!("Invalid JSON: {}", e))?;
+            Ok(rows
+                .into_iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    (
+                        i + 1,
+                        serde_json::from_value::<ImportRow>(row).map_err(|e| e.to_string()),
+                    )
+                })
+                .collect())
+        }
+    }
+}
+
+/// Writes the parsed rows. Each row stands on its own: invalid rows are reported and skipped,
+/// the rest are created or updated by SKU. With `dry_run` nothing is written and the report
+/// says what would have happened.
+async fn run_import(
+    prisma_client: &PrismaClient,
+    rows: Vec<(usize, Result<ImportRow, String>)>,
+    dry_run: bool,
+    actor_id: Option<String>,
+    ip: Option<String>,
+) -> Result<Value, String> {
+    let category_ids = match prisma_client
+        .category()
+        .find_many(vec![category::deleted_at::equals(None)])
+        .exec()
+        .await
+    {
+        Ok(categories) => categories
+            .into_iter()
+            .map(|c| (c.name.to_lowercase(), c.id))
+            .collect::<HashMap<_, _>>(),
+        Err(_) => return Err("Database error.".to_string()),
+    };
+
+    let mut seen = HashSet::new();
+    let mut errors = vec![];
+    let (mut created, mut updated) = (0, 0);
+
+    for batch in rows.chunks(IMPORT_BATCH_SIZE) {
+        let skus = batch
+            .iter()
+            .filter_map(|(_, row)| row.as_ref().ok().map(|row| row.sku.clone()))
+            .collect::<Vec<_>>();
+        let existing = match prisma_client
+            .product()
+            .find_many(vec![product::sku::in_vec(skus)])
+            .with(product::categories::fetch(vec![]))
+            .exec()
+            .await
+        {
+            Ok(products) => products
+                .into_iter()
+                .filter_map(|p| p.sku.clone().map(|sku| (sku, p)))
+                .collect::<HashMap<_, _>>(),
+            Err(_) => {
+                for (row_number, _) in batch {
+                    errors.push(RowError {
+                        row: *row_number,
+                        sku: None,
+                        errors: vec![FieldError::new("row", "Database error.")],
+                    });
+                }
+                continue;
+            }
+        };
+
+        for (row_number, row) in batch {
+            let row = match row {
+                Ok(row) => row,
+                Err(err) => {
+                    errors.push(RowError {
+                        row: *row_number,
+                        sku: None,
+                        errors: vec![FieldError::new("row", err)],
+                    });
+                    continue;
+                }
+            };
+
+            let mut field_errors = validate_fields(
+                Some(&row.name),
+                Some(row.price),
+                Some(row.stock),
+                row.imageurl.as_deref(),
+            );
+            if row.sku.trim().is_empty() {
+                field_errors.push(FieldError::new("sku", "SKU must not be empty."));
+            } else if !seen.insert(row.sku.clone()) {
+                field_errors.push(FieldError::new("sku", "SKU appears more than once."));
+            }
+            let before = existing.get(&row.sku);
+            // Deleted and archived products keep their SKU. Writing to one would bring it
+            // back without anyone asking, so it has to be restored first.
+            if before.map_or(false, |before| before.deleted_at.is_some()) {
+                field_errors.push(FieldError::new(
+                    "sku",
+                    "SKU belongs to a deleted product; restore it before importing it.",
+                ));
+            }
+            if before.is_none() && row.imageurl.is_none() {
+                field_errors.push(FieldError::new(
+                    "imageurl",
+                    "Image URL is required for new products.",
+                ));
+            }
+            let mut ids = vec![];
+            let mut unknown = vec![];
+            for name in &row.categories {
+                match category_ids.get(&name.to_lowercase()) {
+                    Some(id) => ids.push(id.clone()),
+                    None => unknown.push(name.as_str()),
+                }
+            }
+            if !unknown.is_empty() {
+                field_errors.push(FieldError::new(
+                    "categories",
+                    &format!("Unknown categories: {}.", unknown.join(", ")),
+                ));
+            }
+            if !field_errors.is_empty() {
+                errors.push(RowError {
+                    row: *row_number,
+                    sku: Some(row.sku.clone()),
+                    errors: field_errors,
+                });
+                continue;
+            }
+
+            if dry_run {
+                match before {
+                    Some(_) => updated += 1,
+                    None => created += 1,
+                }
+                continue;
+            }
+
+            let movement = Movement::new(MovementType::Adjustment)
+                .reference("import")
+                .actor(actor_id.clone());
+            // The inner error is the default warehouse's stock when the row would take more out
+            // of it than it holds.
+            let result: Result<Result<product::Data, i32>, QueryError> = match before {
+                Some(before) => {
+                    let id = before.id.clone();
+                    let stock = row.stock;
+                    let mut update_operations = vec![
+                        product::name::set(row.name.clone()),
+                        product::description::set(row.description.clone()),
+                        product::price::set(row.price),
+                    ];
+                    if let Some(imageurl) = &row.imageurl {
+                        update_operations.push(product::image_url::set(imageurl.clone()));
+                    }
+                    prisma_client
+                        ._transaction()
+                        .run(|client| {
+                            Box::pin(async move {
+                                if let Err(held) =
+                                    inventory::adjust_to(&client, &id, stock, movement).await?
+                                {
+                                    return Ok(Err(held));
+                                }
+                                let kept = keep_deleted_links(&client, &id, &ids).await?;
+                                update_operations.push(product::categories::set(
+                                    kept.into_iter().map(category::id::equals).collect(),
+                                ));
+                                client
+                                    .product()
+                                    .update(product::id::equals(id), update_operations)
+                                    .with(product::categories::fetch(vec![]))
+                                    .exec()
+                                    .await
+                                    .map(Ok)
+                            })
+                        })
+                        .await
+                }
+                None => {
+                    let row = row.clone();
+                    prisma_client
+                        ._transaction()
+                        .run(|client| {
+                            Box::pin(async move {
+                                let saved = client
+                                    .product()
+                                    .create(
+                                        row.name,
+                                        row.description,
+                                        row.price,
+                                        row.stock,
+                                        row.imageurl.unwrap_or_default(),
+                                        vec![
+                                            product::sku::set(Some(row.sku)),
+                                            product::categories::connect(
+                                                ids.into_iter().map(category::id::equals).collect(),
+                                            ),
+                                        ],
+                                    )
+                                    .with(product::categories::fetch(vec![]))
+                                    .exec()
+                                    .await?;
+                                inventory::open(
+                                    &client,
+                                    &saved.id,
+                                    saved.stock,
+                                    movement.note("Initial stock"),
+                                )
+                                .await?;
+                                Ok(Ok(saved))
+                            })
+                        })
+                        .await
+                }
+            };
+
+            match result {
+                Ok(Ok(saved)) => {
+                    let event = match before {
+                        Some(before) => {
+                            updated += 1;
+                            AuditEvent::new("product.updated", "product")
+                                .before(audit::product_snapshot(before))
+                        }
+                        None => {
+                            created += 1;
+                            AuditEvent::new("product.created", "product")
+                        }
+                    };
+                    event
+                        .actor(actor_id.clone())
+                        .ip(ip.clone())
+                        .entity(&saved.id)
+                        .after(audit::product_snapshot(&saved))
+                        .metadata(json!({"source": "import"}))
+                        .record_or_log(prisma_client)
+                        .await;
+                }
+                Ok(Err(held)) => errors.push(RowError {
+                    row: *row_number,
+                    sku: Some(row.sku.clone()),
+                    errors: vec![FieldError::new(
+                        "stock",
+                        &format!(
+                            "Stock can only go down by what the default warehouse holds ({}).",
+                            held
+                        ),
+                    )],
+                }),
+                Err(_) => errors.push(RowError {
+                    row: *row_number,
+                    sku: Some(row.sku.clone()),
+                    errors: vec![FieldError::new("row", "Could not save product.")],
+                }),
+            }
+        }
+    }
+
+    Ok(json!({
+        "dryRun": dry_run,
+        "total": rows.len(),
+        "created": created,
+        "updated": updated,
+        "failed": errors.len(),
+        "errors": errors,
+    }))
+}
+
+/// Payload of an `IMPORT_JOB`: the upload itself plus who sent it, for the audit log.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportJob {
+    format: Format,
+    dry_run: bool,
+    body: String,
+    actor_id: Option<String>,
+    ip: Option<String>,
+}
+
+/// Runs an import queued by `import_products` with `background=true`.
+pub async fn run_import_job(
+    prisma_client: &PrismaClient,
+    payload: &Value,
+) -> Result<Value, String> {
+    let job: ImportJob = serde_json::from_value(payload.clone()).map_err(|e| e.to_string())?;
+    let rows = parse_rows(job.format, job.body.as_bytes())?;
+    run_import(prisma_client, rows, job.dry_run, job.actor_id, job.ip).await
+}
+
+/// Imports a CSV or JSON catalog and returns the report. Large files can be queued with
+/// `background=true`; the report then ends up in the job's result.
+pub async fn import_products(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    query: web::Query<CatalogImportQuery>,
+    body: web::Bytes,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}));
+        }
+
+        let format = match format_of(&req, query.format.as_deref()) {
+            Ok(format) => format,
+            Err(err) => return HttpResponse::BadRequest().json(json!({"error": err})),
+        };
+        // Parsed up front even for background imports, so a malformed file is rejected now
+        // rather than failing later in the queue.
+        let rows = match parse_rows(format, &body) {
+            Ok(rows) => rows,
+            Err(err) => return HttpResponse::BadRequest().json(json!({"error": err})),
+        };
+        if rows.len() > IMPORT_ROW_LIMIT {
+            return HttpResponse::BadRequest().json(json!({
+                "error": format!("An import can have at most {} rows.", IMPORT_ROW_LIMIT)
+            }));
+        }
+
+        if query.background {
+            let body = match String::from_utf8(body.to_vec()) {
+                Ok(body) => body,
+                Err(_) => {
+                    return HttpResponse::BadRequest()
+                        .json(json!({"error": "The file must be UTF-8 encoded."}))
+                }
+            };
+            let payload = ImportJob {
+                format,
+                dry_run: query.dry_run,
+                body,
+                actor_id: audit::actor_of(&req),
+                ip: client_ip(&req),
+            };
+            return match queue::enqueue(&prisma_client, IMPORT_JOB, json!(payload), None).await {
+                Ok(job) => HttpResponse::Accepted().json(json!({"jobId": job.id})),
+                Err(_) => {
+                    HttpResponse::InternalServerError().json(json!({"error": "Database error."}))
+                }
+            };
+        }
+
+        match run_import(
+            &prisma_client,
+            rows,
+            query.dry_run,
+            audit::actor_of(&req),
+            client_ip(&req),
+        )
+        .await
+        {
+            Ok(report) => HttpResponse::Ok().json(report),
+            Err(err) => HttpResponse::InternalServerError().json(json!({"error": err})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+    }
+}
+
+/// Every live product in the layout `import_products` accepts.
+pub async fn export_products(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    query: web::Query<CatalogExportQuery>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}));
+        }
+
+        let format = match query.format.as_deref().unwrap_or("csv") {
+            "csv" => Format::Csv,
+            "json" => Format::Json,
+            other => {
+                return HttpResponse::BadRequest()
+                    .json(json!({"error": format!("Unsupported format {}.", other)}))
+            }
+        };
+
+        let products = match prisma_client
+            .product()
+            .find_many(vec![product::deleted_at::equals(None)])
+            .with(product::categories::fetch(vec![
+                category::deleted_at::equals(None),
+            ]))
+            .order_by(product::name::order(prisma_client_rust::Direction::Asc))
+            .exec()
+            .await
+        {
+            Ok(products) => products,
+            Err(_) => {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Database error."}))
+            }
+        };
+        let category_names = |p: &product::Data| -> Vec<String> {
+            p.categories
+                .as_ref()
+                .map_or(vec![], |cats| cats.iter().map(|c| c.name.clone()).collect())
+        };
+
+        match format {
+            Format::Json => {
+                let rows = products
+                    .iter()
+                    .map(|p| {
+                        json!({
+                            "sku": p.sku,
+                            "name": p.name,
+                            "description": p.description,
+                            "price": p.price,
+                            "stock": p.stock,
+                            "imageurl": p.image_url,
+                            "categories": category_names(p),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                HttpResponse::Ok()
+                    .insert_header((
+                        "Content-Disposition",
+                        "attachment; filename=\"products.json\"",
+                    ))
+                    .json(rows)
+            }
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(vec![]);
+                let header = [
+                    "sku",
+                    "name",
+                    "description",
+                    "price",
+                    "stock",
+                    "imageurl",
+                    "categories",
+                ];
+                let mut written = writer.write_record(header);
+                for p in &products {
+                    if written.is_err() {
+                        break;
+                    }
+                    written = writer.write_record([
+                        p.sku.clone().unwrap_or_default(),
+                        p.name.clone(),
+                        p.description.clone(),
+                        p.price.to_string(),
+                        p.stock.to_string(),
+                        p.image_url.clone(),
+                        category_names(p).join(CATEGORY_SEPARATOR),
+                    ]);
+                }
+                let body = match written
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| writer.into_inner().map_err(|e| e.to_string()))
+                {
+                    Ok(body) => body,
+                    Err(_) => {
+                        return HttpResponse::InternalServerError()
+                            .json(json!({"error": "Failed to write CSV."}))
+                    }
+                };
+
+                HttpResponse::Ok()
+                    .content_type("text/csv; charset=utf-8")
+                    .insert_header((
+                        "Content-Disposition",
+                        "attachment; filename=\"products.csv\"",
+                    ))
+                    .body(body)
+            }
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn request(content_type: &str) -> HttpRequest {
+        TestRequest::default()
+            .insert_header((CONTENT_TYPE, content_type))
+            .to_http_request()
+    }
+
+    #[test]
+    fn format_parameter_wins_over_content_type() {
+        let req = request("application/json");
+        assert!(matches!(format_of(&req, Some("csv")), Ok(Format::Csv)));
+        assert!(format_of(&req, Some("xml")).is_err());
+    }
+
+    #[test]
+    fn format_falls_back_to_content_type() {
+        let csv = request("text/csv; charset=utf-8");
+        assert!(matches!(format_of(&csv, None), Ok(Format::Csv)));
+        let json = request("application/json");
+        assert!(matches!(format_of(&json, None), Ok(Format::Json)));
+        assert!(format_of(&request("text/plain"), None).is_err());
+    }
+
+    #[test]
+    fn parses_csv_rows_and_splits_categories() {
+        let body = "sku,name,description,price,stock,imageurl,categories\n\
+                    A-1, Mug ,,9.5,3,, Kitchen | Gifts |\n";
+        let rows = parse_rows(Format::Csv, body.as_bytes()).unwrap();
+        assert_eq!(rows.len(), 1);
+        let (number, row) = &rows[0];
+        let row = row.as_ref().unwrap();
+        assert_eq!(*number, 1);
+        assert_eq!(row.sku, "A-1");
+        assert_eq!(row.name, "Mug");
+        assert_eq!(row.imageurl, None);
+        assert_eq!(row.categories, vec!["Kitchen", "Gifts"]);
+    }
+
+    #[test]
+    fn a_bad_csv_row_only_fails_that_row() {
+        let body = "sku,name,price,stock\nA-1,Mug,cheap,3\nA-2,Cup,2.5,1\n";
+        let rows = parse_rows(Format::Csv, body.as_bytes()).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].1.is_err());
+        assert_eq!(rows[1].0, 2);
+        assert_eq!(rows[1].1.as_ref().unwrap().sku, "A-2");
+    }
+
+    #[test]
+    fn parses_json_rows_one_by_one() {
+        let body = r#"[
+            {"sku": "A-1", "name": "Mug", "price": 9.5, "stock": 3, "categories": ["Kitchen"]},
+            {"sku": "A-2", "name": "Cup"}
+        ]"#;
+        let rows = parse_rows(Format::Json, body.as_bytes()).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].1.as_ref().unwrap().categories, vec!["Kitchen"]);
+        assert!(rows[1].1.is_err());
+    }
+
+    #[test]
+    fn unreadable_json_fails_the_whole_file() {
+        assert!(parse_rows(Format::Json, b"{\"sku\": \"A-1\"}").is_err());
+        assert!(parse_rows(Format::Json, b"not json").is_err());
+    }
+}
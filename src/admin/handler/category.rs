@@ -1,8 +1,10 @@
 use crate::admin::model::*;
+use crate::audit::{self, AuditEvent};
 use crate::auth::model::Claims;
 use crate::prisma::PrismaClient; // Adjust based on your actual imports
 use crate::prisma::*;
 use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
 use serde_json::json;
 use std::sync::Arc;
 
@@ -21,6 +23,13 @@ pub async fn create_category(
 
             match new_category_result {
                 Ok(category) => {
+                    AuditEvent::new("category.created", "category")
+                        .by(&req)
+                        .entity(&category.id)
+                        .after(audit::category_snapshot(&category))
+                        .record_or_log(&prisma_client)
+                        .await;
+
                     let response = CategoryResponse {
                         id: category.id.clone(),
                         name: category.name.clone(),
@@ -47,6 +56,13 @@ pub async fn update_category(
     if let Some(claims) = req.extensions().get::<Claims>() {
         if claims.is_admin {
             let category_id = category_id.into_inner();
+            let before = prisma_client
+                .category()
+                .find_unique(category::id::equals(category_id.clone()))
+                .exec()
+                .await
+                .ok()
+                .flatten();
             let update_result = prisma_client
                 .category()
                 .update(
@@ -61,6 +77,15 @@ pub async fn update_category(
 
             match update_result {
                 Ok(category) => {
+                    let mut event = AuditEvent::new("category.updated", "category")
+                        .by(&req)
+                        .entity(&category.id)
+                        .after(audit::category_snapshot(&category));
+                    if let Some(before) = &before {
+                        event = event.before(audit::category_snapshot(before));
+                    }
+                    event.record_or_log(&prisma_client).await;
+
                     let response = CategoryResponse {
                         id: category.id.clone(),
                         name: category.name.clone(),
@@ -86,14 +111,46 @@ pub async fn delete_category(
     if let Some(claims) = req.extensions().get::<Claims>() {
         if claims.is_admin {
             let category_id = category_id.into_inner();
+            let before = match prisma_client
+                .category()
+                .find_first(vec![
+                    category::id::equals(category_id.clone()),
+                    category::deleted_at::equals(None),
+                ])
+                .exec()
+                .await
+            {
+                Ok(Some(before)) => before,
+                Ok(None) => {
+                    return HttpResponse::NotFound().json(json!({"error": "Category not found"}))
+                }
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error"}))
+                }
+            };
+
+            // Soft delete: the category disappears from public listings but keeps its product
+            // links, so restoring it brings everything back.
             let delete_result = prisma_client
                 .category()
-                .delete(category::id::equals(category_id.clone()))
+                .update(
+                    category::id::equals(category_id.clone()),
+                    vec![category::deleted_at::set(Some(Utc::now().fixed_offset()))],
+                )
                 .exec()
                 .await;
 
             match delete_result {
-                Ok(_) => {
+                Ok(category) => {
+                    AuditEvent::new("category.deleted", "category")
+                        .by(&req)
+                        .entity(&category.id)
+                        .before(audit::category_snapshot(&before))
+                        .after(audit::category_snapshot(&category))
+                        .record_or_log(&prisma_client)
+                        .await;
+
                     HttpResponse::Ok().json(json!({"message": "Category deleted successfully"}))
                 }
                 Err(_) => HttpResponse::NotFound().json(json!({"error": "Category not found"})),
@@ -105,3 +162,111 @@ pub async fn delete_category(
         HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
     }
 }
+
+pub async fn restore_category(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    category_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if claims.is_admin {
+            let category_id = category_id.into_inner();
+            match prisma_client
+                .category()
+                .update_many(
+                    vec![
+                        category::id::equals(category_id.clone()),
+                        category::deleted_at::not(None),
+                    ],
+                    vec![category::deleted_at::set(None)],
+                )
+                .exec()
+                .await
+            {
+                Ok(0) => {
+                    HttpResponse::NotFound().json(json!({"error": "Deleted category not found"}))
+                }
+                Ok(_) => {
+                    AuditEvent::new("category.restored", "category")
+                        .by(&req)
+                        .entity(&category_id)
+                        .record_or_log(&prisma_client)
+                        .await;
+
+                    HttpResponse::Ok().json(json!({"message": "Category restored successfully"}))
+                }
+                Err(_) => {
+                    HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+                }
+            }
+        } else {
+            HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Permanently removes a soft-deleted category and its product links. The products stay.
+pub async fn purge_category(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    category_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if claims.is_admin {
+            let category_id = category_id.into_inner();
+            let category_record = match prisma_client
+                .category()
+                .find_first(vec![
+                    category::id::equals(category_id.clone()),
+                    category::deleted_at::not(None),
+                ])
+                .exec()
+                .await
+            {
+                Ok(Some(category_record)) => category_record,
+                Ok(None) => {
+                    return HttpResponse::NotFound()
+                        .json(json!({"error": "Deleted category not found"}))
+                }
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error"}))
+                }
+            };
+
+            match prisma_client
+                ._batch((
+                    prisma_client
+                        .category_products()
+                        .delete_many(vec![category_products::category_id::equals(
+                            category_id.clone(),
+                        )]),
+                    prisma_client
+                        .category()
+                        .delete(category::id::equals(category_id.clone())),
+                ))
+                .await
+            {
+                Ok(_) => {
+                    AuditEvent::new("category.purged", "category")
+                        .by(&req)
+                        .entity(&category_id)
+                        .before(audit::category_snapshot(&category_record))
+                        .record_or_log(&prisma_client)
+                        .await;
+
+                    HttpResponse::Ok().json(json!({"message": "Category permanently deleted"}))
+                }
+                Err(_) => {
+                    HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+                }
+            }
+        } else {
+            HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
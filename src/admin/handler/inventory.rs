@@ -0,0 +1,233 @@
+use crate::admin::model::{StockMovementPayload, StockMovementQuery};
+use crate::audit::{self, AuditEvent};
+use crate::auth::model::Claims;
+use crate::inventory::{self, Movement};
+use crate::prisma::{inventory_movement, product, warehouse, MovementType, PrismaClient};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use prisma_client_rust::{Direction, QueryError};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+fn parse_kind(kind: &str) -> Option<MovementType> {
+    match kind {
+        "sale" => Some(MovementType::Sale),
+        "restock" => Some(MovementType::Restock),
+        "adjustment" => Some(MovementType::Adjustment),
+        "return" => Some(MovementType::Return),
+        "reservation" => Some(MovementType::Reservation),
+        _ => None,
+    }
+}
+
+fn movement_json(movement: &inventory_movement::Data) -> Value {
+    json!({
+        "id": movement.id,
+        "kind": movement.kind,
+        "quantity": movement.quantity,
+        "stockAfter": movement.stock_after,
+        "warehouseId": movement.warehouse_id,
+        "reference": movement.reference,
+        "actorId": movement.actor_id,
+        "note": movement.note,
+        "createdAt": movement.created_at,
+    })
+}
+
+/// Stock movements of one product, newest first.
+pub async fn get_stock_movements(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    product_id: web::Path<String>,
+    query: web::Query<StockMovementQuery>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let product_id = product_id.into_inner();
+        let page = query.page.unwrap_or(1).max(1);
+        let limit = query.limit.unwrap_or(10).clamp(1, 100);
+        let kind = match &query.kind {
+            Some(kind) => match parse_kind(kind) {
+                Some(kind) => Some(kind),
+                None => {
+                    return HttpResponse::BadRequest()
+                        .json(json!({"error": format!("Unknown kind {}", kind)}))
+                }
+            },
+            None => None,
+        };
+        let filters = || {
+            let mut filters = vec![inventory_movement::product_id::equals(product_id.clone())];
+            if let Some(kind) = kind {
+                filters.push(inventory_movement::kind::equals(kind));
+            }
+            filters
+        };
+
+        let (product_record, total_items, movements) = match prisma_client
+            ._batch((
+                prisma_client
+                    .product()
+                    .find_unique(product::id::equals(product_id.clone())),
+                prisma_client.inventory_movement().count(filters()),
+                prisma_client
+                    .inventory_movement()
+                    .find_many(filters())
+                    .order_by(inventory_movement::created_at::order(Direction::Desc))
+                    .skip((page - 1) * limit)
+                    .take(limit),
+            ))
+            .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+        let product_record = match product_record {
+            Some(product_record) => product_record,
+            None => return HttpResponse::NotFound().json(json!({"error": "Product not found"})),
+        };
+
+        HttpResponse::Ok().json(json!({
+            "productId": product_record.id,
+            "stock": product_record.stock,
+            "movements": movements.iter().map(movement_json).collect::<Vec<_>>(),
+            "pagination": {
+                "currentPage": page,
+                "totalPages": (total_items as f64 / limit as f64).ceil() as i64,
+                "totalItems": total_items,
+                "limit": limit,
+            }
+        }))
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Records a restock, customer return or manual correction in one warehouse, the default one
+/// unless the payload names another. Its stock can't go below zero.
+pub async fn create_stock_movement(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    product_id: web::Path<String>,
+    payload: web::Json<StockMovementPayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let kind = match payload.kind.as_str() {
+            "restock" => MovementType::Restock,
+            "return" => MovementType::Return,
+            "adjustment" => MovementType::Adjustment,
+            _ => {
+                return HttpResponse::BadRequest().json(json!({
+                    "error": "Kind must be restock, return or adjustment."
+                }))
+            }
+        };
+        if payload.quantity == 0 || (kind != MovementType::Adjustment && payload.quantity < 0) {
+            return HttpResponse::BadRequest().json(json!({
+                "error": "Quantity must be positive, or non-zero for adjustments."
+            }));
+        }
+
+        let product_id = product_id.into_inner();
+        let id = product_id.clone();
+        let quantity = payload.quantity;
+        let mut movement = Movement::new(kind).actor(audit::actor_of(&req));
+        if let Some(reference) = &payload.reference {
+            movement = movement.reference(reference);
+        }
+        if let Some(note) = &payload.note {
+            movement = movement.note(note);
+        }
+        let warehouse_record = match &payload.warehouse_id {
+            Some(warehouse_id) => prisma_client
+                .warehouse()
+                .find_unique(warehouse::id::equals(warehouse_id.clone()))
+                .exec()
+                .await
+                .map(|found| found.ok_or("Warehouse not found")),
+            None => inventory::default_warehouse(&prisma_client)
+                .await
+                .map(|found| found.ok_or("No default warehouse is set")),
+        };
+        let warehouse_id = match warehouse_record {
+            Ok(Ok(warehouse_record)) => warehouse_record.id,
+            Ok(Err(err)) => return HttpResponse::NotFound().json(json!({"error": err})),
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+        movement = movement.warehouse(&warehouse_id);
+
+        // The inner error is the warehouse's stock when the movement would take it below zero,
+        // or `None` when the product doesn't exist. Nothing was written in either case.
+        let result: Result<Result<inventory_movement::Data, Option<i32>>, QueryError> =
+            prisma_client
+                ._transaction()
+                .run(|client| {
+                    Box::pin(async move {
+                        if inventory::lock_stock(&client, &id).await?.is_none() {
+                            return Ok(Err(None));
+                        }
+                        let stock = inventory::level(&client, &id, &warehouse_id).await?;
+                        if stock + quantity < 0 {
+                            return Ok(Err(Some(stock)));
+                        }
+                        inventory::apply(&client, &id, quantity, movement)
+                            .await
+                            .map(Ok)
+                    })
+                })
+                .await;
+
+        match result {
+            Ok(Ok(movement)) => {
+                AuditEvent::new("inventory.adjusted", "product")
+                    .by(&req)
+                    .entity(&product_id)
+                    .after(movement_json(&movement))
+                    .record_or_log(&prisma_client)
+                    .await;
+
+                HttpResponse::Created().json(movement_json(&movement))
+            }
+            Ok(Err(None)) => HttpResponse::NotFound().json(json!({"error": "Product not found"})),
+            Ok(Err(Some(stock))) => HttpResponse::Conflict().json(json!({
+                "error": "Stock can't go below zero.",
+                "stock": stock,
+            })),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Lists every product whose stock doesn't match the sum of its ledger or warehouse levels.
+pub async fn reconcile_inventory(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        match inventory::reconcile(&prisma_client).await {
+            Ok(discrepancies) => HttpResponse::Ok().json(json!({
+                "consistent": discrepancies.is_empty(),
+                "discrepancies": discrepancies,
+            })),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
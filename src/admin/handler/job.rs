@@ -0,0 +1,251 @@
+use crate::admin::model::JobQuery;
+use crate::audit::AuditEvent;
+use crate::auth::model::Claims;
+use crate::prisma::{job, JobStatus, PrismaClient};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+fn parse_status(status: &str) -> Option<JobStatus> {
+    match status {
+        "queued" => Some(JobStatus::Queued),
+        "running" => Some(JobStatus::Running),
+        "succeeded" => Some(JobStatus::Succeeded),
+        "dead" => Some(JobStatus::Dead),
+        "cancelled" => Some(JobStatus::Cancelled),
+        _ => None,
+    }
+}
+
+fn filters(query: &JobQuery) -> Result<Vec<job::WhereParam>, String> {
+    let mut filters = vec![];
+    if let Some(status) = &query.status {
+        let status = parse_status(status).ok_or_else(|| format!("Unknown status {}", status))?;
+        filters.push(job::status::equals(status));
+    }
+    if let Some(kind) = &query.kind {
+        filters.push(job::kind::equals(kind.clone()));
+    }
+    Ok(filters)
+}
+
+/// The payload is left out of listings since it can hold a whole import file.
+fn job_json(job_record: &job::Data) -> Value {
+    json!({
+        "id": job_record.id,
+        "kind": job_record.kind,
+        "status": job_record.status,
+        "attempts": job_record.attempts,
+        "maxAttempts": job_record.max_attempts,
+        "lastError": job_record.last_error,
+        "result": job_record.result,
+        "runAt": job_record.run_at,
+        "lockedAt": job_record.locked_at,
+        "lockedBy": job_record.locked_by,
+        "finishedAt": job_record.finished_at,
+        "createdAt": job_record.created_at,
+        "updatedAt": job_record.updated_at,
+    })
+}
+
+pub async fn get_jobs(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    query: web::Query<JobQuery>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let page = query.page.unwrap_or(1).max(1);
+        let limit = query.limit.unwrap_or(10).clamp(1, 100);
+        let (where_params, count_params) = match (filters(&query), filters(&query)) {
+            (Ok(where_params), Ok(count_params)) => (where_params, count_params),
+            (Err(err), _) | (_, Err(err)) => {
+                return HttpResponse::BadRequest().json(json!({"error": err}))
+            }
+        };
+
+        let (total_items, jobs) = match prisma_client
+            ._batch((
+                prisma_client.job().count(count_params),
+                prisma_client
+                    .job()
+                    .find_many(where_params)
+                    .order_by(job::created_at::order(prisma_client_rust::Direction::Desc))
+                    .skip((page - 1) * limit)
+                    .take(limit),
+            ))
+            .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+
+        HttpResponse::Ok().json(json!({
+            "jobs": jobs.iter().map(job_json).collect::<Vec<_>>(),
+            "pagination": {
+                "currentPage": page,
+                "totalPages": (total_items as f64 / limit as f64).ceil() as i64,
+                "totalItems": total_items,
+                "limit": limit,
+            }
+        }))
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+pub async fn get_job(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    job_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        match prisma_client
+            .job()
+            .find_unique(job::id::equals(job_id.into_inner()))
+            .exec()
+            .await
+        {
+            Ok(Some(job_record)) => {
+                let mut response = job_json(&job_record);
+                response["payload"] = job_record.payload.clone();
+                HttpResponse::Ok().json(response)
+            }
+            Ok(None) => HttpResponse::NotFound().json(json!({"error": "Job not found"})),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Moves a job from `from` to the state set by `update`. 404 if the job doesn't exist, 409 if
+/// it isn't in one of the `from` states.
+async fn transition(
+    req: &HttpRequest,
+    prisma_client: &PrismaClient,
+    job_id: String,
+    from: Vec<JobStatus>,
+    update: Vec<job::SetParam>,
+    action: &str,
+) -> HttpResponse {
+    let before = match prisma_client
+        .job()
+        .find_unique(job::id::equals(job_id.clone()))
+        .exec()
+        .await
+    {
+        Ok(Some(before)) => before,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Job not found"})),
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+        }
+    };
+
+    match prisma_client
+        .job()
+        .update_many(
+            vec![job::id::equals(job_id.clone()), job::status::in_vec(from)],
+            update,
+        )
+        .exec()
+        .await
+    {
+        Ok(0) => HttpResponse::Conflict().json(json!({
+            "error": format!("Job can't be changed while {}", before.status.to_string())
+        })),
+        Ok(_) => {
+            let after = prisma_client
+                .job()
+                .find_unique(job::id::equals(job_id.clone()))
+                .exec()
+                .await
+                .ok()
+                .flatten();
+            AuditEvent::new(action, "job")
+                .by(req)
+                .entity(&job_id)
+                .before(json!({"status": before.status, "attempts": before.attempts}))
+                .after(json!({
+                    "status": after.as_ref().map(|a| a.status),
+                    "attempts": after.as_ref().map(|a| a.attempts),
+                }))
+                .record_or_log(prisma_client)
+                .await;
+
+            match after {
+                Some(after) => HttpResponse::Ok().json(job_json(&after)),
+                None => HttpResponse::NotFound().json(json!({"error": "Job not found"})),
+            }
+        }
+        Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+    }
+}
+
+/// Requeues a dead or cancelled job with a fresh set of attempts.
+pub async fn retry_job(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    job_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        transition(
+            &req,
+            &prisma_client,
+            job_id.into_inner(),
+            vec![JobStatus::Dead, JobStatus::Cancelled],
+            vec![
+                job::status::set(JobStatus::Queued),
+                job::attempts::set(0),
+                job::run_at::set(Utc::now().fixed_offset()),
+                job::finished_at::set(None),
+            ],
+            "job.retried",
+        )
+        .await
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Cancels a job that hasn't started. Running jobs can't be interrupted.
+pub async fn cancel_job(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    job_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        transition(
+            &req,
+            &prisma_client,
+            job_id.into_inner(),
+            vec![JobStatus::Queued],
+            vec![
+                job::status::set(JobStatus::Cancelled),
+                job::finished_at::set(Some(Utc::now().fixed_offset())),
+            ],
+            "job.cancelled",
+        )
+        .await
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
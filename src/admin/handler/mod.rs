@@ -1,5 +1,13 @@
+pub mod api_key;
+pub mod audit;
+pub mod catalog;
 pub mod category;
+pub mod inventory;
+pub mod job;
 pub mod order;
 pub mod product;
+pub mod purchase_order;
 pub mod sales;
+pub mod supplier;
 pub mod user;
+pub mod warehouse;
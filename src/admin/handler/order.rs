@@ -1,10 +1,39 @@
+use crate::audit::{self, AuditEvent};
 use crate::auth::model::Claims;
+use crate::inventory::{self, Movement};
+use crate::notification::{outbox, templates::Template};
 use crate::prisma::PrismaClient; // Adjust based on your actual imports
 use crate::prisma::*;
 use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use prisma_client_rust::{raw, PrismaValue, QueryError};
+use serde::Deserialize;
 use serde_json::json;
 use std::sync::Arc;
 
+#[derive(Deserialize)]
+struct StatusRow {
+    status: String,
+}
+
+/// Status of an order, locking it until the transaction ends.
+async fn lock(client: &PrismaClient, order_id: &str) -> Result<Option<String>, QueryError> {
+    let rows: Vec<StatusRow> = client
+        ._query_raw(raw!(
+            r#"SELECT "status" FROM "Order" WHERE "id" = {} FOR UPDATE"#,
+            PrismaValue::String(order_id.to_string())
+        ))
+        .exec()
+        .await?;
+    Ok(rows.into_iter().next().map(|row| row.status))
+}
+
+/// How an approval attempt ended.
+enum Approval {
+    Approved(warehouse::Data),
+    NotPending,
+    NoWarehouse,
+}
+
 pub async fn approve_order(
     req: HttpRequest,
     prisma_client: web::Data<Arc<PrismaClient>>,
@@ -23,48 +52,124 @@ pub async fn approve_order(
             .order()
             .find_unique(order::id::equals(order_id.clone()))
             .with(order::items::fetch(vec![]).with(order_item::product::fetch()))
+            .with(order::user::fetch())
             .exec()
             .await
         {
             Ok(Some(order)) => {
                 // Check if the order status is "pending"
                 if order.status == "pending" {
-                    // Start a transaction to update order status and reduce product stocks
-                    let transaction_result: Result<(), prisma_client_rust::QueryError> = prisma_client._transaction().run(|client| {
+                    let customer = order.user.clone();
+                    let approved_order_id = order_id.clone();
+                    let before = audit::order_snapshot(&order);
+                    let actor_id = audit::actor_of(&req);
+                    // Start a transaction to pick a warehouse, update order status and turn the reserved stock into a sale
+                    let transaction_result: Result<Approval, QueryError> = prisma_client._transaction().run(|client| {
                         Box::pin(async move {
+                            // Locked so the expiry task can't release the stock at the same time
+                            match lock(&client, &order_id).await? {
+                                Some(status) if status == "pending" => {}
+                                _ => return Ok(Approval::NotPending),
+                            }
+                            let items = order.items.unwrap();
+                            let lines = items
+                                .iter()
+                                .map(|item| (item.product_id.clone(), item.quantity))
+                                .collect::<Vec<_>>();
+                            let reference = format!("order:{}", order_id);
+
+                            // The whole order ships from one warehouse: the one holding its stock,
+                            // unless that one stopped fulfilling orders or the order predates reservations
+                            let reserved_at = inventory::reserved(&client, &reference)
+                                .await?
+                                .into_keys()
+                                .find_map(|(_, warehouse_id)| warehouse_id);
+                            let holding = match reserved_at {
+                                Some(warehouse_id) => client
+                                    .warehouse()
+                                    .find_first(vec![
+                                        warehouse::id::equals(warehouse_id),
+                                        warehouse::active::equals(true),
+                                    ])
+                                    .exec()
+                                    .await?,
+                                None => None,
+                            };
+                            let fulfilling = match holding {
+                                Some(holding) => holding,
+                                None => match inventory::pick_warehouse(&client, &lines).await? {
+                                    Some(fulfilling) => fulfilling,
+                                    None => return Ok(Approval::NoWarehouse),
+                                },
+                            };
+                            inventory::release(&client, &reference, actor_id.clone()).await?;
+
                             // Update order status to "approved"
                             client.order()
                                 .update(
                                     order::id::equals(order_id.clone()),
-                                    vec![order::status::set("approved".to_string())]
+                                    vec![
+                                        order::status::set("approved".to_string()),
+                                        order::warehouse::connect(warehouse::id::equals(fulfilling.id.clone())),
+                                    ]
                                 )
                                 .exec()
                                 .await
                                 .map_err(|e| e)?;
 
                             // Reduce product stocks based on order items
-                            for item in order.items.unwrap() {
-                                let product_id = item.product_id.clone();
-                                let new_stock = item.product.unwrap().stock - item.quantity;
-
-                                client.product()
-                                    .update(
-                                        product::id::equals(product_id),
-                                        vec![product::stock::set(new_stock)]
-                                    )
-                                    .exec()
-                                    .await
-                                    .map_err(|e| e)?;
+                            for item in items {
+                                inventory::apply(
+                                    &client,
+                                    &item.product_id,
+                                    -item.quantity,
+                                    Movement::new(MovementType::Sale)
+                                        .warehouse(&fulfilling.id)
+                                        .reference(&reference)
+                                        .actor(actor_id.clone()),
+                                )
+                                .await?;
                             }
 
-                            Ok(())
+                            Ok(Approval::Approved(fulfilling))
                         })
                     }).await;
 
                     match transaction_result {
-                        Ok(_) => HttpResponse::Ok().json(json!({
-                            "message": "Order approved successfully",
+                        Ok(Approval::NotPending) => HttpResponse::BadRequest().json(json!({"error": "Order is not in pending status"})),
+                        Ok(Approval::NoWarehouse) => HttpResponse::Conflict().json(json!({
+                            "error": "No warehouse has enough stock to fulfill every item of this order"
                         })),
+                        Ok(Approval::Approved(fulfilling)) => {
+                            let mut after = before.clone();
+                            after["status"] = json!("approved");
+                            after["warehouseId"] = json!(fulfilling.id);
+                            AuditEvent::new("order.approved", "order")
+                                .by(&req)
+                                .entity(&approved_order_id)
+                                .before(before)
+                                .after(after)
+                                .record_or_log(&prisma_client)
+                                .await;
+
+                            if let Some(customer) = customer {
+                                let template = Template::OrderApproved {
+                                    name: customer.first_name.clone(),
+                                    order_id: approved_order_id,
+                                };
+                                outbox::enqueue(&prisma_client, &customer.email, template)
+                                    .await
+                                    .ok();
+                            }
+                            HttpResponse::Ok().json(json!({
+                                "message": "Order approved successfully",
+                                "warehouse": {
+                                    "id": fulfilling.id,
+                                    "code": fulfilling.code,
+                                    "name": fulfilling.name,
+                                },
+                            }))
+                        }
                         Err(err) => HttpResponse::InternalServerError().json(json!({
                             "error": format!("Failed to approve order and reduce product stocks: {:?}", err)
                         })),
@@ -1,11 +1,166 @@
 use crate::admin::model::*;
+use crate::audit::{self, AuditEvent};
 use crate::auth::model::Claims;
+use crate::inventory::{self, Movement};
 use crate::prisma::PrismaClient; // Adjust based on your actual imports
 use crate::prisma::*;
 use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use dotenv::dotenv;
+use prisma_client_rust::QueryError;
 use serde_json::json;
-use std::sync::Arc;
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
 
+/// Most product ids a bulk delete accepts at once.
+const BULK_DELETE_LIMIT: usize = 100;
+
+enum Removal {
+    /// Referenced by orders, so only hidden from the catalogue.
+    Archived {
+        before: product::Data,
+        after: product::Data,
+    },
+    Deleted(product::Data),
+}
+
+/// Deletes the product with its reviews and category links. Fails on the foreign key if an
+/// order item still references it.
+async fn hard_delete(prisma_client: &PrismaClient, product_id: &str) -> Result<(), QueryError> {
+    prisma_client
+        ._batch((
+            prisma_client
+                .review()
+                .delete_many(vec![review::product_id::equals(product_id.to_string())]),
+            prisma_client.category_products().delete_many(vec![
+                category_products::product_id::equals(product_id.to_string()),
+            ]),
+            prisma_client
+                .product()
+                .delete(product::id::equals(product_id.to_string())),
+        ))
+        .await
+        .map(|_| ())
+}
+
+/// Where uploaded product images live, read once from `PRODUCT_IMAGE_DIR` and
+/// `PRODUCT_IMAGE_URL_PREFIX`. `None` unless both are set.
+struct ImageStore {
+    dir: PathBuf,
+    prefix: String,
+}
+
+fn image_store() -> Option<&'static ImageStore> {
+    static STORE: OnceLock<Option<ImageStore>> = OnceLock::new();
+    STORE
+        .get_or_init(|| {
+            dotenv().ok();
+            match (
+                env::var("PRODUCT_IMAGE_DIR"),
+                env::var("PRODUCT_IMAGE_URL_PREFIX"),
+            ) {
+                (Ok(dir), Ok(prefix)) if !prefix.is_empty() => Some(ImageStore {
+                    dir: PathBuf::from(dir),
+                    prefix,
+                }),
+                _ => None,
+            }
+        })
+        .as_ref()
+}
+
+/// Removes an uploaded image once no product row uses it any more. Only URLs under the image
+/// store's prefix are ours, stored as files in its directory; anything else is hosted elsewhere
+/// and left alone. The file is removed on the blocking thread pool.
+async fn remove_image(prisma_client: &PrismaClient, image_url: &str) {
+    let store = match image_store() {
+        Some(store) => store,
+        None => return,
+    };
+    let file_name = match image_url.strip_prefix(store.prefix.as_str()) {
+        Some(name)
+            if !name.is_empty() && name != ".." && !name.contains('/') && !name.contains('\\') =>
+        {
+            name.to_string()
+        }
+        _ => return,
+    };
+
+    let in_use = prisma_client
+        .product()
+        .count(vec![product::image_url::equals(image_url.to_string())])
+        .exec()
+        .await
+        .map_or(true, |count| count > 0);
+    if in_use {
+        return;
+    }
+    let path = store.dir.join(&file_name);
+    match web::block(move || std::fs::remove_file(path)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => println!("failed to remove product image {}: {}", file_name, err),
+        Err(err) => println!("failed to remove product image {}: {}", file_name, err),
+    }
+}
+
+/// Hard-deletes a product nothing has ordered and archives one that order history still
+/// points at. `None` when there's no live product with that id.
+async fn remove_product(
+    prisma_client: &PrismaClient,
+    product_id: &str,
+) -> Result<Option<Removal>, QueryError> {
+    let before = match prisma_client
+        .product()
+        .find_first(vec![
+            product::id::equals(product_id.to_string()),
+            product::deleted_at::equals(None),
+        ])
+        .with(product::categories::fetch(vec![]))
+        .exec()
+        .await?
+    {
+        Some(before) => before,
+        None => return Ok(None),
+    };
+
+    let ordered = prisma_client
+        .order_item()
+        .count(vec![order_item::product_id::equals(product_id.to_string())])
+        .exec()
+        .await?
+        > 0;
+    // An order placed after the count, or a purchase order line, makes the foreign key reject
+    // the delete, in which case the product is archived like any other ordered one.
+    if !ordered && hard_delete(prisma_client, product_id).await.is_ok() {
+        remove_image(prisma_client, &before.image_url).await;
+        return Ok(Some(Removal::Deleted(before)));
+    }
+
+    let after = prisma_client
+        .product()
+        .update(
+            product::id::equals(product_id.to_string()),
+            vec![product::deleted_at::set(Some(Utc::now().fixed_offset()))],
+        )
+        .with(product::categories::fetch(vec![]))
+        .exec()
+        .await?;
+    Ok(Some(Removal::Archived { before, after }))
+}
+
+async fn audit_removal(req: &HttpRequest, prisma_client: &PrismaClient, removal: &Removal) {
+    let event = match removal {
+        Removal::Archived { before, after } => AuditEvent::new("product.archived", "product")
+            .entity(&after.id)
+            .before(audit::product_snapshot(before))
+            .after(audit::product_snapshot(after)),
+        Removal::Deleted(before) => AuditEvent::new("product.deleted", "product")
+            .entity(&before.id)
+            .before(audit::product_snapshot(before)),
+    };
+    event.by(req).record_or_log(prisma_client).await;
+}
 
 pub async fn create_product(
     req: HttpRequest,
@@ -14,23 +169,81 @@ pub async fn create_product(
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
         if claims.is_admin {
+            let mut errors = validate_fields(
+                Some(&payload.name),
+                Some(payload.price),
+                Some(payload.stock),
+                Some(&payload.imageurl),
+            );
+            check_threshold(payload.low_stock_threshold, &mut errors);
+            let checked = match &payload.sku {
+                Some(sku) => check_sku(&prisma_client, sku, None, &mut errors).await,
+                None => Ok(()),
+            };
+            if checked.is_err()
+                || check_categories(&prisma_client, "category", &payload.category, &mut errors)
+                    .await
+                    .is_err()
+            {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Database error."}));
+            }
+            if !errors.is_empty() {
+                return invalid_fields(errors);
+            }
+
             let category_ids = payload
                 .category
                 .iter()
                 .map(|cat_id| category::id::equals(cat_id.clone()))
                 .collect::<Vec<_>>();
+            let mut params = vec![
+                product::sku::set(payload.sku.clone()),
+                product::categories::connect(category_ids.clone()),
+            ];
+            if let Some(threshold) = payload.low_stock_threshold {
+                params.push(product::low_stock_threshold::set(threshold));
+            }
 
-            let new_product_result = prisma_client
-                .product()
-                .create(
-                    payload.name.clone(),
-                    payload.description.clone(),
-                    payload.price,
-                    payload.stock,
-                    payload.imageurl.clone(),
-                    vec![product::categories::connect(category_ids.clone())],
-                )
-                .exec()
+            let (name, description, price, stock, imageurl) = (
+                payload.name.clone(),
+                payload.description.clone(),
+                payload.price,
+                payload.stock,
+                payload.imageurl.clone(),
+            );
+            let actor_id = audit::actor_of(&req);
+            let new_product_result: Result<product::Data, QueryError> = prisma_client
+                ._transaction()
+                .run(|client| {
+                    Box::pin(async move {
+                        let mut product = client
+                            .product()
+                            .create(name, description, price, stock, imageurl, params)
+                            .exec()
+                            .await?;
+                        if product.sku.is_none() {
+                            product = client
+                                .product()
+                                .update(
+                                    product::id::equals(product.id.clone()),
+                                    vec![product::sku::set(Some(default_sku(&product.id)))],
+                                )
+                                .exec()
+                                .await?;
+                        }
+                        inventory::open(
+                            &client,
+                            &product.id,
+                            product.stock,
+                            Movement::new(MovementType::Restock)
+                                .actor(actor_id)
+                                .note("Initial stock"),
+                        )
+                        .await?;
+                        Ok(product)
+                    })
+                })
                 .await;
 
             match new_product_result {
@@ -44,8 +257,16 @@ pub async fn create_product(
 
                     match created_product {
                         Ok(Some(created_product)) => {
+                            AuditEvent::new("product.created", "product")
+                                .by(&req)
+                                .entity(&created_product.id)
+                                .after(audit::product_snapshot(&created_product))
+                                .record_or_log(&prisma_client)
+                                .await;
+
                             let response = ProductResponse {
                                 id: product.id.clone(),
+                                sku: product.sku.clone(),
                                 name: product.name.clone(),
                                 description: product.description.clone(),
                                 price: product.price,
@@ -76,6 +297,242 @@ pub async fn create_product(
     }
 }
 
+/// Checks the product fields that are present. `None` means the field isn't being changed.
+pub fn validate_fields(
+    name: Option<&str>,
+    price: Option<f64>,
+    stock: Option<i32>,
+    imageurl: Option<&str>,
+) -> Vec<FieldError> {
+    let mut errors = vec![];
+    if name.map_or(false, |name| name.trim().is_empty()) {
+        errors.push(FieldError::new("name", "Name must not be empty."));
+    }
+    if price.map_or(false, |price| !price.is_finite() || price < 0.0) {
+        errors.push(FieldError::new(
+            "price",
+            "Price must be a non-negative number.",
+        ));
+    }
+    if stock.map_or(false, |stock| stock < 0) {
+        errors.push(FieldError::new("stock", "Stock must not be negative."));
+    }
+    if imageurl.map_or(false, |imageurl| imageurl.trim().is_empty()) {
+        errors.push(FieldError::new("imageurl", "Image URL must not be empty."));
+    }
+    errors
+}
+
+fn check_threshold(low_stock_threshold: Option<i32>, errors: &mut Vec<FieldError>) {
+    if low_stock_threshold.map_or(false, |threshold| threshold < 0) {
+        errors.push(FieldError::new(
+            "low_stock_threshold",
+            "Low-stock threshold must not be negative.",
+        ));
+    }
+}
+
+/// SKU given to products created without one, so every product can be exported and imported
+/// again. Matches the backfill of the `backfill_product_skus` migration.
+fn default_sku(product_id: &str) -> String {
+    format!("P-{}", product_id)
+}
+
+/// Adds an error unless `sku` is non-empty and free. `product_id` is the product being
+/// updated, which may keep its own SKU.
+async fn check_sku(
+    prisma_client: &PrismaClient,
+    sku: &str,
+    product_id: Option<&str>,
+    errors: &mut Vec<FieldError>,
+) -> Result<(), QueryError> {
+    if sku.trim().is_empty() {
+        errors.push(FieldError::new("sku", "SKU must not be empty."));
+        return Ok(());
+    }
+    let mut filters = vec![product::sku::equals(Some(sku.to_string()))];
+    if let Some(product_id) = product_id {
+        filters.push(product::id::not(product_id.to_string()));
+    }
+    if prisma_client.product().count(filters).exec().await? > 0 {
+        errors.push(FieldError::new(
+            "sku",
+            "SKU is already used by another product.",
+        ));
+    }
+    Ok(())
+}
+
+/// Adds an error for `field` listing any ids that aren't live categories.
+async fn check_categories(
+    prisma_client: &PrismaClient,
+    field: &'static str,
+    ids: &[String],
+    errors: &mut Vec<FieldError>,
+) -> Result<(), QueryError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let found = prisma_client
+        .category()
+        .find_many(vec![
+            category::id::in_vec(ids.to_vec()),
+            category::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?;
+    let unknown = ids
+        .iter()
+        .filter(|id| !found.iter().any(|c| &c.id == *id))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        errors.push(FieldError::new(
+            field,
+            &format!("Unknown categories: {}.", unknown.join(", ")),
+        ));
+    }
+    Ok(())
+}
+
+fn invalid_fields(errors: Vec<FieldError>) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "error": "Invalid input data.",
+        "fields": errors,
+    }))
+}
+
+fn category_params(ids: &[String]) -> Vec<category::UniqueWhereParam> {
+    ids.iter()
+        .map(|cat_id| category::id::equals(cat_id.clone()))
+        .collect()
+}
+
+/// `ids` plus the soft-deleted categories the product is already linked to. Deleted categories
+/// can't be chosen, but replacing a product's categories mustn't drop those links either, or
+/// restoring the category would bring it back without its products.
+pub async fn keep_deleted_links(
+    prisma_client: &PrismaClient,
+    product_id: &str,
+    ids: &[String],
+) -> Result<Vec<String>, QueryError> {
+    let deleted = prisma_client
+        .category()
+        .find_many(vec![
+            category::deleted_at::not(None),
+            category::products::some(vec![product::id::equals(product_id.to_string())]),
+        ])
+        .exec()
+        .await?;
+    let mut kept = ids.to_vec();
+    kept.extend(
+        deleted
+            .into_iter()
+            .map(|deleted_category| deleted_category.id)
+            .filter(|id| !ids.contains(id)),
+    );
+    Ok(kept)
+}
+
+/// Applies `update_operations` to a live product, audits the change and returns the updated
+/// product with its categories. A new `stock` is recorded in the inventory ledger as an
+/// adjustment in the same transaction.
+async fn save_product(
+    req: &HttpRequest,
+    prisma_client: &PrismaClient,
+    product_id: &str,
+    stock: Option<i32>,
+    update_operations: Vec<product::SetParam>,
+) -> HttpResponse {
+    let before = match prisma_client
+        .product()
+        .find_first(vec![
+            product::id::equals(product_id.to_string()),
+            product::deleted_at::equals(None),
+        ])
+        .with(product::categories::fetch(vec![]))
+        .exec()
+        .await
+    {
+        Ok(Some(before)) => before,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Product not found."})),
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({"error": "Database error."}))
+        }
+    };
+
+    let id = product_id.to_string();
+    let actor_id = audit::actor_of(req);
+    // The inner error is the default warehouse's stock when the edit would take more out of it
+    // than it holds.
+    let update_product_result: Result<Result<product::Data, i32>, QueryError> = prisma_client
+        ._transaction()
+        .run(|client| {
+            Box::pin(async move {
+                if let Some(stock) = stock {
+                    let adjusted = inventory::adjust_to(
+                        &client,
+                        &id,
+                        stock,
+                        Movement::new(MovementType::Adjustment)
+                            .actor(actor_id)
+                            .note("Stock edited on the product"),
+                    )
+                    .await?;
+                    if let Err(held) = adjusted {
+                        return Ok(Err(held));
+                    }
+                }
+                client
+                    .product()
+                    .update(product::id::equals(id), update_operations)
+                    .with(product::categories::fetch(vec![]))
+                    .exec()
+                    .await
+                    .map(Ok)
+            })
+        })
+        .await;
+
+    match update_product_result {
+        Ok(Ok(updated_product)) => {
+            AuditEvent::new("product.updated", "product")
+                .by(req)
+                .entity(&updated_product.id)
+                .before(audit::product_snapshot(&before))
+                .after(audit::product_snapshot(&updated_product))
+                .record_or_log(prisma_client)
+                .await;
+
+            let response = ProductResponse {
+                id: updated_product.id.clone(),
+                sku: updated_product.sku.clone(),
+                name: updated_product.name.clone(),
+                description: updated_product.description.clone(),
+                price: updated_product.price,
+                stock: updated_product.stock,
+                category: updated_product
+                    .categories
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|cat| cat.name)
+                    .collect::<Vec<String>>(),
+                imageurl: updated_product.image_url.clone(),
+            };
+            HttpResponse::Ok().json(response)
+        }
+        Ok(Err(held)) => HttpResponse::Conflict().json(json!({
+            "error": "The default warehouse doesn't hold enough stock for that reduction.",
+            "defaultWarehouseStock": held,
+        })),
+        Err(_) => {
+            HttpResponse::InternalServerError().json(json!({"error": "Could not update product."}))
+        }
+    }
+}
+
+/// Replaces the whole product, including its categories. Links to soft-deleted categories are
+/// kept.
 pub async fn update_product(
     req: HttpRequest,
     prisma_client: web::Data<Arc<PrismaClient>>,
@@ -84,63 +541,390 @@ pub async fn update_product(
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
         if claims.is_admin {
-            let category_ids = payload
-                .category
-                .iter()
-                .map(|cat_id| category::id::equals(cat_id.clone()))
-                .collect::<Vec<_>>();
+            let mut errors = validate_fields(
+                Some(&payload.name),
+                Some(payload.price),
+                Some(payload.stock),
+                Some(&payload.imageurl),
+            );
+            check_threshold(payload.low_stock_threshold, &mut errors);
+            let checked = match &payload.sku {
+                Some(sku) => check_sku(&prisma_client, sku, Some(&product_id), &mut errors).await,
+                None => Ok(()),
+            };
+            if checked.is_err()
+                || check_categories(&prisma_client, "category", &payload.category, &mut errors)
+                    .await
+                    .is_err()
+            {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Database error."}));
+            }
+            if !errors.is_empty() {
+                return invalid_fields(errors);
+            }
+            let categories =
+                match keep_deleted_links(&prisma_client, &product_id, &payload.category).await {
+                    Ok(categories) => categories,
+                    Err(_) => {
+                        return HttpResponse::InternalServerError()
+                            .json(json!({"error": "Database error."}))
+                    }
+                };
 
-            let update_operations = vec![
+            // Clients that predate SKUs don't send one, so a missing SKU keeps the current one.
+            let mut update_operations = vec![
                 product::name::set(payload.name.clone()),
                 product::description::set(payload.description.clone()),
                 product::price::set(payload.price),
-                product::stock::set(payload.stock),
                 product::image_url::set(payload.imageurl.clone()),
-                product::categories::connect(category_ids.clone()),
+                product::categories::set(category_params(&categories)),
             ];
+            if let Some(sku) = &payload.sku {
+                update_operations.push(product::sku::set(Some(sku.clone())));
+            }
+            if let Some(threshold) = payload.low_stock_threshold {
+                update_operations.push(product::low_stock_threshold::set(threshold));
+            }
+            save_product(
+                &req,
+                &prisma_client,
+                &product_id,
+                Some(payload.stock),
+                update_operations,
+            )
+            .await
+        } else {
+            HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+    }
+}
 
-            let update_product_result = prisma_client
+/// Changes only the fields present in the payload. Categories are either replaced with
+/// `category` or adjusted with `add_categories` / `remove_categories`.
+pub async fn patch_product(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    product_id: web::Path<String>,
+    payload: web::Json<ProductPatchPayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if claims.is_admin {
+            let mut errors = validate_fields(
+                payload.name.as_deref(),
+                payload.price,
+                payload.stock,
+                payload.imageurl.as_deref(),
+            );
+            check_threshold(payload.low_stock_threshold, &mut errors);
+            if payload.category.is_some()
+                && !(payload.add_categories.is_empty() && payload.remove_categories.is_empty())
+            {
+                errors.push(FieldError::new(
+                    "category",
+                    "Use either category or add_categories/remove_categories, not both.",
+                ));
+            }
+            if payload
+                .add_categories
+                .iter()
+                .any(|id| payload.remove_categories.contains(id))
+            {
+                errors.push(FieldError::new(
+                    "remove_categories",
+                    "A category can't be both added and removed.",
+                ));
+            }
+            let checked = match &payload.category {
+                Some(category) => {
+                    check_categories(&prisma_client, "category", category, &mut errors).await
+                }
+                None => {
+                    check_categories(
+                        &prisma_client,
+                        "add_categories",
+                        &payload.add_categories,
+                        &mut errors,
+                    )
+                    .await
+                }
+            };
+            let sku_checked = match &payload.sku {
+                Some(sku) => check_sku(&prisma_client, sku, Some(&product_id), &mut errors).await,
+                None => Ok(()),
+            };
+            if checked.is_err() || sku_checked.is_err() {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Database error."}));
+            }
+            if !errors.is_empty() {
+                return invalid_fields(errors);
+            }
+
+            let mut update_operations = vec![];
+            if let Some(sku) = &payload.sku {
+                update_operations.push(product::sku::set(Some(sku.clone())));
+            }
+            if let Some(name) = &payload.name {
+                update_operations.push(product::name::set(name.clone()));
+            }
+            if let Some(description) = &payload.description {
+                update_operations.push(product::description::set(description.clone()));
+            }
+            if let Some(price) = payload.price {
+                update_operations.push(product::price::set(price));
+            }
+            if let Some(imageurl) = &payload.imageurl {
+                update_operations.push(product::image_url::set(imageurl.clone()));
+            }
+            if let Some(threshold) = payload.low_stock_threshold {
+                update_operations.push(product::low_stock_threshold::set(threshold));
+            }
+            if let Some(category) = &payload.category {
+                let categories =
+                    match keep_deleted_links(&prisma_client, &product_id, category).await {
+                        Ok(categories) => categories,
+                        Err(_) => {
+                            return HttpResponse::InternalServerError()
+                                .json(json!({"error": "Database error."}))
+                        }
+                    };
+                update_operations.push(product::categories::set(category_params(&categories)));
+            }
+            if !payload.add_categories.is_empty() {
+                update_operations.push(product::categories::connect(category_params(
+                    &payload.add_categories,
+                )));
+            }
+            if !payload.remove_categories.is_empty() {
+                update_operations.push(product::categories::disconnect(category_params(
+                    &payload.remove_categories,
+                )));
+            }
+            if update_operations.is_empty() && payload.stock.is_none() {
+                return HttpResponse::BadRequest().json(json!({"error": "Nothing to update."}));
+            }
+
+            save_product(
+                &req,
+                &prisma_client,
+                &product_id,
+                payload.stock,
+                update_operations,
+            )
+            .await
+        } else {
+            HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+    }
+}
+
+pub async fn restore_product(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    product_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if claims.is_admin {
+            let product_id = product_id.into_inner();
+            match prisma_client
                 .product()
-                .update(product::id::equals(product_id.clone()), update_operations)
+                .update_many(
+                    vec![
+                        product::id::equals(product_id.clone()),
+                        product::deleted_at::not(None),
+                    ],
+                    vec![product::deleted_at::set(None)],
+                )
                 .exec()
-                .await;
+                .await
+            {
+                Ok(0) => {
+                    HttpResponse::NotFound().json(json!({"error": "Deleted product not found."}))
+                }
+                Ok(_) => {
+                    AuditEvent::new("product.restored", "product")
+                        .by(&req)
+                        .entity(&product_id)
+                        .record_or_log(&prisma_client)
+                        .await;
 
-            match update_product_result {
-                Ok(product) => {
-                    // Fetch the updated product along with its categories
-                    let updated_product = prisma_client
-                        .product()
-                        .find_unique(product::id::equals(product_id.clone()))
-                        .with(product::categories::fetch(vec![]))
-                        .exec()
+                    HttpResponse::Ok().json(json!({"message": "Product restored successfully."}))
+                }
+                Err(_) => {
+                    HttpResponse::InternalServerError().json(json!({"error": "Database error."}))
+                }
+            }
+        } else {
+            HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+    }
+}
+
+/// Permanently removes a soft-deleted product with its reviews and category links. Products
+/// that appear in orders or purchase orders can't be purged, since their history has to stay
+/// intact.
+pub async fn purge_product(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    product_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if claims.is_admin {
+            let product_id = product_id.into_inner();
+            let product_record = match prisma_client
+                .product()
+                .find_first(vec![
+                    product::id::equals(product_id.clone()),
+                    product::deleted_at::not(None),
+                ])
+                .with(product::categories::fetch(vec![]))
+                .exec()
+                .await
+            {
+                Ok(Some(product_record)) => product_record,
+                Ok(None) => {
+                    return HttpResponse::NotFound()
+                        .json(json!({"error": "Deleted product not found."}))
+                }
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error."}))
+                }
+            };
+
+            match prisma_client
+                ._batch((
+                    prisma_client
+                        .order_item()
+                        .count(vec![order_item::product_id::equals(product_id.clone())]),
+                    prisma_client
+                        .purchase_order_line()
+                        .count(vec![purchase_order_line::product_id::equals(
+                            product_id.clone(),
+                        )]),
+                ))
+                .await
+            {
+                Ok((0, 0)) => {}
+                Ok(_) => {
+                    return HttpResponse::Conflict().json(json!({
+                        "error": "Product appears in orders or purchase orders and can only stay soft-deleted."
+                    }))
+                }
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error."}))
+                }
+            }
+
+            match hard_delete(&prisma_client, &product_id).await {
+                Ok(()) => {
+                    remove_image(&prisma_client, &product_record.image_url).await;
+                    AuditEvent::new("product.purged", "product")
+                        .by(&req)
+                        .entity(&product_id)
+                        .before(audit::product_snapshot(&product_record))
+                        .record_or_log(&prisma_client)
                         .await;
 
-                    match updated_product {
-                        Ok(Some(updated_product)) => {
-                            let response = ProductResponse {
-                                id: product.id.clone(),
-                                name: product.name.clone(),
-                                description: product.description.clone(),
-                                price: product.price,
-                                stock: product.stock,
-                                category: updated_product
-                                    .categories
-                                    .unwrap()
-                                    .into_iter()
-                                    .map(|cat| cat.name.clone())
-                                    .collect::<Vec<String>>(),
-                                imageurl: product.image_url.clone(),
-                            };
-                            HttpResponse::Ok().json(response)
-                        }
-                        Ok(None) => HttpResponse::InternalServerError()
-                            .json(json!({"error": "Updated product not found."})),
-                        Err(_) => HttpResponse::InternalServerError()
-                            .json(json!({"error": "Could not fetch updated product."})),
+                    HttpResponse::Ok().json(json!({"message": "Product permanently deleted."}))
+                }
+                Err(_) => {
+                    HttpResponse::InternalServerError().json(json!({"error": "Database error."}))
+                }
+            }
+        } else {
+            HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+    }
+}
+
+pub async fn delete_product(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    product_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if claims.is_admin {
+            match remove_product(&prisma_client, &product_id).await {
+                Ok(Some(removal)) => {
+                    audit_removal(&req, &prisma_client, &removal).await;
+                    match removal {
+                        Removal::Archived { .. } => HttpResponse::Ok().json(json!({
+                            "message": "Product archived because it appears in orders.",
+                            "status": "archived",
+                        })),
+                        Removal::Deleted(_) => HttpResponse::Ok().json(json!({
+                            "message": "Product deleted successfully.",
+                            "status": "deleted",
+                        })),
                     }
                 }
-                Err(_) => HttpResponse::BadRequest().json(json!({"error": "Invalid input data."})),
+                Ok(None) => HttpResponse::NotFound().json(json!({"error": "Product not found."})),
+                Err(_) => {
+                    HttpResponse::InternalServerError().json(json!({"error": "Database error."}))
+                }
+            }
+        } else {
+            HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+    }
+}
+
+/// Deletes or archives each product independently and reports what happened to every id.
+pub async fn bulk_delete_products(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    payload: web::Json<BulkDeletePayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if claims.is_admin {
+            if payload.ids.is_empty() || payload.ids.len() > BULK_DELETE_LIMIT {
+                return HttpResponse::BadRequest().json(json!({
+                    "error": format!("Provide between 1 and {} product ids.", BULK_DELETE_LIMIT)
+                }));
             }
+
+            let mut ids = payload.ids.clone();
+            ids.sort();
+            ids.dedup();
+
+            let mut results = vec![];
+            for product_id in ids {
+                let status = match remove_product(&prisma_client, &product_id).await {
+                    Ok(Some(removal)) => {
+                        audit_removal(&req, &prisma_client, &removal).await;
+                        match removal {
+                            Removal::Archived { .. } => "archived",
+                            Removal::Deleted(_) => "deleted",
+                        }
+                    }
+                    Ok(None) => "not_found",
+                    Err(_) => "error",
+                };
+                results.push(json!({"id": product_id, "status": status}));
+            }
+
+            let count = |status: &str| results.iter().filter(|r| r["status"] == status).count();
+            let response = json!({
+                "deleted": count("deleted"),
+                "archived": count("archived"),
+                "notFound": count("not_found"),
+                "failed": count("error"),
+                "results": results,
+            });
+            HttpResponse::Ok().json(response)
         } else {
             HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
         }
@@ -148,3 +932,46 @@ pub async fn update_product(
         HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(errors: &[FieldError]) -> Vec<&'static str> {
+        errors.iter().map(|error| error.field).collect()
+    }
+
+    #[test]
+    fn accepts_a_valid_product() {
+        let errors = validate_fields(Some("Mug"), Some(9.5), Some(0), Some("/uploads/mug.png"));
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn reports_every_invalid_field_at_once() {
+        let errors = validate_fields(Some("  "), Some(-1.0), Some(-3), Some(""));
+        assert_eq!(fields(&errors), vec!["name", "price", "stock", "imageurl"]);
+    }
+
+    #[test]
+    fn rejects_prices_that_are_not_finite() {
+        let errors = validate_fields(None, Some(f64::NAN), None, None);
+        assert_eq!(fields(&errors), vec!["price"]);
+        let errors = validate_fields(None, Some(f64::INFINITY), None, None);
+        assert_eq!(fields(&errors), vec!["price"]);
+    }
+
+    #[test]
+    fn skips_missing_fields() {
+        assert!(validate_fields(None, None, None, None).is_empty());
+    }
+
+    #[test]
+    fn rejects_a_negative_threshold() {
+        let mut errors = vec![];
+        check_threshold(Some(0), &mut errors);
+        assert!(errors.is_empty());
+        check_threshold(Some(-1), &mut errors);
+        assert_eq!(fields(&errors), vec!["low_stock_threshold"]);
+    }
+}
@@ -0,0 +1,1001 @@
+use crate::admin::model::{FieldError, PurchaseOrderPayload, PurchaseOrderQuery, ReceivePayload};
+use crate::audit::{self, AuditEvent};
+use crate::auth::model::Claims;
+use crate::inventory::{self, Movement};
+use crate::prisma::{
+    product, purchase_order, purchase_order_line, supplier, warehouse, MovementType, PrismaClient,
+    PurchaseOrderStatus,
+};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeZone, Utc};
+use prisma_client_rust::{raw, Direction, PrismaValue, QueryError};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Why a change inside a transaction was refused. Nothing has been written when one is
+/// returned, so the transaction can still commit.
+enum Rejection {
+    NotFound,
+    WrongStatus(PurchaseOrderStatus),
+    Invalid(Vec<FieldError>),
+}
+
+impl Rejection {
+    fn response(self) -> HttpResponse {
+        match self {
+            Rejection::NotFound => {
+                HttpResponse::NotFound().json(json!({"error": "Purchase order not found"}))
+            }
+            Rejection::WrongStatus(status) => HttpResponse::Conflict().json(json!({
+                "error": format!("Purchase order is {}", status.to_string()),
+            })),
+            Rejection::Invalid(errors) => invalid_fields(errors),
+        }
+    }
+}
+
+fn invalid_fields(errors: Vec<FieldError>) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "error": "Invalid input data.",
+        "fields": errors,
+    }))
+}
+
+fn parse_status(status: &str) -> Option<PurchaseOrderStatus> {
+    match status {
+        "draft" => Some(PurchaseOrderStatus::Draft),
+        "sent" => Some(PurchaseOrderStatus::Sent),
+        "partially_received" => Some(PurchaseOrderStatus::PartiallyReceived),
+        "received" => Some(PurchaseOrderStatus::Received),
+        _ => None,
+    }
+}
+
+fn filters(query: &PurchaseOrderQuery) -> Result<Vec<purchase_order::WhereParam>, String> {
+    let mut filters = vec![];
+    if let Some(status) = &query.status {
+        let status = parse_status(status).ok_or_else(|| format!("Unknown status {}", status))?;
+        filters.push(purchase_order::status::equals(status));
+    }
+    if let Some(supplier_id) = &query.supplier_id {
+        filters.push(purchase_order::supplier_id::equals(supplier_id.clone()));
+    }
+    Ok(filters)
+}
+
+fn purchase_order_json(purchase_order_record: &purchase_order::Data) -> Value {
+    let mut value = audit::purchase_order_snapshot(purchase_order_record);
+    value["createdById"] = json!(purchase_order_record.created_by_id);
+    value["sentAt"] = json!(purchase_order_record.sent_at);
+    value["receivedAt"] = json!(purchase_order_record.received_at);
+    value["createdAt"] = json!(purchase_order_record.created_at);
+    value["updatedAt"] = json!(purchase_order_record.updated_at);
+    if let Some(supplier_record) = &purchase_order_record.supplier {
+        value["supplier"] = json!({"id": supplier_record.id, "name": supplier_record.name});
+    }
+    if let Some(lines) = &purchase_order_record.lines {
+        let total_cost: f64 = lines
+            .iter()
+            .map(|line| line.quantity as f64 * line.unit_cost)
+            .sum();
+        value["totalCost"] = json!(total_cost);
+    }
+    value
+}
+
+async fn fetch(
+    client: &PrismaClient,
+    purchase_order_id: &str,
+) -> Result<Option<purchase_order::Data>, QueryError> {
+    client
+        .purchase_order()
+        .find_unique(purchase_order::id::equals(purchase_order_id.to_string()))
+        .with(purchase_order::supplier::fetch())
+        .with(purchase_order::lines::fetch(vec![]))
+        .exec()
+        .await
+}
+
+#[derive(Deserialize)]
+struct StatusRow {
+    status: PurchaseOrderStatus,
+}
+
+/// Status of a purchase order, locking it until the transaction ends.
+async fn lock(
+    client: &PrismaClient,
+    purchase_order_id: &str,
+) -> Result<Option<PurchaseOrderStatus>, QueryError> {
+    let rows: Vec<StatusRow> = client
+        ._query_raw(raw!(
+            r#"SELECT "status" FROM "PurchaseOrder" WHERE "id" = {} FOR UPDATE"#,
+            PrismaValue::String(purchase_order_id.to_string())
+        ))
+        .exec()
+        .await?;
+    Ok(rows.into_iter().next().map(|row| row.status))
+}
+
+/// Checks what a draft says on its own, before anything it points at is looked up. Returns
+/// the parsed expected date.
+fn check_fields(
+    payload: &PurchaseOrderPayload,
+    errors: &mut Vec<FieldError>,
+) -> Option<DateTime<FixedOffset>> {
+    if payload.lines.is_empty() {
+        errors.push(FieldError::new(
+            "lines",
+            "A purchase order needs at least one line.",
+        ));
+    }
+    let mut seen = HashSet::new();
+    for line in &payload.lines {
+        if !seen.insert(&line.product_id) {
+            errors.push(FieldError::new(
+                "lines",
+                &format!("Product {} appears more than once.", line.product_id),
+            ));
+        }
+        if line.quantity <= 0 {
+            errors.push(FieldError::new(
+                "lines",
+                &format!("Quantity of product {} must be positive.", line.product_id),
+            ));
+        }
+        if !line.unit_cost.is_finite() || line.unit_cost < 0.0 {
+            errors.push(FieldError::new(
+                "lines",
+                &format!(
+                    "Unit cost of product {} must be a non-negative number.",
+                    line.product_id
+                ),
+            ));
+        }
+    }
+    match &payload.expected_at {
+        Some(expected_at) => match NaiveDate::parse_from_str(expected_at, "%Y-%m-%d") {
+            Ok(date) => Some(
+                Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+                    .fixed_offset(),
+            ),
+            Err(_) => {
+                errors.push(FieldError::new(
+                    "expected_at",
+                    "Expected date must be in YYYY-MM-DD format.",
+                ));
+                None
+            }
+        },
+        None => None,
+    }
+}
+
+/// Checks a draft and everything it points at. Returns the parsed expected date.
+async fn check_payload(
+    prisma_client: &PrismaClient,
+    payload: &PurchaseOrderPayload,
+    errors: &mut Vec<FieldError>,
+) -> Result<Option<DateTime<FixedOffset>>, QueryError> {
+    let expected_at = check_fields(payload, errors);
+    let product_ids = payload
+        .lines
+        .iter()
+        .map(|line| line.product_id.clone())
+        .collect::<Vec<_>>();
+    let (suppliers, products) = prisma_client
+        ._batch((
+            prisma_client
+                .supplier()
+                .count(vec![supplier::id::equals(payload.supplier_id.clone())]),
+            prisma_client.product().find_many(vec![
+                product::id::in_vec(product_ids.clone()),
+                product::deleted_at::equals(None),
+            ]),
+        ))
+        .await?;
+    if suppliers == 0 {
+        errors.push(FieldError::new("supplier_id", "Supplier not found."));
+    }
+    if let Some(warehouse_id) = &payload.warehouse_id {
+        let warehouses = prisma_client
+            .warehouse()
+            .count(vec![warehouse::id::equals(warehouse_id.clone())])
+            .exec()
+            .await?;
+        if warehouses == 0 {
+            errors.push(FieldError::new("warehouse_id", "Warehouse not found."));
+        }
+    }
+    let unknown = product_ids
+        .iter()
+        .filter(|id| !products.iter().any(|p| &p.id == *id))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        errors.push(FieldError::new(
+            "lines",
+            &format!("Unknown products: {}.", unknown.join(", ")),
+        ));
+    }
+    Ok(expected_at)
+}
+
+/// Everything about a purchase order except its supplier and lines, as set from a payload.
+/// Leaves out the warehouse when there is none; an update has to disconnect it itself.
+fn order_params(
+    payload: &PurchaseOrderPayload,
+    expected_at: Option<DateTime<FixedOffset>>,
+) -> Vec<purchase_order::SetParam> {
+    let mut params = vec![
+        purchase_order::expected_at::set(expected_at),
+        purchase_order::notes::set(payload.notes.clone()),
+    ];
+    if let Some(warehouse_id) = &payload.warehouse_id {
+        params.push(purchase_order::warehouse::connect(warehouse::id::equals(
+            warehouse_id.clone(),
+        )));
+    }
+    params
+}
+
+async fn create_lines(
+    client: &PrismaClient,
+    purchase_order_id: &str,
+    lines: Vec<(String, i32, f64)>,
+) -> Result<(), QueryError> {
+    for (product_id, quantity, unit_cost) in lines {
+        client
+            .purchase_order_line()
+            .create(
+                purchase_order::id::equals(purchase_order_id.to_string()),
+                product::id::equals(product_id),
+                quantity,
+                unit_cost,
+                vec![],
+            )
+            .exec()
+            .await?;
+    }
+    Ok(())
+}
+
+fn line_values(payload: &PurchaseOrderPayload) -> Vec<(String, i32, f64)> {
+    payload
+        .lines
+        .iter()
+        .map(|line| (line.product_id.clone(), line.quantity, line.unit_cost))
+        .collect()
+}
+
+pub async fn get_purchase_orders(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    query: web::Query<PurchaseOrderQuery>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let page = query.page.unwrap_or(1).max(1);
+        let limit = query.limit.unwrap_or(10).clamp(1, 100);
+        let (where_params, count_params) = match (filters(&query), filters(&query)) {
+            (Ok(where_params), Ok(count_params)) => (where_params, count_params),
+            (Err(err), _) | (_, Err(err)) => {
+                return HttpResponse::BadRequest().json(json!({"error": err}))
+            }
+        };
+
+        let (total_items, purchase_orders) = match prisma_client
+            ._batch((
+                prisma_client.purchase_order().count(count_params),
+                prisma_client
+                    .purchase_order()
+                    .find_many(where_params)
+                    .with(purchase_order::supplier::fetch())
+                    .with(purchase_order::lines::fetch(vec![]))
+                    .order_by(purchase_order::created_at::order(Direction::Desc))
+                    .skip((page - 1) * limit)
+                    .take(limit),
+            ))
+            .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+
+        HttpResponse::Ok().json(json!({
+            "purchaseOrders": purchase_orders.iter().map(purchase_order_json).collect::<Vec<_>>(),
+            "pagination": {
+                "currentPage": page,
+                "totalPages": (total_items as f64 / limit as f64).ceil() as i64,
+                "totalItems": total_items,
+                "limit": limit,
+            }
+        }))
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+pub async fn get_purchase_order(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    purchase_order_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        match fetch(&prisma_client, &purchase_order_id).await {
+            Ok(Some(purchase_order_record)) => {
+                HttpResponse::Ok().json(purchase_order_json(&purchase_order_record))
+            }
+            Ok(None) => Rejection::NotFound.response(),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Creates a purchase order as a draft; nothing is expected in until it's sent.
+pub async fn create_purchase_order(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    payload: web::Json<PurchaseOrderPayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let mut errors = vec![];
+        let expected_at = match check_payload(&prisma_client, &payload, &mut errors).await {
+            Ok(expected_at) => expected_at,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+        if !errors.is_empty() {
+            return invalid_fields(errors);
+        }
+
+        let supplier_id = payload.supplier_id.clone();
+        let mut params = order_params(&payload, expected_at);
+        params.push(purchase_order::created_by_id::set(audit::actor_of(&req)));
+        let lines = line_values(&payload);
+        let result: Result<String, QueryError> = prisma_client
+            ._transaction()
+            .run(|client| {
+                Box::pin(async move {
+                    let created = client
+                        .purchase_order()
+                        .create(supplier::id::equals(supplier_id), params)
+                        .exec()
+                        .await?;
+                    create_lines(&client, &created.id, lines).await?;
+                    Ok(created.id)
+                })
+            })
+            .await;
+
+        match result {
+            Ok(purchase_order_id) => match fetch(&prisma_client, &purchase_order_id).await {
+                Ok(Some(created)) => {
+                    AuditEvent::new("purchase_order.created", "purchase_order")
+                        .by(&req)
+                        .entity(&created.id)
+                        .after(audit::purchase_order_snapshot(&created))
+                        .record_or_log(&prisma_client)
+                        .await;
+
+                    HttpResponse::Created().json(purchase_order_json(&created))
+                }
+                _ => HttpResponse::InternalServerError()
+                    .json(json!({"error": "Could not fetch created purchase order"})),
+            },
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Replaces a draft, lines included. Sent orders are fixed.
+pub async fn update_purchase_order(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    purchase_order_id: web::Path<String>,
+    payload: web::Json<PurchaseOrderPayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let mut errors = vec![];
+        let expected_at = match check_payload(&prisma_client, &payload, &mut errors).await {
+            Ok(expected_at) => expected_at,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+        if !errors.is_empty() {
+            return invalid_fields(errors);
+        }
+
+        let purchase_order_id = purchase_order_id.into_inner();
+        let before = match fetch(&prisma_client, &purchase_order_id).await {
+            Ok(Some(before)) => before,
+            Ok(None) => return Rejection::NotFound.response(),
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+
+        let id = purchase_order_id.clone();
+        let mut update_operations = order_params(&payload, expected_at);
+        update_operations.push(purchase_order::supplier::connect(supplier::id::equals(
+            payload.supplier_id.clone(),
+        )));
+        if payload.warehouse_id.is_none() {
+            update_operations.push(purchase_order::warehouse::disconnect());
+        }
+        let lines = line_values(&payload);
+        let result: Result<Result<(), Rejection>, QueryError> = prisma_client
+            ._transaction()
+            .run(|client| {
+                Box::pin(async move {
+                    match lock(&client, &id).await? {
+                        None => return Ok(Err(Rejection::NotFound)),
+                        Some(PurchaseOrderStatus::Draft) => {}
+                        Some(status) => return Ok(Err(Rejection::WrongStatus(status))),
+                    }
+                    client
+                        .purchase_order_line()
+                        .delete_many(vec![purchase_order_line::purchase_order_id::equals(
+                            id.clone(),
+                        )])
+                        .exec()
+                        .await?;
+                    client
+                        .purchase_order()
+                        .update(purchase_order::id::equals(id.clone()), update_operations)
+                        .exec()
+                        .await?;
+                    create_lines(&client, &id, lines).await?;
+                    Ok(Ok(()))
+                })
+            })
+            .await;
+
+        match result {
+            Ok(Ok(())) => match fetch(&prisma_client, &purchase_order_id).await {
+                Ok(Some(updated)) => {
+                    AuditEvent::new("purchase_order.updated", "purchase_order")
+                        .by(&req)
+                        .entity(&updated.id)
+                        .before(audit::purchase_order_snapshot(&before))
+                        .after(audit::purchase_order_snapshot(&updated))
+                        .record_or_log(&prisma_client)
+                        .await;
+
+                    HttpResponse::Ok().json(purchase_order_json(&updated))
+                }
+                _ => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+            },
+            Ok(Err(rejection)) => rejection.response(),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// 404 when the purchase order doesn't exist, otherwise 409 naming its status. For when a
+/// conditional update matched nothing.
+async fn not_in_status(prisma_client: &PrismaClient, purchase_order_id: &str) -> HttpResponse {
+    match prisma_client
+        .purchase_order()
+        .find_unique(purchase_order::id::equals(purchase_order_id.to_string()))
+        .exec()
+        .await
+    {
+        Ok(Some(found)) => Rejection::WrongStatus(found.status).response(),
+        Ok(None) => Rejection::NotFound.response(),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+    }
+}
+
+/// Marks a draft as sent to the supplier, after which it can be received against.
+pub async fn send_purchase_order(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    purchase_order_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let purchase_order_id = purchase_order_id.into_inner();
+        match prisma_client
+            .purchase_order()
+            .update_many(
+                vec![
+                    purchase_order::id::equals(purchase_order_id.clone()),
+                    purchase_order::status::equals(PurchaseOrderStatus::Draft),
+                ],
+                vec![
+                    purchase_order::status::set(PurchaseOrderStatus::Sent),
+                    purchase_order::sent_at::set(Some(Utc::now().fixed_offset())),
+                ],
+            )
+            .exec()
+            .await
+        {
+            Ok(0) => not_in_status(&prisma_client, &purchase_order_id).await,
+            Ok(_) => {
+                AuditEvent::new("purchase_order.sent", "purchase_order")
+                    .by(&req)
+                    .entity(&purchase_order_id)
+                    .before(json!({"status": PurchaseOrderStatus::Draft}))
+                    .after(json!({"status": PurchaseOrderStatus::Sent}))
+                    .record_or_log(&prisma_client)
+                    .await;
+
+                match fetch(&prisma_client, &purchase_order_id).await {
+                    Ok(Some(sent)) => HttpResponse::Ok().json(purchase_order_json(&sent)),
+                    _ => HttpResponse::Ok().json(json!({"message": "Purchase order sent"})),
+                }
+            }
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Deletes a draft. Sent orders stay as a record of what was ordered.
+pub async fn delete_purchase_order(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    purchase_order_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let purchase_order_id = purchase_order_id.into_inner();
+        let before = match fetch(&prisma_client, &purchase_order_id).await {
+            Ok(Some(before)) => before,
+            Ok(None) => return Rejection::NotFound.response(),
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+        match prisma_client
+            .purchase_order()
+            .delete_many(vec![
+                purchase_order::id::equals(purchase_order_id.clone()),
+                purchase_order::status::equals(PurchaseOrderStatus::Draft),
+            ])
+            .exec()
+            .await
+        {
+            Ok(0) => not_in_status(&prisma_client, &purchase_order_id).await,
+            Ok(_) => {
+                AuditEvent::new("purchase_order.deleted", "purchase_order")
+                    .by(&req)
+                    .entity(&purchase_order_id)
+                    .before(audit::purchase_order_snapshot(&before))
+                    .record_or_log(&prisma_client)
+                    .await;
+
+                HttpResponse::Ok().json(json!({"message": "Purchase order deleted successfully"}))
+            }
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Quantities to receive by product. Each must be positive and name its product only once.
+fn received_quantities(payload: &ReceivePayload) -> Result<HashMap<String, i32>, Vec<FieldError>> {
+    let mut errors = vec![];
+    if payload.lines.is_empty() {
+        errors.push(FieldError::new("lines", "Nothing to receive."));
+    }
+    let mut received = HashMap::new();
+    for line in &payload.lines {
+        if line.quantity <= 0 {
+            errors.push(FieldError::new(
+                "lines",
+                &format!("Quantity of product {} must be positive.", line.product_id),
+            ));
+        }
+        if received
+            .insert(line.product_id.clone(), line.quantity)
+            .is_some()
+        {
+            errors.push(FieldError::new(
+                "lines",
+                &format!("Product {} appears more than once.", line.product_id),
+            ));
+        }
+    }
+    if errors.is_empty() {
+        Ok(received)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Refuses products that aren't on the order and quantities beyond what is still outstanding,
+/// given per product in `outstanding`.
+fn check_receipt(
+    received: &HashMap<String, i32>,
+    outstanding: &HashMap<&str, i32>,
+) -> Vec<FieldError> {
+    let mut errors = vec![];
+    for (product_id, quantity) in received {
+        match outstanding.get(product_id.as_str()) {
+            None => errors.push(FieldError::new(
+                "lines",
+                &format!("Product {} isn't on this purchase order.", product_id),
+            )),
+            Some(outstanding) if quantity > outstanding => errors.push(FieldError::new(
+                "lines",
+                &format!(
+                    "Only {} of product {} are still outstanding.",
+                    outstanding, product_id
+                ),
+            )),
+            Some(_) => {}
+        }
+    }
+    errors
+}
+
+/// Books goods that arrived against a sent purchase order. Each received quantity is added to
+/// stock as a restock in the order's warehouse; the order becomes `received` once every line
+/// is complete and `partially_received` until then.
+pub async fn receive_purchase_order(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    purchase_order_id: web::Path<String>,
+    payload: web::Json<ReceivePayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let received = match received_quantities(&payload) {
+            Ok(received) => received,
+            Err(errors) => return invalid_fields(errors),
+        };
+
+        let purchase_order_id = purchase_order_id.into_inner();
+        let before = match fetch(&prisma_client, &purchase_order_id).await {
+            Ok(Some(before)) => before,
+            Ok(None) => return Rejection::NotFound.response(),
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+
+        let id = purchase_order_id.clone();
+        let actor_id = audit::actor_of(&req);
+        let note = payload.note.clone();
+        let booked = received.clone();
+        let result: Result<Result<(), Rejection>, QueryError> = prisma_client
+            ._transaction()
+            .run(|client| {
+                Box::pin(async move {
+                    match lock(&client, &id).await? {
+                        None => return Ok(Err(Rejection::NotFound)),
+                        Some(PurchaseOrderStatus::Sent)
+                        | Some(PurchaseOrderStatus::PartiallyReceived) => {}
+                        Some(status) => return Ok(Err(Rejection::WrongStatus(status))),
+                    }
+                    let current = match fetch(&client, &id).await? {
+                        Some(current) => current,
+                        None => return Ok(Err(Rejection::NotFound)),
+                    };
+                    let lines = current.lines.unwrap_or_default();
+
+                    let outstanding = lines
+                        .iter()
+                        .map(|line| {
+                            (
+                                line.product_id.as_str(),
+                                line.quantity - line.received_quantity,
+                            )
+                        })
+                        .collect::<HashMap<_, _>>();
+                    let errors = check_receipt(&received, &outstanding);
+                    if !errors.is_empty() {
+                        return Ok(Err(Rejection::Invalid(errors)));
+                    }
+
+                    for line in &lines {
+                        let quantity = match received.get(&line.product_id) {
+                            Some(quantity) => *quantity,
+                            None => continue,
+                        };
+                        client
+                            .purchase_order_line()
+                            .update(
+                                purchase_order_line::id::equals(line.id.clone()),
+                                vec![purchase_order_line::received_quantity::increment(quantity)],
+                            )
+                            .exec()
+                            .await?;
+                        let mut movement = Movement::new(MovementType::Restock)
+                            .reference(&format!("purchase_order:{}", id))
+                            .actor(actor_id.clone());
+                        if let Some(warehouse_id) = &current.warehouse_id {
+                            movement = movement.warehouse(warehouse_id);
+                        }
+                        if let Some(note) = &note {
+                            movement = movement.note(note);
+                        }
+                        inventory::apply(&client, &line.product_id, quantity, movement).await?;
+                    }
+
+                    let complete = lines.iter().all(|line| {
+                        line.received_quantity + received.get(&line.product_id).unwrap_or(&0)
+                            >= line.quantity
+                    });
+                    let update_operations = if complete {
+                        vec![
+                            purchase_order::status::set(PurchaseOrderStatus::Received),
+                            purchase_order::received_at::set(Some(Utc::now().fixed_offset())),
+                        ]
+                    } else {
+                        vec![purchase_order::status::set(
+                            PurchaseOrderStatus::PartiallyReceived,
+                        )]
+                    };
+                    client
+                        .purchase_order()
+                        .update(purchase_order::id::equals(id), update_operations)
+                        .exec()
+                        .await?;
+                    Ok(Ok(()))
+                })
+            })
+            .await;
+
+        match result {
+            Ok(Ok(())) => match fetch(&prisma_client, &purchase_order_id).await {
+                Ok(Some(updated)) => {
+                    AuditEvent::new("purchase_order.received", "purchase_order")
+                        .by(&req)
+                        .entity(&updated.id)
+                        .before(audit::purchase_order_snapshot(&before))
+                        .after(audit::purchase_order_snapshot(&updated))
+                        .metadata(json!({"received": booked}))
+                        .record_or_log(&prisma_client)
+                        .await;
+
+                    HttpResponse::Ok().json(purchase_order_json(&updated))
+                }
+                _ => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+            },
+            Ok(Err(rejection)) => rejection.response(),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::model::{PurchaseOrderLinePayload, ReceiveLinePayload};
+
+    fn line(product_id: &str, quantity: i32, unit_cost: f64) -> PurchaseOrderLinePayload {
+        PurchaseOrderLinePayload {
+            product_id: product_id.to_string(),
+            quantity,
+            unit_cost,
+        }
+    }
+
+    fn draft(
+        lines: Vec<PurchaseOrderLinePayload>,
+        expected_at: Option<&str>,
+    ) -> PurchaseOrderPayload {
+        PurchaseOrderPayload {
+            supplier_id: "supplier".to_string(),
+            warehouse_id: None,
+            expected_at: expected_at.map(str::to_string),
+            notes: None,
+            lines,
+        }
+    }
+
+    fn messages(errors: &[FieldError]) -> Vec<&str> {
+        errors.iter().map(|error| error.message.as_str()).collect()
+    }
+
+    fn receipt(lines: &[(&str, i32)]) -> ReceivePayload {
+        ReceivePayload {
+            lines: lines
+                .iter()
+                .map(|(product_id, quantity)| ReceiveLinePayload {
+                    product_id: product_id.to_string(),
+                    quantity: *quantity,
+                })
+                .collect(),
+            note: None,
+        }
+    }
+
+    #[test]
+    fn parses_every_status() {
+        assert!(matches!(
+            parse_status("draft"),
+            Some(PurchaseOrderStatus::Draft)
+        ));
+        assert!(matches!(
+            parse_status("sent"),
+            Some(PurchaseOrderStatus::Sent)
+        ));
+        assert!(matches!(
+            parse_status("partially_received"),
+            Some(PurchaseOrderStatus::PartiallyReceived)
+        ));
+        assert!(matches!(
+            parse_status("received"),
+            Some(PurchaseOrderStatus::Received)
+        ));
+        assert!(parse_status("Sent").is_none());
+        assert!(parse_status("cancelled").is_none());
+    }
+
+    #[test]
+    fn accepts_a_valid_draft() {
+        let mut errors = vec![];
+        let expected_at = check_fields(
+            &draft(
+                vec![line("a", 5, 2.5), line("b", 1, 0.0)],
+                Some("2026-11-02"),
+            ),
+            &mut errors,
+        );
+        assert!(errors.is_empty());
+        assert_eq!(
+            expected_at.unwrap().to_rfc3339(),
+            "2026-11-02T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn needs_at_least_one_line() {
+        let mut errors = vec![];
+        check_fields(&draft(vec![], None), &mut errors);
+        assert_eq!(
+            messages(&errors),
+            vec!["A purchase order needs at least one line."]
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_lines() {
+        let mut errors = vec![];
+        check_fields(
+            &draft(vec![line("a", 1, 1.0), line("a", 2, 1.0)], None),
+            &mut errors,
+        );
+        assert_eq!(messages(&errors), vec!["Product a appears more than once."]);
+    }
+
+    #[test]
+    fn rejects_non_positive_quantities() {
+        let mut errors = vec![];
+        check_fields(
+            &draft(vec![line("a", 0, 1.0), line("b", -3, 1.0)], None),
+            &mut errors,
+        );
+        assert_eq!(
+            messages(&errors),
+            vec![
+                "Quantity of product a must be positive.",
+                "Quantity of product b must be positive.",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_negative_and_non_finite_costs() {
+        let mut errors = vec![];
+        check_fields(
+            &draft(
+                vec![
+                    line("a", 1, -0.01),
+                    line("b", 1, f64::NAN),
+                    line("c", 1, f64::INFINITY),
+                ],
+                None,
+            ),
+            &mut errors,
+        );
+        assert_eq!(errors.len(), 3);
+        assert!(errors
+            .iter()
+            .all(|error| error.message.ends_with("must be a non-negative number.")));
+    }
+
+    #[test]
+    fn rejects_badly_formatted_dates() {
+        for expected_at in ["02/11/2026", "2026-13-01", "2026-11-02T00:00:00Z", ""] {
+            let mut errors = vec![];
+            let parsed = check_fields(
+                &draft(vec![line("a", 1, 1.0)], Some(expected_at)),
+                &mut errors,
+            );
+            assert!(parsed.is_none());
+            assert_eq!(errors[0].field, "expected_at");
+        }
+    }
+
+    #[test]
+    fn receipt_quantities_must_be_positive_and_unique() {
+        assert_eq!(
+            received_quantities(&receipt(&[("a", 2), ("b", 1)]))
+                .map(|received| received.len())
+                .ok(),
+            Some(2)
+        );
+        let errors = received_quantities(&receipt(&[("a", 0), ("a", 1)])).unwrap_err();
+        assert_eq!(
+            messages(&errors),
+            vec![
+                "Quantity of product a must be positive.",
+                "Product a appears more than once.",
+            ]
+        );
+        let errors = received_quantities(&receipt(&[])).unwrap_err();
+        assert_eq!(messages(&errors), vec!["Nothing to receive."]);
+    }
+
+    #[test]
+    fn cannot_receive_more_than_is_outstanding() {
+        let outstanding = HashMap::from([("a", 4), ("b", 0)]);
+        let received = HashMap::from([("a".to_string(), 4)]);
+        assert!(check_receipt(&received, &outstanding).is_empty());
+
+        let received = HashMap::from([("a".to_string(), 5)]);
+        assert_eq!(
+            messages(&check_receipt(&received, &outstanding)),
+            vec!["Only 4 of product a are still outstanding."]
+        );
+        let received = HashMap::from([("b".to_string(), 1)]);
+        assert_eq!(
+            messages(&check_receipt(&received, &outstanding)),
+            vec!["Only 0 of product b are still outstanding."]
+        );
+    }
+
+    #[test]
+    fn cannot_receive_products_that_are_not_on_the_order() {
+        let outstanding = HashMap::from([("a", 4)]);
+        let received = HashMap::from([("z".to_string(), 1)]);
+        assert_eq!(
+            messages(&check_receipt(&received, &outstanding)),
+            vec!["Product z isn't on this purchase order."]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use crate::admin::model::SupplierPayload;
+use crate::audit::{self, AuditEvent};
+use crate::auth::model::Claims;
+use crate::prisma::{purchase_order, supplier, PrismaClient};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use prisma_client_rust::Direction;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+fn supplier_json(supplier_record: &supplier::Data) -> Value {
+    let mut value = audit::supplier_snapshot(supplier_record);
+    value["createdAt"] = json!(supplier_record.created_at);
+    value["updatedAt"] = json!(supplier_record.updated_at);
+    value
+}
+
+fn supplier_params(payload: &SupplierPayload) -> Vec<supplier::SetParam> {
+    vec![
+        supplier::email::set(payload.email.clone()),
+        supplier::phone::set(payload.phone.clone()),
+        supplier::address::set(payload.address.clone()),
+        supplier::notes::set(payload.notes.clone()),
+    ]
+}
+
+pub async fn get_suppliers(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        match prisma_client
+            .supplier()
+            .find_many(vec![])
+            .order_by(supplier::name::order(Direction::Asc))
+            .exec()
+            .await
+        {
+            Ok(suppliers) => HttpResponse::Ok().json(json!({
+                "suppliers": suppliers.iter().map(supplier_json).collect::<Vec<_>>(),
+            })),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+pub async fn create_supplier(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    payload: web::Json<SupplierPayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+        if payload.name.trim().is_empty() {
+            return HttpResponse::BadRequest().json(json!({"error": "Name must not be empty."}));
+        }
+
+        match prisma_client
+            .supplier()
+            .create(payload.name.clone(), supplier_params(&payload))
+            .exec()
+            .await
+        {
+            Ok(created) => {
+                AuditEvent::new("supplier.created", "supplier")
+                    .by(&req)
+                    .entity(&created.id)
+                    .after(audit::supplier_snapshot(&created))
+                    .record_or_log(&prisma_client)
+                    .await;
+
+                HttpResponse::Created().json(supplier_json(&created))
+            }
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+pub async fn update_supplier(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    supplier_id: web::Path<String>,
+    payload: web::Json<SupplierPayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+        if payload.name.trim().is_empty() {
+            return HttpResponse::BadRequest().json(json!({"error": "Name must not be empty."}));
+        }
+
+        let supplier_id = supplier_id.into_inner();
+        let before = match prisma_client
+            .supplier()
+            .find_unique(supplier::id::equals(supplier_id.clone()))
+            .exec()
+            .await
+        {
+            Ok(Some(before)) => before,
+            Ok(None) => {
+                return HttpResponse::NotFound().json(json!({"error": "Supplier not found"}))
+            }
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+
+        let mut update_operations = supplier_params(&payload);
+        update_operations.push(supplier::name::set(payload.name.clone()));
+        match prisma_client
+            .supplier()
+            .update(supplier::id::equals(supplier_id), update_operations)
+            .exec()
+            .await
+        {
+            Ok(updated) => {
+                AuditEvent::new("supplier.updated", "supplier")
+                    .by(&req)
+                    .entity(&updated.id)
+                    .before(audit::supplier_snapshot(&before))
+                    .after(audit::supplier_snapshot(&updated))
+                    .record_or_log(&prisma_client)
+                    .await;
+
+                HttpResponse::Ok().json(supplier_json(&updated))
+            }
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Deletes a supplier that was never ordered from; purchase orders keep their supplier.
+pub async fn delete_supplier(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    supplier_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let supplier_id = supplier_id.into_inner();
+        let (found, ordered) = match prisma_client
+            ._batch((
+                prisma_client
+                    .supplier()
+                    .find_unique(supplier::id::equals(supplier_id.clone())),
+                prisma_client
+                    .purchase_order()
+                    .count(vec![purchase_order::supplier_id::equals(
+                        supplier_id.clone(),
+                    )]),
+            ))
+            .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+        let supplier_record = match found {
+            Some(supplier_record) => supplier_record,
+            None => return HttpResponse::NotFound().json(json!({"error": "Supplier not found"})),
+        };
+        if ordered > 0 {
+            return HttpResponse::Conflict()
+                .json(json!({"error": "Supplier has purchase orders and can't be deleted"}));
+        }
+
+        match prisma_client
+            .supplier()
+            .delete(supplier::id::equals(supplier_id.clone()))
+            .exec()
+            .await
+        {
+            Ok(_) => {
+                AuditEvent::new("supplier.deleted", "supplier")
+                    .by(&req)
+                    .entity(&supplier_id)
+                    .before(audit::supplier_snapshot(&supplier_record))
+                    .record_or_log(&prisma_client)
+                    .await;
+
+                HttpResponse::Ok().json(json!({"message": "Supplier deleted successfully"}))
+            }
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
@@ -1,10 +1,13 @@
 use crate::admin::model::*;
-use crate::auth::model::{Claims, UserResponse};
+use crate::audit::{self, AuditEvent};
+use crate::auth::model::{Claims, ImpersonatePayload, UserResponse};
+use crate::auth::{lockout, token};
 use crate::{
-    prisma::{user, PrismaClient},
+    prisma::{comment, order, review, user, PrismaClient},
     RoleType,
 }; // Adjust based on your actual imports
 use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
 use serde_json::json;
 use std::sync::Arc;
 
@@ -14,67 +17,72 @@ pub async fn get_users(
     query: web::Query<PaginationQuery>,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
-        println!("the app passed here");
-        match prisma_client
-            .user()
-            .find_unique(user::id::equals(claims.sub.clone()))
-            .exec()
-            .await
-        {
-            Ok(Some(user)) => {
-                if user.role == RoleType::Admin {
-                    let page = query.page.unwrap_or(1);
-                    let limit = query.limit.unwrap_or(10);
-                    let search = query.search.as_deref().unwrap_or("");
+        if claims.is_admin {
+            let page = query.page.unwrap_or(1);
+            let limit = query.limit.unwrap_or(10);
+            let search = query.search.as_deref().unwrap_or("");
+
+            // Soft-deleted users are only listed on request.
+            let deleted_filter = if query.include_deleted {
+                vec![]
+            } else {
+                vec![user::deleted_at::equals(None)]
+            };
 
-                    // Fetch users with optional search
-                    let total_items = prisma_client.user().count(vec![]).exec().await.unwrap_or(0);
+            // Fetch users with optional search
+            let total_items = prisma_client
+                .user()
+                .count(deleted_filter.clone())
+                .exec()
+                .await
+                .unwrap_or(0);
 
-                    let total_pages = (total_items as f64 / limit as f64).ceil() as i64;
+            let total_pages = (total_items as f64 / limit as f64).ceil() as i64;
 
-                    let users = prisma_client
-                        .user()
-                        .find_many(vec![
+            let users = prisma_client
+                .user()
+                .find_many(
+                    [
+                        vec![
                             user::display_name::contains(search.to_string()),
                             user::email::contains(search.to_string()),
-                        ])
-                        .skip((page - 1) * limit as i64)
-                        .take(limit)
-                        .exec()
-                        .await
-                        .unwrap_or_default();
-
-                    let response = json!({
-                        "users": users.iter().map(|u| {
-                            json!({
-                                "id": u.id,
-                                "username": u.display_name,
-                                "email": u.email,
-                                "firstName": u.first_name,
-                                "lastName": u.last_name,
-                                "role": u.role,
-                                "createdAt": u.created_at,
-                            })
-                        }).collect::<Vec<_>>(),
-                        "pagination": {
-                            "currentPage": page,
-                            "totalPages": total_pages,
-                            "totalItems": total_items,
-                            "limit": limit,
-                        }
-                    });
+                        ],
+                        deleted_filter,
+                    ]
+                    .concat(),
+                )
+                .skip((page - 1) * limit as i64)
+                .take(limit)
+                .exec()
+                .await
+                .unwrap_or_default();
 
-                    HttpResponse::Ok().json(response)
-                } else {
-                    HttpResponse::Unauthorized()
-                        .json(json!({"error": "You don't have admin privilege."}))
+            let response = json!({
+                "users": users.iter().map(|u| {
+                    json!({
+                        "id": u.id,
+                        "username": u.display_name,
+                        "email": u.email,
+                        "firstName": u.first_name,
+                        "lastName": u.last_name,
+                        "role": u.role,
+                        "createdAt": u.created_at,
+                        "deletedAt": u.deleted_at,
+                    })
+                }).collect::<Vec<_>>(),
+                "pagination": {
+                    "currentPage": page,
+                    "totalPages": total_pages,
+                    "totalItems": total_items,
+                    "limit": limit,
                 }
-            }
-            Ok(None) => HttpResponse::NotFound().json(json!({"error": "User not found."})),
-            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error."})),
+            });
+
+            HttpResponse::Ok().json(response)
+        } else {
+            HttpResponse::Unauthorized().json(json!({"error": "You don't have admin privilege."}))
         }
     } else {
-        println!("the app passed here");
         HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
     }
 }
@@ -87,6 +95,14 @@ pub async fn update_user_role(
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
         if claims.is_admin {
+            let before = prisma_client
+                .user()
+                .find_unique(user::id::equals(user_id.clone()))
+                .exec()
+                .await
+                .ok()
+                .flatten();
+
             let updated_user = prisma_client
                 .user()
                 .update(
@@ -101,10 +117,26 @@ pub async fn update_user_role(
 
             match updated_user {
                 Ok(user) => {
+                    // Outstanding tokens still carry the old role, so force the user to log in again.
+                    if token::revoke_user_tokens(&prisma_client, &user.id).await.is_err() {
+                        return HttpResponse::InternalServerError()
+                            .json(json!({"error": "Failed to revoke existing sessions"}));
+                    }
+
+                    let mut event = AuditEvent::new("user.role_updated", "user")
+                        .by(&req)
+                        .entity(&user.id)
+                        .after(audit::user_snapshot(&user));
+                    if let Some(before) = &before {
+                        event = event.before(audit::user_snapshot(before));
+                    }
+                    event.record_or_log(&prisma_client).await;
+
                     let response = UserResponse {
                         id: user.id,
                         username: user.display_name,
                         email: user.email,
+                        email_verified: user.email_verified,
                         first_name: user.first_name,
                         last_name: user.last_name,
                         role: match user.role {
@@ -137,14 +169,290 @@ pub async fn delete_user(
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
         if claims.is_admin {
+            let before = match prisma_client
+                .user()
+                .find_first(vec![
+                    user::id::equals(user_id.clone()),
+                    user::deleted_at::equals(None),
+                ])
+                .exec()
+                .await
+            {
+                Ok(Some(before)) => before,
+                Ok(None) => {
+                    return HttpResponse::NotFound().json(json!({"error": "User not found"}))
+                }
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error"}))
+                }
+            };
+
+            // Soft delete: orders, reviews and comments keep pointing at the row. The
+            // middleware and login refuse deleted users, and their tokens are revoked here.
             let deleted_user = prisma_client
                 .user()
-                .delete(user::id::equals(user_id.clone()))
+                .update(
+                    user::id::equals(user_id.clone()),
+                    vec![user::deleted_at::set(Some(Utc::now().fixed_offset()))],
+                )
                 .exec()
                 .await;
 
             match deleted_user {
-                Ok(_) => HttpResponse::Ok().json(json!({"message": "User deleted successfully"})),
+                Ok(deleted_user) => {
+                    token::revoke_user_tokens(&prisma_client, &deleted_user.id)
+                        .await
+                        .ok();
+                    AuditEvent::new("user.deleted", "user")
+                        .by(&req)
+                        .entity(&deleted_user.id)
+                        .before(audit::user_snapshot(&before))
+                        .after(audit::user_snapshot(&deleted_user))
+                        .record_or_log(&prisma_client)
+                        .await;
+
+                    HttpResponse::Ok().json(json!({"message": "User deleted successfully"}))
+                }
+                Err(_) => {
+                    HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+                }
+            }
+        } else {
+            HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+    }
+}
+
+pub async fn unlock_user(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    user_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if claims.is_admin {
+            match prisma_client
+                .user()
+                .find_unique(user::id::equals(user_id.clone()))
+                .exec()
+                .await
+            {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    return HttpResponse::NotFound().json(json!({"error": "User not found"}))
+                }
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error"}))
+                }
+            }
+
+            let unlocked_user = match lockout::reset(&prisma_client, &user_id).await {
+                Ok(user) => user,
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error"}))
+                }
+            };
+
+            AuditEvent::new("account.unlocked", "user")
+                .by(&req)
+                .entity(&unlocked_user.id)
+                .record_or_log(&prisma_client)
+                .await;
+
+            HttpResponse::Ok().json(json!({"message": "User unlocked successfully"}))
+        } else {
+            HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+    }
+}
+
+/// Lets support staff see the store exactly as a customer does, through a short-lived token
+/// for that customer. Every session is written to the audit log.
+pub async fn impersonate_user(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    user_id: web::Path<String>,
+    payload: web::Json<ImpersonatePayload>,
+) -> impl Responder {
+    let admin_id = match req.extensions().get::<Claims>() {
+        Some(claims)
+            if claims.is_admin && claims.api_key_id.is_none() && claims.impersonator.is_none() =>
+        {
+            claims.sub.clone()
+        }
+        _ => return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."})),
+    };
+
+    if payload.reason.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "A reason is required"}));
+    }
+
+    let target = match prisma_client
+        .user()
+        .find_unique(user::id::equals(user_id.clone()))
+        .exec()
+        .await
+    {
+        Ok(Some(target)) => target,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "User not found"})),
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+        }
+    };
+    if target.role == RoleType::Admin {
+        return HttpResponse::Forbidden().json(json!({"error": "Admins can't be impersonated"}));
+    }
+
+    let access_token = match token::issue_impersonation_token(&target, &admin_id) {
+        Ok(access_token) => access_token,
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(json!({"error": "Failed to generate token"}))
+        }
+    };
+
+    // No audit entry, no token: support sessions must always be traceable.
+    if AuditEvent::new("user.impersonated", "user")
+        .actor(Some(admin_id))
+        .entity(&target.id)
+        .ip(token::client_ip(&req))
+        .metadata(json!({
+            "reason": payload.reason.trim(),
+            "expiresIn": token::IMPERSONATION_TOKEN_TTL,
+        }))
+        .record(&prisma_client)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().json(json!({"error": "Database error"}));
+    }
+
+    HttpResponse::Ok().json(json!({
+        "token": access_token,
+        "expires_in": token::IMPERSONATION_TOKEN_TTL,
+        "user_id": target.id,
+    }))
+}
+
+pub async fn restore_user(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    user_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if claims.is_admin {
+            match prisma_client
+                .user()
+                .update_many(
+                    vec![
+                        user::id::equals(user_id.clone()),
+                        user::deleted_at::not(None),
+                    ],
+                    vec![user::deleted_at::set(None)],
+                )
+                .exec()
+                .await
+            {
+                Ok(0) => {
+                    HttpResponse::NotFound().json(json!({"error": "Deleted user not found"}))
+                }
+                Ok(_) => {
+                    AuditEvent::new("user.restored", "user")
+                        .by(&req)
+                        .entity(&user_id)
+                        .record_or_log(&prisma_client)
+                        .await;
+
+                    HttpResponse::Ok().json(json!({"message": "User restored successfully"}))
+                }
+                Err(_) => {
+                    HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+                }
+            }
+        } else {
+            HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized."}))
+    }
+}
+
+/// Permanently removes a soft-deleted user with their reviews and comments. Users with orders
+/// can't be purged, since order history has to stay intact.
+pub async fn purge_user(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    user_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if claims.is_admin {
+            let user_id = user_id.into_inner();
+            let user_record = match prisma_client
+                .user()
+                .find_first(vec![
+                    user::id::equals(user_id.clone()),
+                    user::deleted_at::not(None),
+                ])
+                .exec()
+                .await
+            {
+                Ok(Some(user_record)) => user_record,
+                Ok(None) => {
+                    return HttpResponse::NotFound()
+                        .json(json!({"error": "Deleted user not found"}))
+                }
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error"}))
+                }
+            };
+
+            match prisma_client
+                .order()
+                .count(vec![order::user_id::equals(user_id.clone())])
+                .exec()
+                .await
+            {
+                Ok(0) => {}
+                Ok(_) => {
+                    return HttpResponse::Conflict().json(json!({
+                        "error": "User has orders and can only stay soft-deleted"
+                    }))
+                }
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error"}))
+                }
+            }
+
+            match prisma_client
+                ._batch((
+                    prisma_client
+                        .comment()
+                        .delete_many(vec![comment::user_id::equals(user_id.clone())]),
+                    prisma_client
+                        .review()
+                        .delete_many(vec![review::user_id::equals(user_id.clone())]),
+                    prisma_client.user().delete(user::id::equals(user_id.clone())),
+                ))
+                .await
+            {
+                Ok(_) => {
+                    AuditEvent::new("user.purged", "user")
+                        .by(&req)
+                        .entity(&user_id)
+                        .before(audit::user_snapshot(&user_record))
+                        .record_or_log(&prisma_client)
+                        .await;
+
+                    HttpResponse::Ok().json(json!({"message": "User permanently deleted"}))
+                }
                 Err(_) => {
                     HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
                 }
@@ -0,0 +1,591 @@
+use crate::admin::model::{
+    PaginationQuery, StockLevelPayload, StockTransferPayload, WarehousePayload,
+};
+use crate::audit::{self, AuditEvent};
+use crate::auth::model::Claims;
+use crate::inventory::{self, Movement};
+use crate::prisma::{
+    order, product, purchase_order, stock_level, warehouse, MovementType, PrismaClient,
+};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use prisma_client_rust::prisma_errors::query_engine::UniqueKeyViolation;
+use prisma_client_rust::{Direction, QueryError};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+fn warehouse_json(warehouse_record: &warehouse::Data) -> Value {
+    let mut value = audit::warehouse_snapshot(warehouse_record);
+    value["createdAt"] = json!(warehouse_record.created_at);
+    value["updatedAt"] = json!(warehouse_record.updated_at);
+    if let Some(levels) = &warehouse_record.stock_levels {
+        value["totalStock"] = json!(levels.iter().map(|l| l.quantity as i64).sum::<i64>());
+    }
+    value
+}
+
+fn check_payload(payload: &WarehousePayload) -> Result<(), &'static str> {
+    if payload.code.trim().is_empty() {
+        return Err("Code must not be empty.");
+    }
+    if payload.name.trim().is_empty() {
+        return Err("Name must not be empty.");
+    }
+    if payload.is_default == Some(true) && payload.active == Some(false) {
+        return Err("The default warehouse must be active.");
+    }
+    Ok(())
+}
+
+/// Takes the default flag off every other warehouse, so `warehouse_id` can have it.
+async fn clear_default(client: &PrismaClient, warehouse_id: &str) -> Result<i64, QueryError> {
+    client
+        .warehouse()
+        .update_many(
+            vec![
+                warehouse::is_default::equals(true),
+                warehouse::id::not(warehouse_id.to_string()),
+            ],
+            vec![warehouse::is_default::set(false)],
+        )
+        .exec()
+        .await
+}
+
+pub async fn get_warehouses(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        match prisma_client
+            .warehouse()
+            .find_many(vec![])
+            .with(warehouse::stock_levels::fetch(vec![]))
+            .order_by(warehouse::code::order(Direction::Asc))
+            .exec()
+            .await
+        {
+            Ok(warehouses) => HttpResponse::Ok().json(json!({
+                "warehouses": warehouses.iter().map(warehouse_json).collect::<Vec<_>>(),
+            })),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+pub async fn create_warehouse(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    payload: web::Json<WarehousePayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+        if let Err(err) = check_payload(&payload) {
+            return HttpResponse::BadRequest().json(json!({"error": err}));
+        }
+
+        let (code, name) = (payload.code.trim().to_uppercase(), payload.name.clone());
+        let is_default = payload.is_default.unwrap_or(false);
+        let params = vec![
+            warehouse::address::set(payload.address.clone()),
+            warehouse::is_default::set(is_default),
+            warehouse::active::set(payload.active.unwrap_or(true)),
+        ];
+        let result: Result<warehouse::Data, QueryError> = prisma_client
+            ._transaction()
+            .run(|client| {
+                Box::pin(async move {
+                    let created = client.warehouse().create(code, name, params).exec().await?;
+                    if is_default {
+                        clear_default(&client, &created.id).await?;
+                    }
+                    Ok(created)
+                })
+            })
+            .await;
+
+        match result {
+            Ok(created) => {
+                AuditEvent::new("warehouse.created", "warehouse")
+                    .by(&req)
+                    .entity(&created.id)
+                    .after(audit::warehouse_snapshot(&created))
+                    .record_or_log(&prisma_client)
+                    .await;
+
+                HttpResponse::Created().json(warehouse_json(&created))
+            }
+            Err(err) if err.is_prisma_error::<UniqueKeyViolation>() => HttpResponse::Conflict()
+                .json(json!({"error": "A warehouse with this code already exists"})),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Updates a warehouse. The default can only move by making another warehouse the default,
+/// so there is always exactly one while any exist.
+pub async fn update_warehouse(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    warehouse_id: web::Path<String>,
+    payload: web::Json<WarehousePayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+        if let Err(err) = check_payload(&payload) {
+            return HttpResponse::BadRequest().json(json!({"error": err}));
+        }
+
+        let warehouse_id = warehouse_id.into_inner();
+        let before = match prisma_client
+            .warehouse()
+            .find_unique(warehouse::id::equals(warehouse_id.clone()))
+            .exec()
+            .await
+        {
+            Ok(Some(before)) => before,
+            Ok(None) => {
+                return HttpResponse::NotFound().json(json!({"error": "Warehouse not found"}))
+            }
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+        if before.is_default && payload.is_default == Some(false) {
+            return HttpResponse::BadRequest().json(json!({
+                "error": "Make another warehouse the default instead"
+            }));
+        }
+        let is_default = payload.is_default.unwrap_or(before.is_default);
+        if is_default && !payload.active.unwrap_or(before.active) {
+            return HttpResponse::BadRequest()
+                .json(json!({"error": "The default warehouse must be active."}));
+        }
+
+        let mut update_operations = vec![
+            warehouse::code::set(payload.code.trim().to_uppercase()),
+            warehouse::name::set(payload.name.clone()),
+            warehouse::address::set(payload.address.clone()),
+            warehouse::is_default::set(is_default),
+        ];
+        if let Some(active) = payload.active {
+            update_operations.push(warehouse::active::set(active));
+        }
+        let id = warehouse_id.clone();
+        let result: Result<warehouse::Data, QueryError> = prisma_client
+            ._transaction()
+            .run(|client| {
+                Box::pin(async move {
+                    if is_default {
+                        clear_default(&client, &id).await?;
+                    }
+                    client
+                        .warehouse()
+                        .update(warehouse::id::equals(id), update_operations)
+                        .exec()
+                        .await
+                })
+            })
+            .await;
+
+        match result {
+            Ok(updated) => {
+                AuditEvent::new("warehouse.updated", "warehouse")
+                    .by(&req)
+                    .entity(&updated.id)
+                    .before(audit::warehouse_snapshot(&before))
+                    .after(audit::warehouse_snapshot(&updated))
+                    .record_or_log(&prisma_client)
+                    .await;
+
+                HttpResponse::Ok().json(warehouse_json(&updated))
+            }
+            Err(err) if err.is_prisma_error::<UniqueKeyViolation>() => HttpResponse::Conflict()
+                .json(json!({"error": "A warehouse with this code already exists"})),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Deletes an empty warehouse. One that still holds stock or has shipped orders can only be
+/// deactivated.
+pub async fn delete_warehouse(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    warehouse_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let warehouse_id = warehouse_id.into_inner();
+        let (found, stocked, orders) = match prisma_client
+            ._batch((
+                prisma_client
+                    .warehouse()
+                    .find_unique(warehouse::id::equals(warehouse_id.clone())),
+                prisma_client.stock_level().count(vec![
+                    stock_level::warehouse_id::equals(warehouse_id.clone()),
+                    stock_level::quantity::not(0),
+                ]),
+                prisma_client
+                    .order()
+                    .count(vec![order::warehouse_id::equals(Some(
+                        warehouse_id.clone(),
+                    ))]),
+            ))
+            .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+        let warehouse_record = match found {
+            Some(warehouse_record) => warehouse_record,
+            None => return HttpResponse::NotFound().json(json!({"error": "Warehouse not found"})),
+        };
+        if warehouse_record.is_default {
+            return HttpResponse::Conflict()
+                .json(json!({"error": "The default warehouse can't be deleted"}));
+        }
+        let purchase_orders = match prisma_client
+            .purchase_order()
+            .count(vec![purchase_order::warehouse_id::equals(Some(
+                warehouse_id.clone(),
+            ))])
+            .exec()
+            .await
+        {
+            Ok(purchase_orders) => purchase_orders,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+        if stocked > 0 || orders > 0 || purchase_orders > 0 {
+            return HttpResponse::Conflict().json(json!({
+                "error": "Warehouse holds stock or has orders or purchase orders; deactivate it instead"
+            }));
+        }
+        // Stock held for pending orders has left the levels but would have nowhere to go back to.
+        match inventory::reservations_in(&prisma_client, &warehouse_id).await {
+            Ok(0) => {}
+            Ok(_) => return HttpResponse::Conflict().json(json!({
+                "error": "Warehouse holds stock reserved for pending orders; deactivate it instead"
+            })),
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        }
+
+        match prisma_client
+            ._batch((
+                prisma_client
+                    .stock_level()
+                    .delete_many(vec![stock_level::warehouse_id::equals(
+                        warehouse_id.clone(),
+                    )]),
+                prisma_client
+                    .warehouse()
+                    .delete(warehouse::id::equals(warehouse_id.clone())),
+            ))
+            .await
+        {
+            Ok(_) => {
+                AuditEvent::new("warehouse.deleted", "warehouse")
+                    .by(&req)
+                    .entity(&warehouse_id)
+                    .before(audit::warehouse_snapshot(&warehouse_record))
+                    .record_or_log(&prisma_client)
+                    .await;
+
+                HttpResponse::Ok().json(json!({"message": "Warehouse deleted successfully"}))
+            }
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Stock levels held in one warehouse, optionally filtered by product name.
+pub async fn get_warehouse_stock(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    warehouse_id: web::Path<String>,
+    query: web::Query<PaginationQuery>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+
+        let warehouse_id = warehouse_id.into_inner();
+        let page = query.page.unwrap_or(1).max(1);
+        let limit = query.limit.unwrap_or(10).clamp(1, 100);
+        let filters = || {
+            let mut filters = vec![stock_level::warehouse_id::equals(warehouse_id.clone())];
+            if let Some(search) = &query.search {
+                filters.push(stock_level::product::is(vec![product::name::contains(
+                    search.clone(),
+                )]));
+            }
+            filters
+        };
+
+        let (total_items, levels) = match prisma_client
+            ._batch((
+                prisma_client.stock_level().count(filters()),
+                prisma_client
+                    .stock_level()
+                    .find_many(filters())
+                    .with(stock_level::product::fetch())
+                    .order_by(stock_level::quantity::order(Direction::Asc))
+                    .skip((page - 1) * limit)
+                    .take(limit),
+            ))
+            .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        };
+
+        let stock = levels
+            .into_iter()
+            .filter_map(|level| {
+                let product_record = level.product?;
+                Some(json!({
+                    "productId": product_record.id,
+                    "sku": product_record.sku,
+                    "name": product_record.name,
+                    "quantity": level.quantity,
+                    "updatedAt": level.updated_at,
+                }))
+            })
+            .collect::<Vec<_>>();
+        HttpResponse::Ok().json(json!({
+            "stock": stock,
+            "pagination": {
+                "currentPage": page,
+                "totalPages": (total_items as f64 / limit as f64).ceil() as i64,
+                "totalItems": total_items,
+                "limit": limit,
+            }
+        }))
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Sets how much of a product one warehouse holds, e.g. after a stock count. The difference
+/// is recorded as an adjustment there and changes the product's total stock with it.
+pub async fn set_stock_level(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    path: web::Path<(String, String)>,
+    payload: web::Json<StockLevelPayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+        if payload.quantity < 0 {
+            return HttpResponse::BadRequest()
+                .json(json!({"error": "Quantity must not be negative."}));
+        }
+
+        let (warehouse_id, product_id) = path.into_inner();
+        match prisma_client
+            ._batch((
+                prisma_client
+                    .warehouse()
+                    .count(vec![warehouse::id::equals(warehouse_id.clone())]),
+                prisma_client
+                    .product()
+                    .count(vec![product::id::equals(product_id.clone())]),
+            ))
+            .await
+        {
+            Ok((0, _)) => {
+                return HttpResponse::NotFound().json(json!({"error": "Warehouse not found"}))
+            }
+            Ok((_, 0)) => {
+                return HttpResponse::NotFound().json(json!({"error": "Product not found"}))
+            }
+            Ok(_) => {}
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        }
+
+        let mut movement = Movement::new(MovementType::Adjustment).actor(audit::actor_of(&req));
+        if let Some(note) = &payload.note {
+            movement = movement.note(note);
+        }
+        let (id, location, quantity) = (product_id.clone(), warehouse_id.clone(), payload.quantity);
+        let result: Result<_, QueryError> = prisma_client
+            ._transaction()
+            .run(|client| {
+                Box::pin(async move {
+                    inventory::adjust_level_to(&client, &id, &location, quantity, movement).await
+                })
+            })
+            .await;
+
+        match result {
+            Ok(movement) => {
+                if let Some(movement) = &movement {
+                    AuditEvent::new("inventory.adjusted", "product")
+                        .by(&req)
+                        .entity(&product_id)
+                        .after(json!({
+                            "warehouseId": warehouse_id,
+                            "quantity": movement.quantity,
+                            "stockAfter": movement.stock_after,
+                        }))
+                        .record_or_log(&prisma_client)
+                        .await;
+                }
+
+                HttpResponse::Ok().json(json!({
+                    "productId": product_id,
+                    "warehouseId": warehouse_id,
+                    "quantity": quantity,
+                }))
+            }
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Moves stock of one product between warehouses. The product's total doesn't change; the
+/// ledger gets a matching pair of adjustments sharing a `transfer:` reference.
+pub async fn transfer_stock(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+    payload: web::Json<StockTransferPayload>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if !claims.is_admin {
+            return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}));
+        }
+        if payload.quantity <= 0 {
+            return HttpResponse::BadRequest().json(json!({"error": "Quantity must be positive."}));
+        }
+        if payload.from_warehouse_id == payload.to_warehouse_id {
+            return HttpResponse::BadRequest()
+                .json(json!({"error": "Source and destination must differ."}));
+        }
+
+        match prisma_client
+            .warehouse()
+            .count(vec![warehouse::id::in_vec(vec![
+                payload.from_warehouse_id.clone(),
+                payload.to_warehouse_id.clone(),
+            ])])
+            .exec()
+            .await
+        {
+            Ok(2) => {}
+            Ok(_) => return HttpResponse::NotFound().json(json!({"error": "Warehouse not found"})),
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        }
+
+        let transfer_id: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(12)
+            .map(char::from)
+            .collect();
+        let reference = format!("transfer:{}", transfer_id);
+        let actor_id = audit::actor_of(&req);
+        let movement = |warehouse_id: &str| {
+            let movement = Movement::new(MovementType::Adjustment)
+                .warehouse(warehouse_id)
+                .reference(&reference)
+                .actor(actor_id.clone());
+            match &payload.note {
+                Some(note) => movement.note(note),
+                None => movement,
+            }
+        };
+        let (outbound, inbound) = (
+            movement(&payload.from_warehouse_id),
+            movement(&payload.to_warehouse_id),
+        );
+        let (product_id, from, quantity) = (
+            payload.product_id.clone(),
+            payload.from_warehouse_id.clone(),
+            payload.quantity,
+        );
+
+        // As with stock movements, the inner error is the source's stock when it holds too
+        // little, or `None` when the product doesn't exist.
+        let result: Result<Result<(), Option<i32>>, QueryError> = prisma_client
+            ._transaction()
+            .run(|client| {
+                Box::pin(async move {
+                    if inventory::lock_stock(&client, &product_id).await?.is_none() {
+                        return Ok(Err(None));
+                    }
+                    let available = inventory::level(&client, &product_id, &from).await?;
+                    if available < quantity {
+                        return Ok(Err(Some(available)));
+                    }
+                    inventory::apply(&client, &product_id, -quantity, outbound).await?;
+                    inventory::apply(&client, &product_id, quantity, inbound).await?;
+                    Ok(Ok(()))
+                })
+            })
+            .await;
+
+        match result {
+            Ok(Ok(())) => {
+                let transfer = json!({
+                    "reference": reference,
+                    "productId": payload.product_id,
+                    "fromWarehouseId": payload.from_warehouse_id,
+                    "toWarehouseId": payload.to_warehouse_id,
+                    "quantity": payload.quantity,
+                });
+                AuditEvent::new("inventory.transferred", "product")
+                    .by(&req)
+                    .entity(&payload.product_id)
+                    .after(transfer.clone())
+                    .record_or_log(&prisma_client)
+                    .await;
+
+                HttpResponse::Created().json(transfer)
+            }
+            Ok(Err(None)) => HttpResponse::NotFound().json(json!({"error": "Product not found"})),
+            Ok(Err(Some(available))) => HttpResponse::Conflict().json(json!({
+                "error": "Not enough stock in the source warehouse.",
+                "stock": available,
+            })),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
@@ -7,6 +7,8 @@ pub struct PaginationQuery {
     #[serde(default = "default_limit")]
     pub limit: Option<i64>,
     pub search: Option<String>,
+    #[serde(default)]
+    pub include_deleted: bool,
 }
 #[derive(Debug, Deserialize)]
 pub struct GetProductsPagniationQuery {
@@ -25,17 +27,178 @@ pub struct RolePayload {
 
 #[derive(Deserialize)]
 pub struct ProductPayload {
+    #[serde(default)]
+    pub sku: Option<String>,
     pub name: String,
     pub description: String,
     pub price: f64,
     pub stock: i32,
     pub category: Vec<String>,
     pub imageurl: String,
+    #[serde(default)]
+    pub low_stock_threshold: Option<i32>,
+}
+
+/// Partial product update: omitted fields are left as they are.
+#[derive(Deserialize)]
+pub struct ProductPatchPayload {
+    pub sku: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub price: Option<f64>,
+    pub stock: Option<i32>,
+    pub imageurl: Option<String>,
+    pub low_stock_threshold: Option<i32>,
+    /// Replaces every category of the product.
+    pub category: Option<Vec<String>>,
+    #[serde(default)]
+    pub add_categories: Vec<String>,
+    #[serde(default)]
+    pub remove_categories: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: &str) -> Self {
+        FieldError {
+            field,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JobQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+    pub status: Option<String>,
+    pub kind: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CatalogImportQuery {
+    /// `csv` or `json`; taken from the Content-Type when omitted.
+    pub format: Option<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Queue the import as a job instead of running it in the request.
+    #[serde(default)]
+    pub background: bool,
+}
+
+#[derive(Deserialize)]
+pub struct CatalogExportQuery {
+    /// `csv` (default) or `json`.
+    pub format: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct StockMovementQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+    pub kind: Option<String>,
+}
+
+/// A manual stock movement. Sales and reservations come from orders, so only `restock`,
+/// `return` and `adjustment` are accepted here.
+#[derive(Deserialize)]
+pub struct StockMovementPayload {
+    pub kind: String,
+    /// Positive adds stock; only adjustments may be negative.
+    pub quantity: i32,
+    pub reference: Option<String>,
+    pub note: Option<String>,
+    /// Defaults to the default warehouse.
+    pub warehouse_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WarehousePayload {
+    pub code: String,
+    pub name: String,
+    pub address: Option<String>,
+    /// Making a warehouse the default takes the flag from the current one.
+    pub is_default: Option<bool>,
+    pub active: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct StockLevelPayload {
+    pub quantity: i32,
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct StockTransferPayload {
+    pub product_id: String,
+    pub from_warehouse_id: String,
+    pub to_warehouse_id: String,
+    pub quantity: i32,
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SupplierPayload {
+    pub name: String,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub address: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PurchaseOrderQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+    pub status: Option<String>,
+    pub supplier_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PurchaseOrderLinePayload {
+    pub product_id: String,
+    pub quantity: i32,
+    pub unit_cost: f64,
+}
+
+/// Creates a draft, or replaces one while it's still a draft.
+#[derive(Deserialize)]
+pub struct PurchaseOrderPayload {
+    pub supplier_id: String,
+    /// Where the goods arrive; the default warehouse when omitted.
+    pub warehouse_id: Option<String>,
+    /// `YYYY-MM-DD`.
+    pub expected_at: Option<String>,
+    pub notes: Option<String>,
+    pub lines: Vec<PurchaseOrderLinePayload>,
+}
+
+#[derive(Deserialize)]
+pub struct ReceiveLinePayload {
+    pub product_id: String,
+    pub quantity: i32,
+}
+
+#[derive(Deserialize)]
+pub struct ReceivePayload {
+    pub lines: Vec<ReceiveLinePayload>,
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct BulkDeletePayload {
+    pub ids: Vec<String>,
 }
 
 #[derive(Serialize)]
 pub struct ProductResponse {
     pub id: String,
+    pub sku: Option<String>,
     pub name: String,
     pub description: String,
     pub price: f64,
@@ -63,6 +226,21 @@ pub struct SalesQuery {
     pub end_date: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    #[serde(default = "default_page")]
+    pub page: Option<i64>,
+    #[serde(default = "default_limit")]
+    pub limit: Option<i64>,
+    pub actor_id: Option<String>,
+    pub action: Option<String>,
+    pub entity_type: Option<String>,
+    pub entity_id: Option<String>,
+    /// Inclusive dates, `YYYY-MM-DD`.
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
 fn default_page() -> Option<i64> {
     Some(1)
 }
@@ -70,3 +248,23 @@ fn default_page() -> Option<i64> {
 fn default_limit() -> Option<i64> {
     Some(10)
 }
+
+#[derive(Deserialize)]
+pub struct ApiKeyPayload {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyResponse {
+    pub id: String,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_by_id: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+    pub revoked_at: Option<String>,
+    pub created_at: String,
+}
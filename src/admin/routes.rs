@@ -1,17 +1,117 @@
-use super::handler::{category::*, order::*, product::*, sales::*, user::*};
+use super::handler::{
+    api_key::*, audit::*, catalog::*, category::*, inventory::*, job::*, order::*, product::*,
+    purchase_order::*, sales::*, supplier::*, user::*, warehouse::*,
+};
 use actix_web::web;
 
 pub fn admin_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/users").route(web::get().to(get_users)));
     cfg.service(web::resource("/users/{user_id}").route(web::put().to(update_user_role)));
     cfg.service(web::resource("/users/{user_id}").route(web::delete().to(delete_user)));
+    cfg.service(web::resource("/users/{user_id}/unlock").route(web::post().to(unlock_user)));
+    cfg.service(
+        web::resource("/users/{user_id}/impersonate").route(web::post().to(impersonate_user)),
+    );
+    cfg.service(web::resource("/users/{user_id}/restore").route(web::post().to(restore_user)));
+    cfg.service(web::resource("/users/{user_id}/purge").route(web::delete().to(purge_user)));
     cfg.service(web::resource("/products").route(web::post().to(create_product)));
-    cfg.service(web::resource("/products/{product_id}").route(web::put().to(update_product)));
+    // Registered before `/products/{product_id}`, which would otherwise claim these paths.
+    cfg.service(
+        web::resource("/products/import")
+            .app_data(web::PayloadConfig::new(IMPORT_MAX_BYTES))
+            .route(web::post().to(import_products)),
+    );
+    cfg.service(web::resource("/products/export").route(web::get().to(export_products)));
+    cfg.service(web::resource("/products/bulk-delete").route(web::post().to(bulk_delete_products)));
+    cfg.service(
+        web::resource("/products/{product_id}")
+            .route(web::put().to(update_product))
+            .route(web::patch().to(patch_product))
+            .route(web::delete().to(delete_product)),
+    );
+    cfg.service(
+        web::resource("/products/{product_id}/restore").route(web::post().to(restore_product)),
+    );
+    cfg.service(
+        web::resource("/products/{product_id}/purge").route(web::delete().to(purge_product)),
+    );
+    cfg.service(
+        web::resource("/products/{product_id}/stock-movements")
+            .route(web::get().to(get_stock_movements))
+            .route(web::post().to(create_stock_movement)),
+    );
+    cfg.service(web::resource("/inventory/reconcile").route(web::get().to(reconcile_inventory)));
     cfg.service(web::resource("/categories").route(web::post().to(create_category)));
     cfg.service(web::resource("/categories/{category_id}").route(web::put().to(update_category)));
     cfg.service(
         web::resource("/categories/{category_id}").route(web::delete().to(delete_category)),
     );
+    cfg.service(
+        web::resource("/categories/{category_id}/restore").route(web::post().to(restore_category)),
+    );
+    cfg.service(
+        web::resource("/categories/{category_id}/purge").route(web::delete().to(purge_category)),
+    );
     cfg.service(web::resource("/orders/{order_id}").route(web::put().to(approve_order)));
     cfg.service(web::resource("/sales").route(web::get().to(sales_result)));
+    cfg.service(web::resource("/audit").route(web::get().to(get_audit_logs)));
+    cfg.service(web::resource("/audit/export").route(web::get().to(export_audit_logs)));
+    cfg.service(
+        web::resource("/api-keys")
+            .route(web::get().to(get_api_keys))
+            .route(web::post().to(create_api_key)),
+    );
+    cfg.service(web::resource("/api-keys/{key_id}").route(web::delete().to(revoke_api_key)));
+    cfg.service(web::resource("/jobs").route(web::get().to(get_jobs)));
+    cfg.service(web::resource("/jobs/{job_id}").route(web::get().to(get_job)));
+    cfg.service(web::resource("/jobs/{job_id}/retry").route(web::post().to(retry_job)));
+    cfg.service(web::resource("/jobs/{job_id}/cancel").route(web::post().to(cancel_job)));
+    // Registered before `/warehouses/{warehouse_id}`, which would otherwise claim this path.
+    cfg.service(web::resource("/warehouses/transfers").route(web::post().to(transfer_stock)));
+    cfg.service(
+        web::resource("/warehouses")
+            .route(web::get().to(get_warehouses))
+            .route(web::post().to(create_warehouse)),
+    );
+    cfg.service(
+        web::resource("/warehouses/{warehouse_id}")
+            .route(web::put().to(update_warehouse))
+            .route(web::delete().to(delete_warehouse)),
+    );
+    cfg.service(
+        web::resource("/warehouses/{warehouse_id}/stock").route(web::get().to(get_warehouse_stock)),
+    );
+    cfg.service(
+        web::resource("/warehouses/{warehouse_id}/stock/{product_id}")
+            .route(web::put().to(set_stock_level)),
+    );
+    cfg.service(
+        web::resource("/suppliers")
+            .route(web::get().to(get_suppliers))
+            .route(web::post().to(create_supplier)),
+    );
+    cfg.service(
+        web::resource("/suppliers/{supplier_id}")
+            .route(web::put().to(update_supplier))
+            .route(web::delete().to(delete_supplier)),
+    );
+    cfg.service(
+        web::resource("/purchase-orders")
+            .route(web::get().to(get_purchase_orders))
+            .route(web::post().to(create_purchase_order)),
+    );
+    cfg.service(
+        web::resource("/purchase-orders/{purchase_order_id}")
+            .route(web::get().to(get_purchase_order))
+            .route(web::put().to(update_purchase_order))
+            .route(web::delete().to(delete_purchase_order)),
+    );
+    cfg.service(
+        web::resource("/purchase-orders/{purchase_order_id}/send")
+            .route(web::post().to(send_purchase_order)),
+    );
+    cfg.service(
+        web::resource("/purchase-orders/{purchase_order_id}/receive")
+            .route(web::post().to(receive_purchase_order)),
+    );
 }
@@ -0,0 +1,297 @@
+use crate::auth::model::Claims;
+use crate::auth::token::client_ip;
+use crate::prisma::{
+    audit_log, category, order, product, purchase_order, supplier, user, warehouse, PrismaClient,
+};
+use actix_web::{HttpMessage, HttpRequest};
+use serde_json::{json, Map, Value};
+
+/// One entry in the audit log. Build it with `AuditEvent::new` and the setters, then `record` it.
+pub struct AuditEvent {
+    action: String,
+    entity_type: String,
+    actor_id: Option<String>,
+    entity_id: Option<String>,
+    ip_address: Option<String>,
+    metadata: Option<Value>,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+impl AuditEvent {
+    pub fn new(action: &str, entity_type: &str) -> Self {
+        AuditEvent {
+            action: action.to_string(),
+            entity_type: entity_type.to_string(),
+            actor_id: None,
+            entity_id: None,
+            ip_address: None,
+            metadata: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn actor(mut self, actor_id: Option<String>) -> Self {
+        self.actor_id = actor_id;
+        self
+    }
+
+    /// Takes the actor and IP from an authenticated request.
+    pub fn by(mut self, req: &HttpRequest) -> Self {
+        self.actor_id = actor_of(req);
+        self.ip_address = client_ip(req);
+        self
+    }
+
+    pub fn entity(mut self, entity_id: &str) -> Self {
+        self.entity_id = Some(entity_id.to_string());
+        self
+    }
+
+    pub fn ip(mut self, ip_address: Option<String>) -> Self {
+        self.ip_address = ip_address;
+        self
+    }
+
+    pub fn metadata(mut self, metadata: Value) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// State of the entity before the change. Leave unset for creations.
+    pub fn before(mut self, before: Value) -> Self {
+        self.before = Some(before);
+        self
+    }
+
+    /// State of the entity after the change. Leave unset for deletions.
+    pub fn after(mut self, after: Value) -> Self {
+        self.after = Some(after);
+        self
+    }
+
+    pub async fn record(
+        self,
+        prisma_client: &PrismaClient,
+    ) -> Result<audit_log::Data, prisma_client_rust::QueryError> {
+        let changes = match (&self.before, &self.after) {
+            (None, None) => None,
+            (before, after) => Some(diff(before.as_ref(), after.as_ref())),
+        };
+
+        prisma_client
+            .audit_log()
+            .create(
+                self.action,
+                self.entity_type,
+                vec![
+                    audit_log::actor_id::set(self.actor_id),
+                    audit_log::entity_id::set(self.entity_id),
+                    audit_log::ip_address::set(self.ip_address),
+                    audit_log::metadata::set(self.metadata),
+                    audit_log::before::set(self.before),
+                    audit_log::after::set(self.after),
+                    audit_log::changes::set(changes),
+                ],
+            )
+            .exec()
+            .await
+    }
+
+    /// Records the event without failing the caller. Used after a write has already been
+    /// committed, where an audit failure must not turn a success into an error response.
+    pub async fn record_or_log(self, prisma_client: &PrismaClient) {
+        let action = self.action.clone();
+        if let Err(err) = self.record(prisma_client).await {
+            println!("failed to record audit event {}: {}", action, err);
+        }
+    }
+}
+
+/// The actor recorded for an authenticated request. API keys are recorded as `api_key:<id>`
+/// so they can't be mistaken for users.
+pub fn actor_of(req: &HttpRequest) -> Option<String> {
+    req.extensions()
+        .get::<Claims>()
+        .map(|claims| match &claims.api_key_id {
+            Some(api_key_id) => format!("api_key:{}", api_key_id),
+            None => claims.sub.clone(),
+        })
+}
+
+/// Top-level fields whose values differ, as `{"field": {"from": .., "to": ..}}`.
+pub fn diff(before: Option<&Value>, after: Option<&Value>) -> Value {
+    let empty = Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+
+    let mut changes = Map::new();
+    for key in before.keys().chain(after.keys()) {
+        if changes.contains_key(key) {
+            continue;
+        }
+        let from = before.get(key).unwrap_or(&Value::Null);
+        let to = after.get(key).unwrap_or(&Value::Null);
+        if from != to {
+            changes.insert(key.clone(), json!({"from": from, "to": to}));
+        }
+    }
+    Value::Object(changes)
+}
+
+/// Audited view of a user. Secrets such as the password hash and TOTP seed are left out.
+pub fn user_snapshot(user_record: &user::Data) -> Value {
+    json!({
+        "id": user_record.id,
+        "displayName": user_record.display_name,
+        "firstName": user_record.first_name,
+        "lastName": user_record.last_name,
+        "email": user_record.email,
+        "emailVerified": user_record.email_verified,
+        "role": user_record.role,
+        "otpEnabled": user_record.otp_enabled,
+        "deletedAt": user_record.deleted_at,
+    })
+}
+
+pub fn product_snapshot(product_record: &product::Data) -> Value {
+    let mut snapshot = json!({
+        "id": product_record.id,
+        "sku": product_record.sku,
+        "name": product_record.name,
+        "description": product_record.description,
+        "price": product_record.price,
+        "stock": product_record.stock,
+        "lowStockThreshold": product_record.low_stock_threshold,
+        "imageUrl": product_record.image_url,
+        "deletedAt": product_record.deleted_at,
+    });
+    if let Some(categories) = &product_record.categories {
+        let mut category_ids = categories.iter().map(|c| c.id.clone()).collect::<Vec<_>>();
+        category_ids.sort();
+        snapshot["categories"] = json!(category_ids);
+    }
+    snapshot
+}
+
+pub fn category_snapshot(category_record: &category::Data) -> Value {
+    json!({
+        "id": category_record.id,
+        "name": category_record.name,
+        "description": category_record.description,
+        "deletedAt": category_record.deleted_at,
+    })
+}
+
+pub fn warehouse_snapshot(warehouse_record: &warehouse::Data) -> Value {
+    json!({
+        "id": warehouse_record.id,
+        "code": warehouse_record.code,
+        "name": warehouse_record.name,
+        "address": warehouse_record.address,
+        "isDefault": warehouse_record.is_default,
+        "active": warehouse_record.active,
+    })
+}
+
+pub fn supplier_snapshot(supplier_record: &supplier::Data) -> Value {
+    json!({
+        "id": supplier_record.id,
+        "name": supplier_record.name,
+        "email": supplier_record.email,
+        "phone": supplier_record.phone,
+        "address": supplier_record.address,
+        "notes": supplier_record.notes,
+    })
+}
+
+/// Includes the lines when they were fetched.
+pub fn purchase_order_snapshot(purchase_order_record: &purchase_order::Data) -> Value {
+    let mut snapshot = json!({
+        "id": purchase_order_record.id,
+        "supplierId": purchase_order_record.supplier_id,
+        "warehouseId": purchase_order_record.warehouse_id,
+        "status": purchase_order_record.status,
+        "expectedAt": purchase_order_record.expected_at,
+        "notes": purchase_order_record.notes,
+    });
+    if let Some(lines) = &purchase_order_record.lines {
+        snapshot["lines"] = lines
+            .iter()
+            .map(|line| {
+                json!({
+                    "productId": line.product_id,
+                    "quantity": line.quantity,
+                    "receivedQuantity": line.received_quantity,
+                    "unitCost": line.unit_cost,
+                })
+            })
+            .collect();
+    }
+    snapshot
+}
+
+pub fn order_snapshot(order_record: &order::Data) -> Value {
+    json!({
+        "id": order_record.id,
+        "userId": order_record.user_id,
+        "status": order_record.status,
+        "payedPrice": order_record.payed_price,
+        "paymentMethod": order_record.payment_method,
+        "warehouseId": order_record.warehouse_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_only_fields_that_changed() {
+        let before = json!({"name": "Mug", "price": 9.5, "stock": 3});
+        let after = json!({"name": "Mug", "price": 12.0, "stock": 3});
+        assert_eq!(
+            diff(Some(&before), Some(&after)),
+            json!({"price": {"from": 9.5, "to": 12.0}})
+        );
+    }
+
+    #[test]
+    fn treats_missing_fields_as_null() {
+        let before = json!({"name": "Mug", "sku": "MUG-1"});
+        let after = json!({"name": "Mug", "deletedAt": "2026-10-19T00:00:00Z"});
+        assert_eq!(
+            diff(Some(&before), Some(&after)),
+            json!({
+                "sku": {"from": "MUG-1", "to": null},
+                "deletedAt": {"from": null, "to": "2026-10-19T00:00:00Z"},
+            })
+        );
+    }
+
+    #[test]
+    fn creation_and_deletion_diff_against_nothing() {
+        let record = json!({"name": "Mug"});
+        assert_eq!(
+            diff(None, Some(&record)),
+            json!({"name": {"from": null, "to": "Mug"}})
+        );
+        assert_eq!(
+            diff(Some(&record), None),
+            json!({"name": {"from": "Mug", "to": null}})
+        );
+    }
+
+    #[test]
+    fn compares_nested_values_as_a_whole() {
+        let before = json!({"categories": ["a", "b"]});
+        let after = json!({"categories": ["b", "a"]});
+        assert_eq!(
+            diff(Some(&before), Some(&after)),
+            json!({"categories": {"from": ["a", "b"], "to": ["b", "a"]}})
+        );
+        assert_eq!(diff(Some(&before), Some(&before)), json!({}));
+        assert_eq!(diff(Some(&json!("not an object")), None), json!({}));
+    }
+}
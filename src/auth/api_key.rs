@@ -0,0 +1,213 @@
+use crate::auth::model::Claims;
+use crate::auth::token::hash_token;
+use crate::prisma::{api_key, PrismaClient};
+use actix_web::http::Method;
+use chrono::{Duration, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+/// `last_used_at` is only refreshed when older than this, to avoid a write per request.
+const LAST_USED_RESOLUTION_SECONDS: i64 = 60;
+
+pub enum ApiKeyRejection {
+    /// Unknown, malformed, revoked or expired.
+    Invalid,
+    MissingScope(String),
+}
+
+/// Keys look like `sk_<prefix>_<secret>`. The prefix is stored in clear to find the key and to
+/// tell keys apart in listings; only a hash of the whole key is stored.
+pub fn generate() -> (String, String) {
+    let prefix: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(|c| (c as char).to_ascii_lowercase())
+        .collect();
+    let secret = crate::auth::token::generate_secret().to_ascii_lowercase();
+    let key = format!("sk_{}_{}", prefix, secret);
+    (prefix, key)
+}
+
+fn prefix_of(key: &str) -> Option<&str> {
+    let mut parts = key.splitn(3, '_');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("sk"), Some(prefix), Some(secret)) if !prefix.is_empty() && !secret.is_empty() => {
+            Some(prefix)
+        }
+        _ => None,
+    }
+}
+
+/// Scopes are `<resource>:read`, `<resource>:write` or `*` for everything.
+pub fn is_valid_scope(scope: &str) -> bool {
+    if scope == "*" {
+        return true;
+    }
+    match scope.split_once(':') {
+        Some((resource, access)) => {
+            !resource.is_empty()
+                && resource
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c == '-' || c == '_')
+                && (access == "read" || access == "write")
+        }
+        None => false,
+    }
+}
+
+/// The scope an admin request needs: the first path segment after `/api/admin/`, with `read`
+/// for safe methods and `write` for everything else. Keys are only accepted under `api/admin`.
+pub fn required_scope(path: &str, method: &Method) -> Option<String> {
+    let resource = path
+        .trim_start_matches('/')
+        .strip_prefix("api/admin/")?
+        .split('/')
+        .next()
+        .filter(|resource| !resource.is_empty())?;
+    let access = if method == Method::GET || method == Method::HEAD {
+        "read"
+    } else {
+        "write"
+    };
+    Some(format!("{}:{}", resource, access))
+}
+
+fn grants(scopes: &[String], required: &str) -> bool {
+    let resource = required.split(':').next().unwrap_or("");
+    scopes.iter().any(|scope| {
+        scope == "*"
+            || scope == required
+            // Write access to a resource includes reading it.
+            || (required.ends_with(":read") && *scope == format!("{}:write", resource))
+    })
+}
+
+/// Checks the key and returns claims that let it act as an admin within its scopes.
+pub async fn authenticate(
+    prisma_client: &PrismaClient,
+    key: &str,
+    required: &str,
+) -> Result<Claims, ApiKeyRejection> {
+    let prefix = prefix_of(key).ok_or(ApiKeyRejection::Invalid)?;
+    let key_record = prisma_client
+        .api_key()
+        .find_unique(api_key::prefix::equals(prefix.to_string()))
+        .exec()
+        .await
+        .ok()
+        .flatten()
+        .ok_or(ApiKeyRejection::Invalid)?;
+
+    let now = Utc::now().fixed_offset();
+    if key_record.key_hash != hash_token(key)
+        || key_record.revoked_at.is_some()
+        || key_record
+            .expires_at
+            .map_or(false, |expires_at| expires_at < now)
+    {
+        return Err(ApiKeyRejection::Invalid);
+    }
+    if !grants(&key_record.scopes, required) {
+        return Err(ApiKeyRejection::MissingScope(required.to_string()));
+    }
+
+    let stale = key_record.last_used_at.map_or(true, |last_used_at| {
+        now - last_used_at > Duration::seconds(LAST_USED_RESOLUTION_SECONDS)
+    });
+    if stale {
+        prisma_client
+            .api_key()
+            .update(
+                api_key::id::equals(key_record.id.clone()),
+                vec![api_key::last_used_at::set(Some(now))],
+            )
+            .exec()
+            .await
+            .ok();
+    }
+
+    Ok(Claims {
+        sub: key_record.id.clone(),
+        exp: key_record
+            .expires_at
+            .map_or(usize::MAX, |expires_at| expires_at.timestamp() as usize),
+        is_admin: true,
+        sid: None,
+        ver: 0,
+        two_factor_pending: false,
+        api_key_id: Some(key_record.id),
+        impersonator: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(scopes: &[&str]) -> Vec<String> {
+        scopes.iter().map(|scope| scope.to_string()).collect()
+    }
+
+    #[test]
+    fn scope_is_the_first_segment_under_api_admin() {
+        assert_eq!(
+            required_scope("/api/admin/products/42/images", &Method::GET),
+            Some("products:read".to_string())
+        );
+        assert_eq!(
+            required_scope("api/admin/users", &Method::HEAD),
+            Some("users:read".to_string())
+        );
+        assert_eq!(
+            required_scope("/api/admin/orders/7/approve", &Method::PUT),
+            Some("orders:write".to_string())
+        );
+        assert_eq!(
+            required_scope("/api/admin/products", &Method::DELETE),
+            Some("products:write".to_string())
+        );
+    }
+
+    #[test]
+    fn no_scope_outside_api_admin() {
+        assert_eq!(required_scope("/api/auth/me", &Method::GET), None);
+        assert_eq!(required_scope("/api/admin", &Method::GET), None);
+        assert_eq!(required_scope("/api/admin/", &Method::GET), None);
+        assert_eq!(required_scope("/api/administrators/x", &Method::GET), None);
+    }
+
+    #[test]
+    fn grants_the_exact_scope_or_the_wildcard() {
+        assert!(grants(&scopes(&["products:read"]), "products:read"));
+        assert!(grants(&scopes(&["*"]), "users:write"));
+        assert!(!grants(&scopes(&["products:read"]), "orders:read"));
+        assert!(!grants(&[], "products:read"));
+    }
+
+    #[test]
+    fn write_includes_read_but_not_the_other_way_round() {
+        assert!(grants(&scopes(&["products:write"]), "products:read"));
+        assert!(!grants(&scopes(&["products:read"]), "products:write"));
+        assert!(!grants(&scopes(&["orders:write"]), "products:read"));
+    }
+
+    #[test]
+    fn validates_scope_syntax() {
+        assert!(is_valid_scope("purchase-orders:write"));
+        assert!(is_valid_scope("*"));
+        assert!(!is_valid_scope("products"));
+        assert!(!is_valid_scope("products:delete"));
+        assert!(!is_valid_scope(":read"));
+        assert!(!is_valid_scope("Products:read"));
+    }
+
+    #[test]
+    fn finds_the_prefix_of_well_formed_keys_only() {
+        assert_eq!(prefix_of("sk_abcd1234_secret"), Some("abcd1234"));
+        assert_eq!(prefix_of("sk__secret"), None);
+        assert_eq!(prefix_of("pk_abcd1234_secret"), None);
+        assert_eq!(prefix_of("sk_abcd1234"), None);
+    }
+}
@@ -1,20 +1,23 @@
 use crate::auth::model::{
-    Claims, GetRecoveryKeyPayload, LoginUser, Passwords, RegisterUser, ResetPasswordPayload,
-    UpdateProfile, UserResponse,
+    Claims, GetRecoveryKeyPayload, IdentityResponse, LoginUser, OidcCallbackQuery, Passwords,
+    RefreshTokenPayload, RegisterUser, ResetPasswordPayload, SessionResponse, UpdateProfile,
+    UserResponse,
 };
+use crate::auth::password::{self, PasswordPolicy, PasswordViolation};
+use crate::auth::{keys, lockout, oidc, token, two_factor};
+use crate::notification::{outbox, templates::Template};
+use crate::utils::{bearer_token, decode_token, reject_api_key, reject_impersonation};
 use crate::prisma::*;
 use crate::prisma::{self, PrismaClient};
-use crate::utils::get_secret_key;
 use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use base32;
-use bcrypt::{hash, verify, DEFAULT_COST};
-use jsonwebtoken::{encode, EncodingKey, Header};
+use chrono::Utc;
 use rand::Rng;
 use serde_json::json;
 use std::sync::Arc;
 use totp_rs::{Algorithm, Secret, TOTP};
 
-use super::model::VerifyOTPSchema;
+use super::model::{ValidateOTPSchema, VerifyEmailQuery, VerifyOTPSchema};
 
 pub async fn register_user(
     user: web::Json<RegisterUser>,
@@ -30,9 +33,15 @@ pub async fn register_user(
             return HttpResponse::Conflict().json(json!({"error": "User already exists"}));
         }
         Ok(None) => {
-            let hashed_password = match hash(user.password.clone(), DEFAULT_COST) {
-                Ok(p) => p,
-                Err(_) => {
+            if let Err(violations) = PasswordPolicy::from_env()
+                .validate(&user.password, &[user.email.as_str(), user.username.as_str()])
+            {
+                return password_policy_error(violations);
+            }
+
+            let hashed_password = match password::hash_password_in_pool(&user.password).await {
+                Some(p) => p,
+                None => {
                     return HttpResponse::InternalServerError()
                         .json(json!({"error": "Failed to hash password"}))
                 }
@@ -58,10 +67,23 @@ pub async fn register_user(
                 .await
             {
                 Ok(new_user) => {
+                    if let Ok(verification_token) =
+                        token::issue_email_verification_token(&new_user.id, &new_user.email)
+                    {
+                        let template = Template::Welcome {
+                            name: new_user.first_name.clone(),
+                            verification_token,
+                        };
+                        outbox::enqueue(&prisma_client, &new_user.email, template)
+                            .await
+                            .ok();
+                    }
+
                     return HttpResponse::Created().json(UserResponse {
                         id: new_user.id,
                         username: new_user.display_name,
                         email: new_user.email,
+                        email_verified: new_user.email_verified,
                         first_name: new_user.first_name,
                         last_name: new_user.last_name,
                         role: match new_user.role {
@@ -87,79 +109,177 @@ pub async fn register_user(
     }
 }
 
+fn password_policy_error(violations: Vec<PasswordViolation>) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "error": "Password does not meet requirements",
+        "violations": violations,
+    }))
+}
+
+/// Opens a session for a fully authenticated user and returns the login response.
+async fn complete_login(
+    req: &HttpRequest,
+    prisma_client: &PrismaClient,
+    user_record: user::Data,
+) -> HttpResponse {
+    let (session_record, refresh_token) =
+        match token::start_session(prisma_client, &user_record, req).await {
+            Ok(s) => s,
+            Err(_) => {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Failed to create session"}))
+            }
+        };
+
+    let access_token = match token::issue_access_token(&user_record, &session_record.id) {
+        Ok(t) => t,
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(json!({"error": "Failed to generate token"}))
+        }
+    };
+
+    HttpResponse::Ok().json(json!({
+        "token": access_token,
+        "refresh_token": refresh_token,
+        "expires_in": token::access_token_ttl(),
+        "user": UserResponse {
+            id: user_record.id,
+            username: user_record.display_name,
+            email: user_record.email,
+            email_verified: user_record.email_verified,
+            first_name: user_record.first_name,
+            last_name: user_record.last_name,
+            role: match user_record.role {
+                RoleType::Admin => String::from("admin"),
+                _ => String::from("client"),
+            },
+            otp_enabled: user_record.otp_enabled,
+            otp_verified: user_record.otp_verified,
+            otp_auth_url: user_record.otp_auth_url.to_owned(),
+            otp_base32: user_record.opt_base_32.to_owned(),
+        }
+    }))
+}
+
+/// Called once the first factor (password or external identity) checks out: asks for the
+/// second factor when 2FA is on and the device isn't trusted, otherwise completes the login.
+async fn finish_first_factor(
+    req: &HttpRequest,
+    prisma_client: &PrismaClient,
+    user_record: user::Data,
+) -> HttpResponse {
+    if user_record.otp_enabled
+        && !two_factor::is_trusted_device(prisma_client, &user_record.id, req).await
+    {
+        return match token::issue_two_factor_token(&user_record) {
+            Ok(two_factor_token) => HttpResponse::Ok().json(json!({
+                "otp_required": true,
+                "two_factor_token": two_factor_token,
+                "expires_in": token::TWO_FACTOR_TOKEN_TTL,
+            })),
+            Err(_) => HttpResponse::InternalServerError()
+                .json(json!({"error": "Failed to generate token"})),
+        };
+    }
+
+    complete_login(req, prisma_client, user_record).await
+}
+
+fn too_many_attempts(retry_after: i64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .json(json!({
+            "error": "Too many failed attempts, try again later",
+            "retry_after": retry_after,
+        }))
+}
+
 pub async fn login_user(
+    req: HttpRequest,
     user: web::Json<LoginUser>,
     prisma_client: web::Data<Arc<PrismaClient>>,
 ) -> impl Responder {
+    let ip_address = token::client_ip(&req);
+    match lockout::ip_blocked_for(&prisma_client, ip_address.as_deref()).await {
+        Ok(Some(retry_after)) => return too_many_attempts(retry_after),
+        Ok(None) => {}
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+        }
+    }
+
     match prisma_client
         .user()
         .find_unique(user::email::equals(user.email.clone()))
         .exec()
         .await
     {
-        Ok(Some(user_record)) => {
-            println!(
-                "user record is {} and password is {}",
-                user_record.password, user.password
-            );
-            match verify(user.password.clone(), &user_record.password) {
-                Ok(valid) => {
-                    if valid {
-                        let exp = chrono::Utc::now()
-                            .checked_add_signed(chrono::Duration::days(1))
-                            .expect("valid timestamp")
-                            .timestamp() as usize;
-
-                        let claims = Claims {
-                            sub: user_record.id.clone(),
-                            exp,
-                            is_admin: user_record.role == RoleType::Admin,
-                        };
-
-                        let secret = get_secret_key();
-                        let token = match encode(
-                            &Header::default(),
-                            &claims,
-                            &EncodingKey::from_secret(secret.as_bytes()),
-                        ) {
-                            Ok(t) => t,
-                            Err(_) => {
-                                return HttpResponse::InternalServerError()
-                                    .json(json!({"error": "Failed to generate token"}))
-                            }
-                        };
+        // Deleted accounts look exactly like unknown ones.
+        Ok(Some(user_record)) if user_record.deleted_at.is_none() => {
+            // A locked account is rejected before the password is checked, so guessing
+            // during the lockout neither succeeds nor reveals whether a guess was right.
+            if let Some(retry_after) = lockout::account_locked_for(&user_record) {
+                return too_many_attempts(retry_after);
+            }
 
-                        return HttpResponse::Ok().json(json!({
-                            "token": token,
-                            "user": UserResponse {
-                                id: user_record.id,
-                                username: user_record.display_name,
-                                email: user_record.email,
-                                first_name: user_record.first_name,
-                                last_name: user_record.last_name,
-                                role: match user_record.role {
-                                    RoleType::Admin => String::from("admin"),
-                                    _ => String::from("client"),
-                                },
-                                otp_enabled: user_record.otp_enabled,
-                                otp_verified: user_record.otp_verified,
-                                otp_auth_url: user_record.otp_auth_url.to_owned(),
-                                otp_base32: user_record.opt_base_32.to_owned(),
-
-                            }
-                        }));
-                    } else {
-                        return HttpResponse::Unauthorized()
-                            .json(json!({"error": "Invalid credentials"}));
-                    }
-                }
-                Err(_) => {
+            if !password::verify_password_in_pool(&user.password, &user_record.password).await {
+                if lockout::record_failure(
+                    &prisma_client,
+                    &user.email,
+                    Some(&user_record),
+                    ip_address,
+                    "password",
+                )
+                .await
+                .is_err()
+                {
                     return HttpResponse::InternalServerError()
-                        .json(json!({"error": "invalid email or password"}));
+                        .json(json!({"error": "Database error"}));
+                }
+                return HttpResponse::Unauthorized().json(json!({"error": "Invalid credentials"}));
+            }
+
+            if lockout::record_success(&prisma_client, &user_record, ip_address, "password")
+                .await
+                .is_err()
+            {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Database error"}));
+            }
+
+            // Legacy bcrypt hashes and hashes made with outdated Argon2 costs are upgraded
+            // now, while we have the plaintext. Failing to do so must not block the login.
+            let user_record = if password::needs_rehash(&user_record.password) {
+                match password::hash_password_in_pool(&user.password).await {
+                    Some(hashed_password) => prisma_client
+                        .user()
+                        .update(
+                            user::id::equals(user_record.id.clone()),
+                            vec![user::password::set(hashed_password)],
+                        )
+                        .exec()
+                        .await
+                        .unwrap_or(user_record),
+                    None => user_record,
                 }
+            } else {
+                user_record
+            };
+
+            finish_first_factor(&req, &prisma_client, user_record).await
+        }
+        Ok(_) => {
+            // Unknown emails still count against the IP.
+            if lockout::record_failure(&prisma_client, &user.email, None, ip_address, "password")
+                .await
+                .is_err()
+            {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Database error"}));
             }
+            HttpResponse::Unauthorized().json(json!({"error": "Invalid credentials"}))
         }
-        Ok(None) => HttpResponse::Unauthorized().json(json!({"error": "Invalid credentials"})),
         Err(err) => HttpResponse::InternalServerError()
             .json(json!({"error": format!("Failed to query user: {}", err)})),
     }
@@ -171,22 +291,70 @@ pub async fn change_pass(
     prisma_client: web::Data<Arc<PrismaClient>>,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        if let Some(response) = reject_impersonation(claims) {
+            return response;
+        }
+        let user_record = match prisma_client
+            .user()
+            .find_unique(user::id::equals(claims.sub.clone()))
+            .exec()
+            .await
+        {
+            Ok(Some(user_record)) => user_record,
+            Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Didn't find user"})),
+            Err(_) => {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Database error"}))
+            }
+        };
+
+        if !password::verify_password_in_pool(&passwords.oldpassword, &user_record.password).await {
+            return HttpResponse::Unauthorized()
+                .json(json!({"error": "Current password is incorrect"}));
+        }
+        if passwords.oldpassword == passwords.newpassword {
+            return HttpResponse::BadRequest()
+                .json(json!({"error": "New password must differ from the current one"}));
+        }
+        if let Err(violations) = PasswordPolicy::from_env().validate(
+            &passwords.newpassword,
+            &[user_record.email.as_str(), user_record.display_name.as_str()],
+        ) {
+            return password_policy_error(violations);
+        }
+
+        let hashed_password = match password::hash_password_in_pool(&passwords.newpassword).await {
+            Some(p) => p,
+            None => {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Failed to hash password"}))
+            }
+        };
+
         match prisma_client
             .user()
             .update(
                 user::id::equals(claims.sub.clone()),
-                vec![user::password::set(passwords.newpassword.clone())],
+                vec![user::password::set(hashed_password)],
             )
             .exec()
             .await
         {
-            Ok(_) => return HttpResponse::Ok().json("password is updated successfully"),
-            Err(_) => return HttpResponse::NotFound().json("Didn't find user"),
+            Ok(_) => {
+                if token::revoke_user_tokens(&prisma_client, &claims.sub).await.is_err() {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Failed to revoke existing sessions"}));
+                }
+                HttpResponse::Ok().json("password is updated successfully")
+            }
+            Err(_) => HttpResponse::NotFound().json(json!({"error": "Didn't find user"})),
         }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
     }
-    return HttpResponse::Ok().json(
-        json!({"oldpassword": passwords.oldpassword, "newPassword": passwords.newpassword }),
-    );
 }
 
 pub async fn update_profile(
@@ -195,31 +363,45 @@ pub async fn update_profile(
     prisma_client: web::Data<Arc<PrismaClient>>,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        if let Some(response) = reject_impersonation(claims) {
+            return response;
+        }
         match prisma_client
             .user()
             .find_unique(user::id::equals(claims.sub.clone()))
             .exec()
             .await
         {
-            Ok(Some(_)) => {
+            Ok(Some(current_user)) => {
+                let email_changed = current_user.email != newprofile.email;
+                let mut changes = vec![
+                    user::display_name::set(newprofile.username.clone()),
+                    user::first_name::set(newprofile.firstname.clone()),
+                    user::last_name::set(newprofile.lastname.clone()),
+                    user::email::set(newprofile.email.clone()),
+                ];
+                if email_changed {
+                    changes.push(user::email_verified::set(false));
+                }
+
                 match prisma_client
                     .user()
-                    .update(
-                        user::id::equals(claims.sub.clone()),
-                        vec![
-                            user::display_name::set(newprofile.username.clone()),
-                            user::first_name::set(newprofile.firstname.clone()),
-                            user::last_name::set(newprofile.lastname.clone()),
-                            user::email::set(newprofile.email.clone()),
-                        ],
-                    )
+                    .update(user::id::equals(claims.sub.clone()), changes)
                     .exec()
                     .await
                 {
-                    Ok(updated_user) => HttpResponse::Ok().json(UserResponse {
+                    Ok(updated_user) => {
+                        if email_changed {
+                            send_verification_email(&prisma_client, &updated_user).await;
+                        }
+                        HttpResponse::Ok().json(UserResponse {
                         id: updated_user.id,
                         username: updated_user.display_name,
                         email: updated_user.email,
+                        email_verified: updated_user.email_verified,
                         first_name: updated_user.first_name,
                         last_name: updated_user.last_name,
                         role: match updated_user.role {
@@ -230,7 +412,8 @@ pub async fn update_profile(
                         otp_verified: updated_user.otp_verified,
                         otp_auth_url: updated_user.otp_auth_url.to_owned(),
                         otp_base32: updated_user.opt_base_32.to_owned(),
-                    }),
+                        })
+                    }
                     Err(_) => {
                         HttpResponse::BadRequest().json(json!({"error": "Invalid input data"}))
                     }
@@ -245,77 +428,192 @@ pub async fn update_profile(
 }
 
 pub async fn recovery_key(
-    req: HttpRequest,
     payload: web::Json<GetRecoveryKeyPayload>,
     prisma_client: web::Data<Arc<PrismaClient>>,
 ) -> impl Responder {
-    let recoverykey = "123456".to_string();
-    if let Some(claims) = req.extensions().get::<Claims>() {
-        match prisma_client
-            .user()
-            .find_unique(user::id::equals(claims.sub.clone()))
-            .exec()
-            .await
-        {
-            Ok(Some(_)) => {
-                match prisma_client
-                    .user()
-                    .update(
-                        user::email::equals(payload.email.clone()),
-                        vec![user::key::set(Some(recoverykey))],
-                    )
-                    .exec()
-                    .await
-                {
-                    Ok(updated_user) => {
-                        return HttpResponse::Ok().json(json!({"recoveryKey": updated_user.key}))
-                    }
-                    Err(_) => HttpResponse::BadRequest().json(json!({"error": "Invalid email"})),
-                }
-            }
-            Ok(None) => HttpResponse::BadRequest().json(json!({"error": "Invalid input data"})),
-            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+    // Same answer whether or not the email exists, so this can't be used to probe for accounts.
+    let accepted = HttpResponse::Ok().json(
+        json!({"message": "If an account exists for this email, a reset link has been sent"}),
+    );
+
+    let user_record = match prisma_client
+        .user()
+        .find_unique(user::email::equals(payload.email.clone()))
+        .exec()
+        .await
+    {
+        Ok(Some(user_record)) if user_record.deleted_at.is_none() => user_record,
+        Ok(_) => return accepted,
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
         }
-    } else {
-        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    };
+
+    let window_start = (Utc::now() - chrono::Duration::hours(1)).fixed_offset();
+    match prisma_client
+        .password_reset_token()
+        .count(vec![
+            password_reset_token::user_id::equals(user_record.id.clone()),
+            password_reset_token::created_at::gte(window_start),
+        ])
+        .exec()
+        .await
+    {
+        // From here on failures are only logged: answering differently for known emails would
+        // tell callers which accounts exist.
+        Ok(count) if count >= token::password_reset_max_per_hour() => {
+            println!("password reset limit reached for user {}", user_record.id);
+            return accepted;
+        }
+        Ok(_) => {}
+        Err(err) => {
+            println!("failed to count password resets: {:?}", err);
+            return accepted;
+        }
+    }
+
+    let reset_token = token::generate_secret();
+    let expires_at =
+        (Utc::now() + chrono::Duration::minutes(token::password_reset_ttl_minutes())).fixed_offset();
+    if let Err(err) = prisma_client
+        .password_reset_token()
+        .create(
+            user::id::equals(user_record.id.clone()),
+            token::hash_token(&reset_token),
+            expires_at,
+            vec![],
+        )
+        .exec()
+        .await
+    {
+        println!("failed to create password reset token: {:?}", err);
+        return accepted;
+    }
+
+    let template = Template::PasswordReset {
+        name: user_record.first_name.clone(),
+        token: reset_token,
+        expires_in_minutes: token::password_reset_ttl_minutes(),
+    };
+    if let Err(err) = outbox::enqueue(&prisma_client, &user_record.email, template).await {
+        println!("failed to queue password reset email: {:?}", err);
     }
+    accepted
 }
 
 pub async fn reset_password(
     payload: web::Json<ResetPasswordPayload>,
     prisma_client: web::Data<Arc<PrismaClient>>,
 ) -> impl Responder {
-    match prisma_client
+    let token_hash = token::hash_token(&payload.token);
+    let reset_token = match prisma_client
+        .password_reset_token()
+        .find_unique(password_reset_token::token_hash::equals(token_hash.clone()))
+        .exec()
+        .await
+    {
+        Ok(Some(reset_token))
+            if reset_token.used_at.is_none() && reset_token.expires_at > Utc::now() =>
+        {
+            reset_token
+        }
+        Ok(_) => {
+            return HttpResponse::BadRequest()
+                .json(json!({"error": "Reset token is invalid or has expired"}))
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+        }
+    };
+
+    let user_record = match prisma_client
         .user()
-        .find_first(vec![
-            user::key::equals(Some(payload.recoverykey.clone())),
-            user::email::equals(payload.email.clone()),
-        ])
+        .find_unique(user::id::equals(reset_token.user_id.clone()))
         .exec()
         .await
     {
-        Ok(Some(_)) => {
-            let hashed_password = match hash(payload.newpassword.clone(), DEFAULT_COST) {
-                Ok(p) => p,
-                Err(_) => {
-                    return HttpResponse::InternalServerError()
-                        .json(json!({"error": "Failed to hash password"}))
-                }
-            };
-            match prisma_client
-                .user()
-                .update(
-                    user::email::equals(payload.email.clone()),
-                    vec![user::password::set(hashed_password.clone())],
-                )
-                .exec()
-                .await
-            {
-                Ok(_) => HttpResponse::Ok().json(json!({"message": "Password reset successfully"})),
-                Err(_) => HttpResponse::BadRequest().json(json!({"error": "Invalid input data"})),
-            }
+        Ok(Some(user_record)) => user_record,
+        Ok(None) => {
+            return HttpResponse::BadRequest()
+                .json(json!({"error": "Reset token is invalid or has expired"}))
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+        }
+    };
+
+    if let Err(violations) = PasswordPolicy::from_env().validate(
+        &payload.newpassword,
+        &[user_record.email.as_str(), user_record.display_name.as_str()],
+    ) {
+        return password_policy_error(violations);
+    }
+
+    let hashed_password = match password::hash_password_in_pool(&payload.newpassword).await {
+        Some(p) => p,
+        None => {
+            return HttpResponse::InternalServerError()
+                .json(json!({"error": "Failed to hash password"}))
         }
-        Ok(None) => HttpResponse::BadRequest().json(json!({"error": "Invalid input data"})),
+    };
+
+    // The token is burnt before the password is written, and only while it is still unused, so
+    // two requests racing with the same token can't both reset the password.
+    let user_id = reset_token.user_id.clone();
+    let result: Result<bool, prisma_client_rust::QueryError> = prisma_client
+        ._transaction()
+        .run(|client| {
+            Box::pin(async move {
+                let now = Utc::now().fixed_offset();
+                let burnt = client
+                    .password_reset_token()
+                    .update_many(
+                        vec![
+                            password_reset_token::token_hash::equals(token_hash),
+                            password_reset_token::used_at::equals(None),
+                            password_reset_token::expires_at::gt(now),
+                        ],
+                        vec![password_reset_token::used_at::set(Some(now))],
+                    )
+                    .exec()
+                    .await?;
+                if burnt != 1 {
+                    return Ok(false);
+                }
+
+                // Any other outstanding tokens for the user go with it.
+                client
+                    .password_reset_token()
+                    .update_many(
+                        vec![
+                            password_reset_token::user_id::equals(user_id.clone()),
+                            password_reset_token::used_at::equals(None),
+                        ],
+                        vec![password_reset_token::used_at::set(Some(now))],
+                    )
+                    .exec()
+                    .await?;
+                client
+                    .user()
+                    .update(
+                        user::id::equals(user_id),
+                        vec![user::password::set(hashed_password)],
+                    )
+                    .exec()
+                    .await?;
+                Ok(true)
+            })
+        })
+        .await;
+
+    match result {
+        Ok(true) => match token::revoke_user_tokens(&prisma_client, &reset_token.user_id).await {
+            Ok(_) => HttpResponse::Ok().json(json!({"message": "Password reset successfully"})),
+            Err(_) => HttpResponse::InternalServerError()
+                .json(json!({"error": "Failed to revoke existing sessions"})),
+        },
+        Ok(false) => HttpResponse::BadRequest()
+            .json(json!({"error": "Reset token is invalid or has expired"})),
         Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
     }
 }
@@ -325,6 +623,12 @@ pub async fn generate_otp(
     prisma_client: web::Data<Arc<PrismaClient>>,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        if let Some(response) = reject_impersonation(claims) {
+            return response;
+        }
         let mut rng = rand::thread_rng();
         let data_byte: [u8; 21] = rng.gen();
         let base32_string =
@@ -378,6 +682,12 @@ pub async fn verify_otp(
     prisma_client: web::Data<Arc<PrismaClient>>,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        if let Some(response) = reject_impersonation(claims) {
+            return response;
+        }
         let user = prisma_client
             .user()
             .find_unique(user::id::equals(claims.sub.clone()))
@@ -417,7 +727,23 @@ pub async fn verify_otp(
             .exec()
             .await
         {
-            Ok(_) => HttpResponse::Ok().json(json!({"otp_verified": true, "user": user})),
+            Ok(_) => match two_factor::regenerate_backup_codes(&prisma_client, &claims.sub).await {
+                Ok(backup_codes) => {
+                    let template = Template::TwoFactorEnabled {
+                        name: user.first_name.clone(),
+                    };
+                    outbox::enqueue(&prisma_client, &user.email, template)
+                        .await
+                        .ok();
+
+                    HttpResponse::Ok().json(
+                        json!({"otp_verified": true, "user": user, "backup_codes": backup_codes}),
+                    )
+                }
+                Err(_) => {
+                    HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+                }
+            },
             Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
         }
     } else {
@@ -426,50 +752,142 @@ pub async fn verify_otp(
 }
 
 pub async fn validate_otp(
+    req: HttpRequest,
+    body: web::Json<ValidateOTPSchema>,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    // This route sits outside `Authentication`: the caller holds the 2FA-pending token from
+    // `login_user`, which the middleware deliberately rejects.
+    let claims = match bearer_token(&req).and_then(|t| decode_token(t).ok()) {
+        Some(claims) if claims.two_factor_pending => claims,
+        _ => return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"})),
+    };
+
+    let user = match prisma_client
+        .user()
+        .find_unique(user::id::equals(claims.sub.clone()))
+        .exec()
+        .await
+    {
+        Ok(Some(user)) if user.token_version == claims.ver => user,
+        Ok(_) => return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"})),
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+        }
+    };
+
+    if !user.otp_enabled {
+        let json_error = json! ({
+            "status": "fail".to_string(),
+            "message": "2FA not enabled".to_string(),
+        });
+
+        return HttpResponse::Forbidden().json(json_error);
+    }
+
+    let ip_address = token::client_ip(&req);
+    match lockout::ip_blocked_for(&prisma_client, ip_address.as_deref()).await {
+        Ok(Some(retry_after)) => return too_many_attempts(retry_after),
+        Ok(None) => {}
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+        }
+    }
+    if let Some(retry_after) = lockout::account_locked_for(&user) {
+        return too_many_attempts(retry_after);
+    }
+
+    let is_valid = match (&body.token, &body.backup_code) {
+        (Some(code), _) => two_factor::check_totp(&user, code),
+        (None, Some(backup_code)) => {
+            match two_factor::consume_backup_code(&prisma_client, &user.id, backup_code).await {
+                Ok(used) => used,
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error"}))
+                }
+            }
+        }
+        (None, None) => false,
+    };
+
+    let recorded = if is_valid {
+        lockout::record_success(&prisma_client, &user, ip_address, "otp").await
+    } else {
+        lockout::record_failure(&prisma_client, &user.email, Some(&user), ip_address, "otp").await
+    };
+    if recorded.is_err() {
+        return HttpResponse::InternalServerError().json(json!({"error": "Database error"}));
+    }
+
+    if !is_valid {
+        let json_error = json! ({
+            "status": "fail".to_string(),
+            "message": "Token is invalid or user doesn't exist".to_string(),
+        });
+
+        return HttpResponse::Forbidden().json(json_error);
+    }
+
+    let trusted_device_cookie = if body.remember_device {
+        match two_factor::trust_device(&prisma_client, &user.id, &req).await {
+            Ok(cookie) => Some(cookie),
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut response = complete_login(&req, &prisma_client, user).await;
+    if let Some(cookie) = trusted_device_cookie {
+        response.add_cookie(&cookie).ok();
+    }
+    response
+}
+
+pub async fn regenerate_backup_codes(
     req: HttpRequest,
     body: web::Json<VerifyOTPSchema>,
     prisma_client: web::Data<Arc<PrismaClient>>,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
-        let user = prisma_client
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        if let Some(response) = reject_impersonation(claims) {
+            return response;
+        }
+        let user = match prisma_client
             .user()
             .find_unique(user::id::equals(claims.sub.clone()))
             .exec()
             .await
-            .unwrap()
-            .unwrap();
+        {
+            Ok(Some(user)) => user,
+            Ok(None) => return HttpResponse::NotFound().json(json!({"error": "User not found"})),
+            Err(_) => {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Database error"}))
+            }
+        };
 
         if !user.otp_enabled {
-            let json_error = json! ({
-                "status": "fail".to_string(),
-                "message": "2FA not enabled".to_string(),
-            });
-
-            return HttpResponse::Forbidden().json(json_error);
+            return HttpResponse::BadRequest().json(json!({"error": "2FA not enabled"}));
         }
 
-        let otp_base32 = user.opt_base_32.clone().unwrap();
-
-        let totp = TOTP::new(
-            Algorithm::SHA1,
-            6,
-            1,
-            30,
-            Secret::Encoded(otp_base32).to_bytes().unwrap(),
-        )
-        .unwrap();
-
-        let is_valid = totp.check_current(body.token.as_ref()).unwrap();
-
-        if !is_valid {
-            let json_error = json! ({
-                "status": "fail".to_string(),
-                "message": "Token is invalid or user doesn't exist".to_string(),
-            });
+        if !two_factor::check_totp(&user, &body.token) {
+            return HttpResponse::Forbidden().json(json!({
+                "status": "fail",
+                "message": "Token is invalid or user doesn't exist",
+            }));
+        }
 
-            return HttpResponse::Forbidden().json(json_error);
+        match two_factor::regenerate_backup_codes(&prisma_client, &user.id).await {
+            Ok(backup_codes) => HttpResponse::Ok().json(json!({"backup_codes": backup_codes})),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
         }
-        HttpResponse::Ok().json(json!({"otp_valid": true}))
     } else {
         HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
     }
@@ -480,6 +898,12 @@ pub async fn disable_otp(
     prisma_client: web::Data<Arc<PrismaClient>>,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>() {
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        if let Some(response) = reject_impersonation(claims) {
+            return response;
+        }
         let result = prisma_client
         .user()
         .update(
@@ -495,10 +919,603 @@ pub async fn disable_otp(
         .await;
 
         match result {
-            Ok(user) => HttpResponse::Ok().json(json!({"user": user})),
+            Ok(user) => match two_factor::clear_two_factor_state(&prisma_client, &user.id).await {
+                Ok(_) => HttpResponse::Ok().json(json!({"user": user})),
+                Err(_) => HttpResponse::InternalServerError().json(json!({"error":"database error"}))
+            },
             Err(_) => HttpResponse::InternalServerError().json(json!({"error":"database error"}))
         }
     } else {
         HttpResponse::Unauthorized().json(json!({"error": "the user is unauthorized"}))
     }
 }
+
+pub async fn refresh_token(
+    req: HttpRequest,
+    payload: web::Json<RefreshTokenPayload>,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    let (session_id, secret) = match token::split_refresh_token(&payload.refresh_token) {
+        Some(parts) => parts,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "Invalid refresh token"})),
+    };
+
+    match prisma_client
+        .session()
+        .find_unique(session::id::equals(session_id.clone()))
+        .with(session::user::fetch())
+        .exec()
+        .await
+    {
+        Ok(Some(session_record)) => {
+            if session_record.revoked_at.is_some() || session_record.expires_at < Utc::now() {
+                return HttpResponse::Unauthorized()
+                    .json(json!({"error": "Session expired or revoked"}));
+            }
+
+            // A refresh token that was already rotated out is being replayed: assume it leaked
+            // and kill the whole session so neither party can keep using it.
+            let presented_hash = token::hash_token(&secret);
+            if session_record.refresh_token_hash != presented_hash {
+                return reject_reused_refresh_token(&prisma_client, &session_id).await;
+            }
+
+            let user_record = match session_record.user {
+                Some(user_record) => *user_record,
+                None => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error"}))
+                }
+            };
+
+            let refresh_token =
+                match token::rotate_session(&prisma_client, &session_id, &presented_hash, &req)
+                    .await
+                {
+                    Ok(Some(t)) => t,
+                    // Another request rotated the same token in the meantime.
+                    Ok(None) => {
+                        return reject_reused_refresh_token(&prisma_client, &session_id).await
+                    }
+                    Err(_) => {
+                        return HttpResponse::InternalServerError()
+                            .json(json!({"error": "Failed to rotate session"}))
+                    }
+                };
+
+            match token::issue_access_token(&user_record, &session_id) {
+                Ok(access_token) => HttpResponse::Ok().json(json!({
+                    "token": access_token,
+                    "refresh_token": refresh_token,
+                    "expires_in": token::access_token_ttl(),
+                })),
+                Err(_) => HttpResponse::InternalServerError()
+                    .json(json!({"error": "Failed to generate token"})),
+            }
+        }
+        Ok(None) => HttpResponse::Unauthorized().json(json!({"error": "Invalid refresh token"})),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+    }
+}
+
+async fn reject_reused_refresh_token(
+    prisma_client: &PrismaClient,
+    session_id: &str,
+) -> HttpResponse {
+    token::revoke_session(prisma_client, session_id).await.ok();
+    HttpResponse::Unauthorized()
+        .json(json!({"error": "Refresh token reuse detected, session revoked"}))
+}
+
+pub async fn logout(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        match &claims.sid {
+            Some(session_id) => match token::revoke_session(&prisma_client, session_id).await {
+                Ok(_) => HttpResponse::Ok().json(json!({"message": "Logged out successfully"})),
+                Err(_) => {
+                    HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+                }
+            },
+            None => HttpResponse::Ok().json(json!({"message": "Logged out successfully"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+pub async fn get_sessions(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        match prisma_client
+            .session()
+            .find_many(vec![
+                session::user_id::equals(claims.sub.clone()),
+                session::revoked_at::equals(None),
+                session::expires_at::gt(Utc::now().fixed_offset()),
+            ])
+            .order_by(session::last_used_at::order(prisma_client_rust::Direction::Desc))
+            .exec()
+            .await
+        {
+            Ok(sessions) => {
+                let response = sessions
+                    .into_iter()
+                    .map(|s| SessionResponse {
+                        current: claims.sid.as_deref() == Some(s.id.as_str()),
+                        id: s.id,
+                        device_info: s.device_info,
+                        ip_address: s.ip_address,
+                        last_used_at: s.last_used_at.to_rfc3339(),
+                        created_at: s.created_at.to_rfc3339(),
+                        expires_at: s.expires_at.to_rfc3339(),
+                    })
+                    .collect::<Vec<_>>();
+                HttpResponse::Ok().json(response)
+            }
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+pub async fn revoke_session(
+    req: HttpRequest,
+    session_id: web::Path<String>,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        if let Some(response) = reject_impersonation(claims) {
+            return response;
+        }
+        let session_id = session_id.into_inner();
+        match prisma_client
+            .session()
+            .update_many(
+                vec![
+                    session::id::equals(session_id),
+                    session::user_id::equals(claims.sub.clone()),
+                    session::revoked_at::equals(None),
+                ],
+                vec![session::revoked_at::set(Some(Utc::now().fixed_offset()))],
+            )
+            .exec()
+            .await
+        {
+            Ok(0) => HttpResponse::NotFound().json(json!({"error": "Session not found"})),
+            Ok(_) => HttpResponse::Ok().json(json!({"message": "Session revoked successfully"})),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+async fn send_verification_email(prisma_client: &PrismaClient, user_record: &user::Data) {
+    if let Ok(verification_token) =
+        token::issue_email_verification_token(&user_record.id, &user_record.email)
+    {
+        let template = Template::VerifyEmail {
+            name: user_record.first_name.clone(),
+            verification_token,
+        };
+        outbox::enqueue(prisma_client, &user_record.email, template)
+            .await
+            .ok();
+    }
+}
+
+pub async fn verify_email(
+    query: web::Query<VerifyEmailQuery>,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    let claims = match token::decode_email_verification_token(&query.token) {
+        Ok(claims) => claims,
+        Err(_) => {
+            return HttpResponse::BadRequest()
+                .json(json!({"error": "Verification link is invalid or has expired"}))
+        }
+    };
+
+    match prisma_client
+        .user()
+        .update_many(
+            vec![
+                user::id::equals(claims.sub),
+                user::email::equals(claims.email),
+            ],
+            vec![user::email_verified::set(true)],
+        )
+        .exec()
+        .await
+    {
+        Ok(0) => HttpResponse::BadRequest()
+            .json(json!({"error": "Verification link is invalid or has expired"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Email verified successfully"})),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+    }
+}
+
+pub async fn resend_verification_email(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        match prisma_client
+            .user()
+            .find_unique(user::id::equals(claims.sub.clone()))
+            .exec()
+            .await
+        {
+            Ok(Some(user_record)) if user_record.email_verified => {
+                HttpResponse::BadRequest().json(json!({"error": "Email is already verified"}))
+            }
+            Ok(Some(user_record)) => {
+                send_verification_email(&prisma_client, &user_record).await;
+                HttpResponse::Ok().json(json!({"message": "Verification email sent"}))
+            }
+            Ok(None) => HttpResponse::NotFound().json(json!({"error": "User not found"})),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+fn identity_response(identity_record: identity::Data) -> IdentityResponse {
+    IdentityResponse {
+        provider: identity_record.provider,
+        email: identity_record.email,
+        created_at: identity_record.created_at.to_rfc3339(),
+        last_login_at: identity_record.last_login_at.map(|t| t.to_rfc3339()),
+    }
+}
+
+pub async fn oidc_login(
+    provider_name: web::Path<String>,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    let provider = match oidc::provider(&provider_name) {
+        Some(provider) => provider,
+        None => {
+            return HttpResponse::NotFound().json(json!({"error": "Unknown identity provider"}))
+        }
+    };
+
+    match oidc::authorization_url(&prisma_client, &provider, None).await {
+        Ok(authorization_url) => {
+            HttpResponse::Ok().json(json!({"authorization_url": authorization_url}))
+        }
+        Err(err) => HttpResponse::BadGateway()
+            .json(json!({"error": format!("Identity provider error: {}", err)})),
+    }
+}
+
+pub async fn oidc_link(
+    req: HttpRequest,
+    provider_name: web::Path<String>,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    let user_id = match req.extensions().get::<Claims>() {
+        Some(claims) => {
+            if let Some(response) = reject_api_key(claims).or_else(|| reject_impersonation(claims))
+            {
+                return response;
+            }
+            claims.sub.clone()
+        }
+        None => return HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"})),
+    };
+    let provider = match oidc::provider(&provider_name) {
+        Some(provider) => provider,
+        None => {
+            return HttpResponse::NotFound().json(json!({"error": "Unknown identity provider"}))
+        }
+    };
+
+    match oidc::authorization_url(&prisma_client, &provider, Some(user_id)).await {
+        Ok(authorization_url) => {
+            HttpResponse::Ok().json(json!({"authorization_url": authorization_url}))
+        }
+        Err(err) => HttpResponse::BadGateway()
+            .json(json!({"error": format!("Identity provider error: {}", err)})),
+    }
+}
+
+/// The provider redirects the browser to the frontend, which passes `code` and `state` on to
+/// this endpoint. Depending on how the flow was started it either signs the user in (creating
+/// the account on first use) or links the identity to the signed-in user.
+pub async fn oidc_callback(
+    req: HttpRequest,
+    provider_name: web::Path<String>,
+    query: web::Query<OidcCallbackQuery>,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    let provider = match oidc::provider(&provider_name) {
+        Some(provider) => provider,
+        None => {
+            return HttpResponse::NotFound().json(json!({"error": "Unknown identity provider"}))
+        }
+    };
+
+    let login_state = match oidc::take_login_state(&prisma_client, &provider, &query.state).await {
+        Ok(Some(login_state)) => login_state,
+        Ok(None) => {
+            return HttpResponse::BadRequest()
+                .json(json!({"error": "Sign-in request is invalid or has expired"}))
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+        }
+    };
+
+    let id_claims = match oidc::exchange_code(&provider, &login_state, &query.code).await {
+        Ok(id_claims) => id_claims,
+        Err(err) => {
+            return HttpResponse::BadGateway()
+                .json(json!({"error": format!("Identity provider error: {}", err)}))
+        }
+    };
+
+    let existing = match prisma_client
+        .identity()
+        .find_unique(identity::provider_subject(
+            provider.name.clone(),
+            id_claims.sub.clone(),
+        ))
+        .with(identity::user::fetch())
+        .exec()
+        .await
+    {
+        Ok(existing) => existing,
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({"error": "Database error"}))
+        }
+    };
+
+    if let Some(link_user_id) = login_state.link_user_id {
+        return match existing {
+            Some(identity_record) if identity_record.user_id != link_user_id => {
+                HttpResponse::Conflict()
+                    .json(json!({"error": "This identity is already linked to another account"}))
+            }
+            Some(identity_record) => HttpResponse::Ok().json(identity_response(identity_record)),
+            None => match prisma_client
+                .identity()
+                .create(
+                    user::id::equals(link_user_id),
+                    provider.name.clone(),
+                    id_claims.sub.clone(),
+                    vec![identity::email::set(id_claims.email.clone())],
+                )
+                .exec()
+                .await
+            {
+                Ok(identity_record) => {
+                    HttpResponse::Created().json(identity_response(identity_record))
+                }
+                Err(_) => HttpResponse::Conflict()
+                    .json(json!({"error": "An identity from this provider is already linked"})),
+            },
+        };
+    }
+
+    let user_record = match existing {
+        Some(identity_record) => {
+            prisma_client
+                .identity()
+                .update(
+                    identity::id::equals(identity_record.id.clone()),
+                    vec![identity::last_login_at::set(Some(
+                        Utc::now().fixed_offset(),
+                    ))],
+                )
+                .exec()
+                .await
+                .ok();
+            match identity_record.user {
+                Some(user_record) if user_record.deleted_at.is_some() => {
+                    return HttpResponse::Forbidden()
+                        .json(json!({"error": "This account has been deleted"}))
+                }
+                Some(user_record) => *user_record,
+                None => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error"}))
+                }
+            }
+        }
+        None => {
+            let email = match id_claims.verified_email() {
+                Some(email) => email.to_string(),
+                None => {
+                    return HttpResponse::BadRequest().json(
+                        json!({"error": "The identity provider did not return a verified email"}),
+                    )
+                }
+            };
+
+            let user_record = match prisma_client
+                .user()
+                .find_unique(user::email::equals(email.clone()))
+                .exec()
+                .await
+            {
+                Ok(Some(user_record)) if user_record.deleted_at.is_some() => {
+                    return HttpResponse::Forbidden()
+                        .json(json!({"error": "This account has been deleted"}))
+                }
+                // Whoever registered an unverified account may not own the address, so it is
+                // not handed over to the provider's user.
+                Ok(Some(user_record)) if !user_record.email_verified => {
+                    return HttpResponse::Conflict().json(json!({
+                        "error": "An account with this email already exists. Sign in with your password and link the provider from your profile"
+                    }))
+                }
+                Ok(Some(user_record)) => user_record,
+                Ok(None) => {
+                    let display_name = id_claims
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| email.split('@').next().unwrap_or("").to_string());
+                    // An empty password hash never verifies, so the account can only be used
+                    // through its identities until the user sets a password via reset.
+                    match prisma_client
+                        .user()
+                        .create(
+                            display_name,
+                            id_claims.given_name.clone().unwrap_or_default(),
+                            id_claims.family_name.clone().unwrap_or_default(),
+                            email.clone(),
+                            String::new(),
+                            RoleType::Client,
+                            vec![user::email_verified::set(true)],
+                        )
+                        .exec()
+                        .await
+                    {
+                        Ok(user_record) => user_record,
+                        Err(_) => {
+                            return HttpResponse::InternalServerError()
+                                .json(json!({"error": "Failed to create user"}))
+                        }
+                    }
+                }
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"error": "Database error"}))
+                }
+            };
+
+            if prisma_client
+                .identity()
+                .create(
+                    user::id::equals(user_record.id.clone()),
+                    provider.name.clone(),
+                    id_claims.sub.clone(),
+                    vec![
+                        identity::email::set(Some(email)),
+                        identity::last_login_at::set(Some(Utc::now().fixed_offset())),
+                    ],
+                )
+                .exec()
+                .await
+                .is_err()
+            {
+                return HttpResponse::Conflict()
+                    .json(json!({"error": "An identity from this provider is already linked"}));
+            }
+            user_record
+        }
+    };
+
+    finish_first_factor(&req, &prisma_client, user_record).await
+}
+
+pub async fn get_identities(
+    req: HttpRequest,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        match prisma_client
+            .identity()
+            .find_many(vec![identity::user_id::equals(claims.sub.clone())])
+            .exec()
+            .await
+        {
+            Ok(identities) => HttpResponse::Ok().json(
+                identities
+                    .into_iter()
+                    .map(identity_response)
+                    .collect::<Vec<_>>(),
+            ),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+pub async fn unlink_identity(
+    req: HttpRequest,
+    provider_name: web::Path<String>,
+    prisma_client: web::Data<Arc<PrismaClient>>,
+) -> impl Responder {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
+        if let Some(response) = reject_impersonation(claims) {
+            return response;
+        }
+        let user_record = match prisma_client
+            .user()
+            .find_unique(user::id::equals(claims.sub.clone()))
+            .with(user::identities::fetch(vec![]))
+            .exec()
+            .await
+        {
+            Ok(Some(user_record)) => user_record,
+            Ok(None) => return HttpResponse::NotFound().json(json!({"error": "User not found"})),
+            Err(_) => {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Database error"}))
+            }
+        };
+
+        let identities = user_record.identities.unwrap_or_default();
+        if !identities
+            .iter()
+            .any(|identity_record| identity_record.provider == *provider_name)
+        {
+            return HttpResponse::NotFound().json(json!({"error": "Identity not linked"}));
+        }
+        if user_record.password.is_empty() && identities.len() == 1 {
+            return HttpResponse::Conflict().json(json!({
+                "error": "Set a password before unlinking your last sign-in method"
+            }));
+        }
+
+        match prisma_client
+            .identity()
+            .delete_many(vec![
+                identity::user_id::equals(user_record.id),
+                identity::provider::equals(provider_name.into_inner()),
+            ])
+            .exec()
+            .await
+        {
+            Ok(_) => HttpResponse::Ok().json(json!({"message": "Identity unlinked"})),
+            Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Database error"})),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({"error": "Unauthorized"}))
+    }
+}
+
+/// Public keys other services use to verify our tokens.
+pub async fn jwks() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(keys::store().jwks())
+}
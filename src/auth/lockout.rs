@@ -0,0 +1,251 @@
+use crate::audit::AuditEvent;
+use crate::prisma::{login_attempt, user, PrismaClient};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use dotenv::dotenv;
+use serde_json::json;
+use std::env;
+
+/// Thresholds for failed logins, configurable through `LOGIN_*` environment variables.
+pub struct LockoutPolicy {
+    pub account_threshold: i32,
+    pub ip_threshold: i64,
+    pub ip_window_minutes: i64,
+    pub base_seconds: i64,
+    pub max_seconds: i64,
+}
+
+fn env_number(name: &str, default: i64) -> i64 {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+impl LockoutPolicy {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        LockoutPolicy {
+            account_threshold: env_number("LOGIN_LOCKOUT_THRESHOLD", 5) as i32,
+            ip_threshold: env_number("LOGIN_IP_MAX_FAILURES", 20),
+            ip_window_minutes: env_number("LOGIN_IP_WINDOW_MINUTES", 15),
+            base_seconds: env_number("LOGIN_LOCKOUT_BASE_SECONDS", 60),
+            max_seconds: env_number("LOGIN_LOCKOUT_MAX_SECONDS", 3600),
+        }
+    }
+
+    /// Lock duration after `failures` consecutive failures: nothing below the threshold, then
+    /// doubling from `base_seconds` with every further failure, capped at `max_seconds`.
+    pub fn backoff(&self, failures: i64, threshold: i64) -> Option<Duration> {
+        if failures < threshold {
+            return None;
+        }
+        let exponent = (failures - threshold).min(20) as u32;
+        let seconds = self
+            .base_seconds
+            .saturating_mul(2i64.saturating_pow(exponent))
+            .min(self.max_seconds);
+        Some(Duration::seconds(seconds))
+    }
+}
+
+fn seconds_until(until: DateTime<FixedOffset>) -> Option<i64> {
+    let remaining = (until - Utc::now().fixed_offset()).num_seconds();
+    if remaining > 0 {
+        Some(remaining)
+    } else {
+        None
+    }
+}
+
+/// Seconds the account stays locked, if it is locked right now.
+pub fn account_locked_for(user_record: &user::Data) -> Option<i64> {
+    user_record.locked_until.and_then(seconds_until)
+}
+
+/// Seconds the IP must wait before trying again, based on its recent failures across all
+/// accounts.
+pub async fn ip_blocked_for(
+    prisma_client: &PrismaClient,
+    ip_address: Option<&str>,
+) -> Result<Option<i64>, prisma_client_rust::QueryError> {
+    let ip_address = match ip_address {
+        Some(ip_address) => ip_address.to_string(),
+        None => return Ok(None),
+    };
+    let policy = LockoutPolicy::from_env();
+    let window_start = (Utc::now() - Duration::minutes(policy.ip_window_minutes)).fixed_offset();
+    let failed = || {
+        vec![
+            login_attempt::ip_address::equals(Some(ip_address.clone())),
+            login_attempt::successful::equals(false),
+            login_attempt::created_at::gte(window_start),
+        ]
+    };
+
+    let failures = prisma_client.login_attempt().count(failed()).exec().await?;
+    let backoff = match policy.backoff(failures, policy.ip_threshold) {
+        Some(backoff) => backoff,
+        None => return Ok(None),
+    };
+
+    let last_failure = prisma_client
+        .login_attempt()
+        .find_first(failed())
+        .order_by(login_attempt::created_at::order(
+            prisma_client_rust::Direction::Desc,
+        ))
+        .exec()
+        .await?;
+
+    Ok(last_failure.and_then(|attempt| seconds_until(attempt.created_at + backoff)))
+}
+
+/// Records a failed password or 2FA attempt and locks the account once it crosses the
+/// threshold. Counters live in the database so every server instance sees the same state.
+pub async fn record_failure(
+    prisma_client: &PrismaClient,
+    email: &str,
+    user_record: Option<&user::Data>,
+    ip_address: Option<String>,
+    method: &str,
+) -> Result<(), prisma_client_rust::QueryError> {
+    prisma_client
+        .login_attempt()
+        .create(
+            email.to_string(),
+            false,
+            method.to_string(),
+            vec![
+                login_attempt::user_id::set(user_record.map(|u| u.id.clone())),
+                login_attempt::ip_address::set(ip_address.clone()),
+            ],
+        )
+        .exec()
+        .await?;
+
+    let user_record = match user_record {
+        Some(user_record) => user_record,
+        None => return Ok(()),
+    };
+
+    let updated = prisma_client
+        .user()
+        .update(
+            user::id::equals(user_record.id.clone()),
+            vec![user::failed_login_count::increment(1)],
+        )
+        .exec()
+        .await?;
+
+    let policy = LockoutPolicy::from_env();
+    if let Some(backoff) = policy.backoff(
+        updated.failed_login_count as i64,
+        policy.account_threshold as i64,
+    ) {
+        let locked_until = (Utc::now() + backoff).fixed_offset();
+        prisma_client
+            .user()
+            .update(
+                user::id::equals(updated.id.clone()),
+                vec![user::locked_until::set(Some(locked_until))],
+            )
+            .exec()
+            .await?;
+
+        AuditEvent::new("account.locked", "user")
+            .entity(&updated.id)
+            .ip(ip_address)
+            .metadata(json!({
+                "failedAttempts": updated.failed_login_count,
+                "lockedUntil": locked_until,
+                "method": method,
+            }))
+            .record(prisma_client)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Records a successful attempt and clears the account's failure counter.
+pub async fn record_success(
+    prisma_client: &PrismaClient,
+    user_record: &user::Data,
+    ip_address: Option<String>,
+    method: &str,
+) -> Result<(), prisma_client_rust::QueryError> {
+    prisma_client
+        .login_attempt()
+        .create(
+            user_record.email.clone(),
+            true,
+            method.to_string(),
+            vec![
+                login_attempt::user_id::set(Some(user_record.id.clone())),
+                login_attempt::ip_address::set(ip_address),
+            ],
+        )
+        .exec()
+        .await?;
+
+    if user_record.failed_login_count > 0 || user_record.locked_until.is_some() {
+        reset(prisma_client, &user_record.id).await?;
+    }
+    Ok(())
+}
+
+pub async fn reset(
+    prisma_client: &PrismaClient,
+    user_id: &str,
+) -> Result<user::Data, prisma_client_rust::QueryError> {
+    prisma_client
+        .user()
+        .update(
+            user::id::equals(user_id.to_string()),
+            vec![
+                user::failed_login_count::set(0),
+                user::locked_until::set(None),
+            ],
+        )
+        .exec()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> LockoutPolicy {
+        LockoutPolicy {
+            account_threshold: 5,
+            ip_threshold: 20,
+            ip_window_minutes: 15,
+            base_seconds: 60,
+            max_seconds: 3600,
+        }
+    }
+
+    #[test]
+    fn no_lock_below_the_threshold() {
+        assert_eq!(policy().backoff(0, 5), None);
+        assert_eq!(policy().backoff(4, 5), None);
+    }
+
+    #[test]
+    fn locks_for_the_base_duration_at_the_threshold() {
+        assert_eq!(policy().backoff(5, 5), Some(Duration::seconds(60)));
+    }
+
+    #[test]
+    fn doubles_with_every_further_failure() {
+        assert_eq!(policy().backoff(6, 5), Some(Duration::seconds(120)));
+        assert_eq!(policy().backoff(7, 5), Some(Duration::seconds(240)));
+        assert_eq!(policy().backoff(10, 5), Some(Duration::seconds(1920)));
+    }
+
+    #[test]
+    fn is_capped_at_the_maximum() {
+        assert_eq!(policy().backoff(11, 5), Some(Duration::seconds(3600)));
+        assert_eq!(policy().backoff(i64::MAX, 5), Some(Duration::seconds(3600)));
+    }
+}
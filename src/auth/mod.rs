@@ -1,3 +1,10 @@
+pub mod api_key;
 pub mod handler;
+pub mod keys;
+pub mod lockout;
 pub mod model;
-pub mod routes;
\ No newline at end of file
+pub mod oidc;
+pub mod password;
+pub mod routes;
+pub mod token;
+pub mod two_factor;
@@ -15,6 +15,7 @@ pub struct UserResponse {
     pub id: String,
     pub username: String,
     pub email: String,
+    pub email_verified: bool,
     pub first_name: String,
     pub last_name: String,
     pub role: String,
@@ -35,6 +36,18 @@ pub struct Claims {
     pub sub: String,
     pub exp: usize,
     pub is_admin: bool,
+    #[serde(default)]
+    pub sid: Option<String>,
+    #[serde(default)]
+    pub ver: i32,
+    #[serde(default)]
+    pub two_factor_pending: bool,
+    /// Set when the request authenticated with an API key rather than a user token.
+    #[serde(default)]
+    pub api_key_id: Option<String>,
+    /// Id of the admin acting as this user, for tokens issued by impersonation.
+    #[serde(default)]
+    pub impersonator: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -59,8 +72,7 @@ pub struct GetRecoveryKeyPayload {
 
 #[derive(Deserialize)]
 pub struct ResetPasswordPayload {
-    pub recoverykey: String,
-    pub email: String,
+    pub token: String,
     pub newpassword: String,
 }
 
@@ -68,3 +80,58 @@ pub struct ResetPasswordPayload {
 pub struct VerifyOTPSchema {
     pub token: String,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct ValidateOTPSchema {
+    pub token: Option<String>,
+    pub backup_code: Option<String>,
+    #[serde(default)]
+    pub remember_device: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshTokenPayload {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub id: String,
+    pub device_info: Option<String>,
+    pub ip_address: Option<String>,
+    pub last_used_at: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub current: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EmailVerificationClaims {
+    pub sub: String,
+    pub email: String,
+    pub exp: usize,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Serialize)]
+pub struct IdentityResponse {
+    pub provider: String,
+    pub email: Option<String>,
+    pub created_at: String,
+    pub last_login_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ImpersonatePayload {
+    pub reason: String,
+}
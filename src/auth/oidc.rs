@@ -0,0 +1,326 @@
+use crate::auth::token::{generate_secret, hash_token};
+use crate::prisma::{oidc_login_state, PrismaClient};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use dotenv::dotenv;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+
+/// How long a user has to finish signing in at the provider.
+const LOGIN_STATE_TTL_MINUTES: i64 = 10;
+
+/// An OpenID Connect provider configured through `OIDC_<NAME>_*` environment variables. Names
+/// are listed in `OIDC_PROVIDERS`, e.g. `google,mock`.
+pub struct Provider {
+    pub name: String,
+    pub issuer: String,
+    /// Defaults to `<issuer>/.well-known/openid-configuration`; override it to point at a local
+    /// mock server.
+    pub discovery_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: String,
+}
+
+pub fn provider(name: &str) -> Option<Provider> {
+    dotenv().ok();
+    let enabled = env::var("OIDC_PROVIDERS").unwrap_or_default();
+    if !enabled.split(',').any(|p| p.trim() == name) {
+        return None;
+    }
+
+    let var = |key: &str| env::var(format!("OIDC_{}_{}", name.to_ascii_uppercase(), key)).ok();
+    let issuer = var("ISSUER")?.trim_end_matches('/').to_string();
+    Some(Provider {
+        name: name.to_string(),
+        discovery_url: var("DISCOVERY_URL")
+            .unwrap_or_else(|| format!("{}/.well-known/openid-configuration", issuer)),
+        issuer,
+        client_id: var("CLIENT_ID")?,
+        client_secret: var("CLIENT_SECRET"),
+        redirect_uri: var("REDIRECT_URI")?,
+        scopes: var("SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    #[serde(default)]
+    pub id_token_signing_alg_values_supported: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The ID token claims we rely on.
+#[derive(Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    /// Some providers send this as a string, so it is read loosely.
+    #[serde(default)]
+    pub email_verified: Option<serde_json::Value>,
+    pub nonce: Option<String>,
+    pub name: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+}
+
+impl IdTokenClaims {
+    /// The email, but only when the provider vouches for it.
+    pub fn verified_email(&self) -> Option<&str> {
+        let verified = match &self.email_verified {
+            Some(serde_json::Value::Bool(verified)) => *verified,
+            Some(serde_json::Value::String(verified)) => verified == "true",
+            _ => false,
+        };
+        if verified {
+            self.email.as_deref()
+        } else {
+            None
+        }
+    }
+}
+
+/// Algorithms an ID token may be signed with: the one the key is published for, otherwise the
+/// ones the provider advertises, otherwise RS256 as OpenID Connect prescribes. HMAC is never
+/// accepted, since its key would be the public JWK. The token's own header only gets to choose
+/// within this list.
+fn signing_algorithms(key_algorithm: Option<Algorithm>, advertised: &[String]) -> Vec<Algorithm> {
+    let algorithms = match key_algorithm {
+        Some(algorithm) => vec![algorithm],
+        None if !advertised.is_empty() => advertised
+            .iter()
+            .filter_map(|algorithm| algorithm.parse().ok())
+            .collect(),
+        None => vec![Algorithm::RS256],
+    };
+    algorithms
+        .into_iter()
+        .filter(|algorithm| {
+            !matches!(
+                algorithm,
+                Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+            )
+        })
+        .collect()
+}
+
+pub async fn discover(provider: &Provider) -> Result<Discovery, String> {
+    let discovery: Discovery = reqwest::get(&provider.discovery_url)
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if discovery.issuer.trim_end_matches('/') != provider.issuer {
+        return Err(format!(
+            "discovery document issuer {} does not match {}",
+            discovery.issuer, provider.issuer
+        ));
+    }
+    Ok(discovery)
+}
+
+fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Starts an authorization-code flow with PKCE and returns the URL to send the user to. When
+/// `link_user_id` is set the callback links the identity to that user instead of signing in.
+pub async fn authorization_url(
+    prisma_client: &PrismaClient,
+    provider: &Provider,
+    link_user_id: Option<String>,
+) -> Result<String, String> {
+    let discovery = discover(provider).await?;
+    let state = generate_secret();
+    let nonce = generate_secret();
+    let code_verifier = generate_secret();
+
+    prisma_client
+        .oidc_login_state()
+        .create(
+            hash_token(&state),
+            provider.name.clone(),
+            code_verifier.clone(),
+            nonce.clone(),
+            (Utc::now() + Duration::minutes(LOGIN_STATE_TTL_MINUTES)).fixed_offset(),
+            vec![oidc_login_state::link_user_id::set(link_user_id)],
+        )
+        .exec()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Url::parse_with_params(
+        &discovery.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", provider.client_id.as_str()),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+            ("scope", provider.scopes.as_str()),
+            ("state", state.as_str()),
+            ("nonce", nonce.as_str()),
+            ("code_challenge", code_challenge(&code_verifier).as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map(|url| url.to_string())
+    .map_err(|e| e.to_string())
+}
+
+/// Looks up and burns the login state for `state`. Returns `None` when it is unknown, expired,
+/// or was issued for another provider.
+pub async fn take_login_state(
+    prisma_client: &PrismaClient,
+    provider: &Provider,
+    state: &str,
+) -> Result<Option<oidc_login_state::Data>, prisma_client_rust::QueryError> {
+    let state_hash = hash_token(state);
+    let login_state = prisma_client
+        .oidc_login_state()
+        .find_unique(oidc_login_state::state_hash::equals(state_hash.clone()))
+        .exec()
+        .await?;
+
+    let login_state = match login_state {
+        Some(login_state) => login_state,
+        None => return Ok(None),
+    };
+    // Only the request that actually deletes the row may continue, so a replayed callback
+    // racing the first one loses.
+    let deleted = prisma_client
+        .oidc_login_state()
+        .delete_many(vec![oidc_login_state::state_hash::equals(state_hash)])
+        .exec()
+        .await?;
+
+    if deleted == 0
+        || login_state.provider != provider.name
+        || login_state.expires_at < Utc::now().fixed_offset()
+    {
+        return Ok(None);
+    }
+    Ok(Some(login_state))
+}
+
+/// Exchanges the authorization code and returns the verified ID token claims.
+pub async fn exchange_code(
+    provider: &Provider,
+    login_state: &oidc_login_state::Data,
+    code: &str,
+) -> Result<IdTokenClaims, String> {
+    let discovery = discover(provider).await?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", provider.redirect_uri.as_str()),
+        ("client_id", provider.client_id.as_str()),
+        ("code_verifier", login_state.code_verifier.as_str()),
+    ];
+    if let Some(client_secret) = &provider.client_secret {
+        form.push(("client_secret", client_secret.as_str()));
+    }
+
+    let http = reqwest::Client::new();
+    let tokens: TokenResponse = http
+        .post(&discovery.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let jwks: JwkSet = http
+        .get(&discovery.jwks_uri)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let header = decode_header(&tokens.id_token).map_err(|e| e.to_string())?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    }
+    .ok_or("no matching signing key in JWKS")?;
+    let decoding_key = DecodingKey::from_jwk(jwk).map_err(|e| e.to_string())?;
+
+    let algorithms = signing_algorithms(
+        jwk.common.algorithm,
+        &discovery.id_token_signing_alg_values_supported,
+    );
+    if !algorithms.contains(&header.alg) {
+        return Err(format!(
+            "ID token is signed with {:?}, expected one of {:?}",
+            header.alg, algorithms
+        ));
+    }
+    let mut validation = Validation::new(header.alg);
+    validation.algorithms = algorithms;
+    validation.set_audience(&[&provider.client_id]);
+    validation.set_issuer(&[&discovery.issuer]);
+    let claims = decode::<IdTokenClaims>(&tokens.id_token, &decoding_key, &validation)
+        .map_err(|e| e.to_string())?
+        .claims;
+
+    if claims.nonce.as_deref() != Some(login_state.nonce.as_str()) {
+        return Err("ID token nonce does not match".to_string());
+    }
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_the_algorithm_of_the_key() {
+        let advertised = vec!["RS256".to_string(), "ES256".to_string()];
+        assert_eq!(
+            signing_algorithms(Some(Algorithm::ES256), &advertised),
+            vec![Algorithm::ES256]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_advertised_algorithms_then_rs256() {
+        let advertised = vec!["ES256".to_string(), "none".to_string()];
+        assert_eq!(
+            signing_algorithms(None, &advertised),
+            vec![Algorithm::ES256]
+        );
+        assert_eq!(signing_algorithms(None, &[]), vec![Algorithm::RS256]);
+    }
+
+    #[test]
+    fn never_accepts_hmac() {
+        let advertised = vec!["HS256".to_string(), "RS256".to_string()];
+        assert_eq!(
+            signing_algorithms(None, &advertised),
+            vec![Algorithm::RS256]
+        );
+        assert!(signing_algorithms(Some(Algorithm::HS256), &[]).is_empty());
+    }
+}
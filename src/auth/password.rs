@@ -0,0 +1,301 @@
+use actix_web::web;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use dotenv::dotenv;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::env;
+use std::sync::OnceLock;
+
+static COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+#[derive(Debug, Serialize)]
+pub struct PasswordViolation {
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Password rules, configurable through `PASSWORD_*` environment variables.
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    env::var(name)
+        .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "true" | "1" | "yes"))
+        .unwrap_or(default)
+}
+
+fn env_number(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        PasswordPolicy {
+            min_length: env_number("PASSWORD_MIN_LENGTH", 8),
+            max_length: env_number("PASSWORD_MAX_LENGTH", 128),
+            require_uppercase: env_flag("PASSWORD_REQUIRE_UPPERCASE", true),
+            require_lowercase: env_flag("PASSWORD_REQUIRE_LOWERCASE", true),
+            require_digit: env_flag("PASSWORD_REQUIRE_DIGIT", true),
+            require_symbol: env_flag("PASSWORD_REQUIRE_SYMBOL", false),
+        }
+    }
+
+    /// Checks `password` against every rule and returns all violations at once. `personal`
+    /// holds values such as the email or username that the password must not contain.
+    pub fn validate(
+        &self,
+        password: &str,
+        personal: &[&str],
+    ) -> Result<(), Vec<PasswordViolation>> {
+        let mut violations = vec![];
+        let length = password.chars().count();
+
+        if length < self.min_length {
+            violations.push(PasswordViolation {
+                code: "too_short",
+                message: format!(
+                    "Password must be at least {} characters long",
+                    self.min_length
+                ),
+            });
+        }
+        if password.len() > self.max_length {
+            violations.push(PasswordViolation {
+                code: "too_long",
+                message: format!("Password must be at most {} bytes long", self.max_length),
+            });
+        }
+        if self.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+            violations.push(PasswordViolation {
+                code: "missing_uppercase",
+                message: "Password must contain an uppercase letter".to_string(),
+            });
+        }
+        if self.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+            violations.push(PasswordViolation {
+                code: "missing_lowercase",
+                message: "Password must contain a lowercase letter".to_string(),
+            });
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violations.push(PasswordViolation {
+                code: "missing_digit",
+                message: "Password must contain a digit".to_string(),
+            });
+        }
+        if self.require_symbol && password.chars().all(|c| c.is_alphanumeric()) {
+            violations.push(PasswordViolation {
+                code: "missing_symbol",
+                message: "Password must contain a symbol".to_string(),
+            });
+        }
+
+        let lowered = password.to_lowercase();
+        if is_common_password(&lowered) {
+            violations.push(PasswordViolation {
+                code: "common_password",
+                message: "Password is too common, choose a less predictable one".to_string(),
+            });
+        }
+        if personal
+            .iter()
+            .map(|value| value.split('@').next().unwrap_or("").to_lowercase())
+            .any(|value| value.len() >= 3 && lowered.contains(&value))
+        {
+            violations.push(PasswordViolation {
+                code: "contains_personal_info",
+                message: "Password must not contain your username or email".to_string(),
+            });
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+fn is_common_password(lowered: &str) -> bool {
+    static SET: OnceLock<HashSet<&'static str>> = OnceLock::new();
+    SET.get_or_init(|| {
+        COMMON_PASSWORDS
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect()
+    })
+    .contains(lowered)
+}
+
+/// Argon2id cost parameters, tunable through `ARGON2_*` environment variables. Defaults follow
+/// the OWASP recommendation (19 MiB, 2 iterations, 1 lane).
+fn argon2_params() -> Params {
+    dotenv().ok();
+    Params::new(
+        env_number("ARGON2_MEMORY_KIB", 19 * 1024) as u32,
+        env_number("ARGON2_ITERATIONS", 2) as u32,
+        env_number("ARGON2_PARALLELISM", 1) as u32,
+        None,
+    )
+    .unwrap_or_default()
+}
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, argon2_params())
+}
+
+/// Hashes with Argon2id. The result is a PHC string (`$argon2id$v=19$m=..,t=..,p=..$salt$hash`)
+/// that records the algorithm, version and cost, so older hashes stay verifiable after tuning.
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())?;
+    argon2()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hashed| hashed.to_string())
+}
+
+/// Verifies against either a current Argon2 hash or a legacy bcrypt one.
+pub fn verify_password(password: &str, hashed: &str) -> bool {
+    if is_bcrypt(hashed) {
+        return bcrypt::verify(password, hashed).unwrap_or(false);
+    }
+    match PasswordHash::new(hashed) {
+        Ok(parsed) => argon2()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Runs [`hash_password`] on the blocking thread pool. Hashing is slow on purpose and would
+/// otherwise stall every request sharing the async worker. `None` if hashing failed.
+pub async fn hash_password_in_pool(password: &str) -> Option<String> {
+    let password = password.to_string();
+    web::block(move || hash_password(&password))
+        .await
+        .ok()?
+        .ok()
+}
+
+/// Runs [`verify_password`] on the blocking thread pool, for the same reason.
+pub async fn verify_password_in_pool(password: &str, hashed: &str) -> bool {
+    let (password, hashed) = (password.to_string(), hashed.to_string());
+    web::block(move || verify_password(&password, &hashed))
+        .await
+        .unwrap_or(false)
+}
+
+/// Whether a stored hash was made with an older algorithm or different cost than we use now and
+/// should be replaced the next time we see the plaintext.
+pub fn needs_rehash(hashed: &str) -> bool {
+    if is_bcrypt(hashed) {
+        return true;
+    }
+    let parsed = match PasswordHash::new(hashed) {
+        Ok(parsed) => parsed,
+        Err(_) => return true,
+    };
+    let current = argon2_params();
+    parsed.algorithm != Algorithm::Argon2id.ident()
+        || parsed.version != Some(Version::V0x13.into())
+        || Params::try_from(&parsed)
+            .map(|params| {
+                params.m_cost() != current.m_cost()
+                    || params.t_cost() != current.t_cost()
+                    || params.p_cost() != current.p_cost()
+            })
+            .unwrap_or(true)
+}
+
+fn is_bcrypt(hashed: &str) -> bool {
+    hashed.starts_with("$2a$") || hashed.starts_with("$2b$") || hashed.starts_with("$2y$")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 16,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: true,
+        }
+    }
+
+    fn codes(password: &str, personal: &[&str]) -> Vec<&'static str> {
+        match policy().validate(password, personal) {
+            Ok(()) => vec![],
+            Err(violations) => violations.into_iter().map(|v| v.code).collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_a_password_meeting_every_rule() {
+        assert!(codes("Tr4vel-Mug", &["jane@example.com"]).is_empty());
+    }
+
+    #[test]
+    fn reports_every_violation_at_once() {
+        assert_eq!(
+            codes("abc", &[]),
+            vec![
+                "too_short",
+                "missing_uppercase",
+                "missing_digit",
+                "missing_symbol"
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_characters_for_the_minimum_and_bytes_for_the_maximum() {
+        assert!(!codes("Ää1-Ää1-", &[]).contains(&"too_short"));
+        assert!(codes("Ää1-Ää1-Ää1-Ää1-", &[]).contains(&"too_long"));
+    }
+
+    #[test]
+    fn rejects_common_passwords_in_any_case() {
+        assert!(codes("PassWord1", &[]).contains(&"common_password"));
+        assert!(is_common_password("qwerty123"));
+        assert!(!is_common_password("tr4vel-mug"));
+    }
+
+    #[test]
+    fn rejects_the_local_part_of_the_email() {
+        assert!(codes("Xjanedoe-1", &["JaneDoe@example.com"]).contains(&"contains_personal_info"));
+        // Too short to be worth matching.
+        assert!(!codes("Xjo-12345", &["jo@example.com"]).contains(&"contains_personal_info"));
+    }
+
+    #[test]
+    fn skips_rules_that_are_turned_off() {
+        let relaxed = PasswordPolicy {
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            ..policy()
+        };
+        assert!(relaxed.validate("lowercase only", &[]).is_ok());
+    }
+
+    #[test]
+    fn bcrypt_hashes_always_need_rehashing() {
+        assert!(needs_rehash("$2b$12$abcdefghijklmnopqrstuv"));
+        assert!(needs_rehash("not a hash"));
+    }
+}
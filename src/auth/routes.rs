@@ -11,6 +11,34 @@ pub fn auth_routes(cfg: &mut web::ServiceConfig) {
         web::resource("/login")
             .route(web::post().to(login_user))
     );
+    cfg.service(
+        web::resource("/verify-email")
+            .route(web::get().to(verify_email))
+    );
+    cfg.service(
+        web::resource("/verify-email/resend")
+        .wrap(Authentication)
+        .route(web::post().to(resend_verification_email))
+    );
+    cfg.service(
+        web::resource("/refresh")
+            .route(web::post().to(refresh_token))
+    );
+    cfg.service(
+        web::resource("/logout")
+        .wrap(Authentication)
+        .route(web::post().to(logout))
+    );
+    cfg.service(
+        web::resource("/sessions")
+        .wrap(Authentication)
+        .route(web::get().to(get_sessions))
+    );
+    cfg.service(
+        web::resource("/sessions/{session_id}")
+        .wrap(Authentication)
+        .route(web::delete().to(revoke_session))
+    );
     cfg.service(
         web::resource("/password-change")
         .wrap(Authentication)
@@ -23,7 +51,6 @@ pub fn auth_routes(cfg: &mut web::ServiceConfig) {
     );
     cfg.service(
         web::resource("/recovery-key")
-        .wrap(Authentication)
         .route(web::post().to(recovery_key))
     );
     cfg.service(
@@ -49,5 +76,33 @@ pub fn auth_routes(cfg: &mut web::ServiceConfig) {
         .wrap(Authentication)
         .route(web::post().to(generate_otp))
     );
+    cfg.service(
+        web::resource("/otp/backup-codes")
+        .wrap(Authentication)
+        .route(web::post().to(regenerate_backup_codes))
+    );
+    cfg.service(
+        web::resource("/oidc/{provider}/login")
+        .route(web::get().to(oidc_login))
+    );
+    cfg.service(
+        web::resource("/oidc/{provider}/callback")
+        .route(web::get().to(oidc_callback))
+    );
+    cfg.service(
+        web::resource("/oidc/{provider}/link")
+        .wrap(Authentication)
+        .route(web::post().to(oidc_link))
+    );
+    cfg.service(
+        web::resource("/identities")
+        .wrap(Authentication)
+        .route(web::get().to(get_identities))
+    );
+    cfg.service(
+        web::resource("/identities/{provider}")
+        .wrap(Authentication)
+        .route(web::delete().to(unlink_identity))
+    );
 
 }
\ No newline at end of file
@@ -0,0 +1,395 @@
+use crate::auth::keys;
+use crate::auth::model::{Claims, EmailVerificationClaims};
+use crate::prisma::{session, trusted_device, user, PrismaClient, RoleType};
+use actix_web::HttpRequest;
+use chrono::{Duration, Utc};
+use dotenv::dotenv;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+
+/// Lifetime of a 2FA-pending token in seconds.
+pub const TWO_FACTOR_TOKEN_TTL: i64 = 300;
+
+/// Lifetime of an impersonation token in seconds. There is no refresh token for it.
+pub const IMPERSONATION_TOKEN_TTL: i64 = 900;
+
+/// Lifetime of an access token in seconds, read from `JWT_EXPIRES_IN`.
+pub fn access_token_ttl() -> i64 {
+    dotenv().ok();
+    env::var("JWT_EXPIRES_IN")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(900)
+}
+
+/// Lifetime of a refresh token (and its session) in days, read from `REFRESH_TOKEN_EXPIRES_IN_DAYS`.
+pub fn refresh_token_ttl_days() -> i64 {
+    dotenv().ok();
+    env::var("REFRESH_TOKEN_EXPIRES_IN_DAYS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(30)
+}
+
+/// How long an emailed password reset link stays valid, read from `PASSWORD_RESET_TTL_MINUTES`.
+pub fn password_reset_ttl_minutes() -> i64 {
+    dotenv().ok();
+    env::var("PASSWORD_RESET_TTL_MINUTES")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(30)
+}
+
+/// Reset emails allowed per account per hour, read from `PASSWORD_RESET_MAX_PER_HOUR`.
+pub fn password_reset_max_per_hour() -> i64 {
+    dotenv().ok();
+    env::var("PASSWORD_RESET_MAX_PER_HOUR")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(3)
+}
+
+/// Random, URL-safe secret used for refresh tokens.
+pub fn generate_secret() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Refresh tokens are handed out as `<session id>.<secret>`; only the secret's hash is stored.
+pub fn split_refresh_token(token: &str) -> Option<(String, String)> {
+    let (session_id, secret) = token.split_once('.')?;
+    if session_id.is_empty() || secret.is_empty() {
+        return None;
+    }
+    Some((session_id.to_string(), secret.to_string()))
+}
+
+pub fn issue_access_token(
+    user_record: &user::Data,
+    session_id: &str,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let exp = Utc::now()
+        .checked_add_signed(Duration::seconds(access_token_ttl()))
+        .expect("valid timestamp")
+        .timestamp() as usize;
+
+    let claims = Claims {
+        sub: user_record.id.clone(),
+        exp,
+        is_admin: user_record.role == RoleType::Admin,
+        sid: Some(session_id.to_string()),
+        ver: user_record.token_version,
+        two_factor_pending: false,
+        api_key_id: None,
+        impersonator: None,
+    };
+
+    keys::store().sign(&claims)
+}
+
+/// Access token that lets an admin act as `user_record`. It carries no session, so it can't be
+/// refreshed, and it names the admin so sensitive actions can refuse it.
+pub fn issue_impersonation_token(
+    user_record: &user::Data,
+    impersonator_id: &str,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let exp = Utc::now()
+        .checked_add_signed(Duration::seconds(IMPERSONATION_TOKEN_TTL))
+        .expect("valid timestamp")
+        .timestamp() as usize;
+
+    let claims = Claims {
+        sub: user_record.id.clone(),
+        exp,
+        is_admin: user_record.role == RoleType::Admin,
+        sid: None,
+        ver: user_record.token_version,
+        two_factor_pending: false,
+        api_key_id: None,
+        impersonator: Some(impersonator_id.to_string()),
+    };
+
+    keys::store().sign(&claims)
+}
+
+/// Short-lived token handed out after a correct password for users with 2FA enabled. The
+/// authentication middleware refuses it; only `/otp/validate` exchanges it for a real session.
+pub fn issue_two_factor_token(
+    user_record: &user::Data,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let exp = Utc::now()
+        .checked_add_signed(Duration::seconds(TWO_FACTOR_TOKEN_TTL))
+        .expect("valid timestamp")
+        .timestamp() as usize;
+
+    let claims = Claims {
+        sub: user_record.id.clone(),
+        exp,
+        is_admin: false,
+        sid: None,
+        ver: user_record.token_version,
+        two_factor_pending: true,
+        api_key_id: None,
+        impersonator: None,
+    };
+
+    keys::store().sign(&claims)
+}
+
+/// How long an email verification link stays valid, read from `EMAIL_VERIFICATION_TTL_HOURS`.
+pub fn email_verification_ttl_hours() -> i64 {
+    dotenv().ok();
+    env::var("EMAIL_VERIFICATION_TTL_HOURS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(48)
+}
+
+/// Signed token for the verification link. It names the address being verified, so a link sent
+/// to a previous email stops working once the user changes it.
+pub fn issue_email_verification_token(
+    user_id: &str,
+    email: &str,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let exp = Utc::now()
+        .checked_add_signed(Duration::hours(email_verification_ttl_hours()))
+        .expect("valid timestamp")
+        .timestamp() as usize;
+
+    let claims = EmailVerificationClaims {
+        sub: user_id.to_string(),
+        email: email.to_string(),
+        exp,
+    };
+
+    keys::store().sign(&claims)
+}
+
+pub fn decode_email_verification_token(
+    token: &str,
+) -> Result<EmailVerificationClaims, jsonwebtoken::errors::Error> {
+    keys::store().verify::<EmailVerificationClaims>(token)
+}
+
+pub fn device_info(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(actix_web::http::header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+/// Reverse proxies allowed to report the client address, read once from the comma-separated
+/// `TRUSTED_PROXIES`. Empty unless configured, so forwarding headers are ignored by default.
+fn trusted_proxies() -> &'static [IpAddr] {
+    static PROXIES: OnceLock<Vec<IpAddr>> = OnceLock::new();
+    PROXIES.get_or_init(|| {
+        dotenv().ok();
+        env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|proxy| proxy.trim().parse().ok())
+            .collect()
+    })
+}
+
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    hop.parse()
+        .ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// Walks `X-Forwarded-For` from the right, starting at the TCP peer, for as long as the hop
+/// that reported the next address is a trusted proxy. Anything left of the first untrusted hop
+/// could have been written by the client and is ignored.
+fn forwarded_client(peer: IpAddr, forwarded_for: &[&str], trusted: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+    for hop in forwarded_for
+        .iter()
+        .flat_map(|header| header.split(','))
+        .rev()
+    {
+        if !trusted.contains(&client) {
+            break;
+        }
+        match parse_hop(hop) {
+            Some(ip) => client = ip,
+            None => break,
+        }
+    }
+    client
+}
+
+/// Address of the client: the TCP peer, or the address a trusted proxy forwarded for it.
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    let forwarded_for = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>();
+    Some(forwarded_client(peer, &forwarded_for, trusted_proxies()).to_string())
+}
+
+/// Creates a new session for `user_record` and returns it with the plaintext refresh token.
+pub async fn start_session(
+    prisma_client: &PrismaClient,
+    user_record: &user::Data,
+    req: &HttpRequest,
+) -> Result<(session::Data, String), prisma_client_rust::QueryError> {
+    let secret = generate_secret();
+    let expires_at = (Utc::now() + Duration::days(refresh_token_ttl_days())).fixed_offset();
+
+    let session_record = prisma_client
+        .session()
+        .create(
+            user::id::equals(user_record.id.clone()),
+            hash_token(&secret),
+            expires_at,
+            vec![
+                session::device_info::set(device_info(req)),
+                session::ip_address::set(client_ip(req)),
+            ],
+        )
+        .exec()
+        .await?;
+
+    let refresh_token = format!("{}.{}", session_record.id, secret);
+    Ok((session_record, refresh_token))
+}
+
+/// Replaces the refresh token of an existing session, invalidating the previous one.
+///
+/// The update only matches while the session is live and still holds `previous_hash`, so of two
+/// concurrent refreshes with the same token only one wins. `None` means the token was already
+/// rotated out or the session revoked, which callers treat as reuse.
+pub async fn rotate_session(
+    prisma_client: &PrismaClient,
+    session_id: &str,
+    previous_hash: &str,
+    req: &HttpRequest,
+) -> Result<Option<String>, prisma_client_rust::QueryError> {
+    let secret = generate_secret();
+    let updated = prisma_client
+        .session()
+        .update_many(
+            vec![
+                session::id::equals(session_id.to_string()),
+                session::refresh_token_hash::equals(previous_hash.to_string()),
+                session::revoked_at::equals(None),
+            ],
+            vec![
+                session::refresh_token_hash::set(hash_token(&secret)),
+                session::last_used_at::set(Utc::now().fixed_offset()),
+                session::ip_address::set(client_ip(req)),
+            ],
+        )
+        .exec()
+        .await?;
+
+    if updated == 0 {
+        return Ok(None);
+    }
+    Ok(Some(format!("{}.{}", session_id, secret)))
+}
+
+pub async fn revoke_session(
+    prisma_client: &PrismaClient,
+    session_id: &str,
+) -> Result<i64, prisma_client_rust::QueryError> {
+    prisma_client
+        .session()
+        .update_many(
+            vec![
+                session::id::equals(session_id.to_string()),
+                session::revoked_at::equals(None),
+            ],
+            vec![session::revoked_at::set(Some(Utc::now().fixed_offset()))],
+        )
+        .exec()
+        .await
+}
+
+/// Invalidates every access token and session of a user by bumping their token version, and
+/// forgets their trusted devices so the next login asks for a second factor again.
+pub async fn revoke_user_tokens(
+    prisma_client: &PrismaClient,
+    user_id: &str,
+) -> Result<(), prisma_client_rust::QueryError> {
+    prisma_client
+        .user()
+        .update(
+            user::id::equals(user_id.to_string()),
+            vec![user::token_version::increment(1)],
+        )
+        .exec()
+        .await?;
+
+    prisma_client
+        .session()
+        .update_many(
+            vec![
+                session::user_id::equals(user_id.to_string()),
+                session::revoked_at::equals(None),
+            ],
+            vec![session::revoked_at::set(Some(Utc::now().fixed_offset()))],
+        )
+        .exec()
+        .await?;
+
+    prisma_client
+        .trusted_device()
+        .delete_many(vec![trusted_device::user_id::equals(user_id.to_string())])
+        .exec()
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn ignores_forwarded_for_from_untrusted_peers() {
+        let client = forwarded_client(ip("203.0.113.7"), &["198.51.100.1"], &[]);
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn follows_trusted_proxies_only() {
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+        // The client prepended a fake hop; the proxies appended the real ones.
+        let client = forwarded_client(
+            ip("10.0.0.2"),
+            &["1.1.1.1, 198.51.100.9", "10.0.0.1"],
+            &trusted,
+        );
+        assert_eq!(client, ip("198.51.100.9"));
+    }
+
+    #[test]
+    fn stops_at_an_unparsable_hop() {
+        let trusted = [ip("10.0.0.1")];
+        let client = forwarded_client(ip("10.0.0.1"), &["unknown"], &trusted);
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn accepts_hops_with_ports() {
+        let trusted = [ip("10.0.0.1")];
+        let client = forwarded_client(ip("10.0.0.1"), &["198.51.100.9:4711"], &trusted);
+        assert_eq!(client, ip("198.51.100.9"));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::auth::token::{device_info, generate_secret, hash_token};
+use crate::prisma::{backup_code, trusted_device, user, PrismaClient};
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::HttpRequest;
+use chrono::{Duration, Utc};
+use rand::Rng;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+pub const BACKUP_CODE_COUNT: usize = 10;
+pub const TRUSTED_DEVICE_COOKIE: &str = "trusted_device";
+pub const TRUSTED_DEVICE_DAYS: i64 = 30;
+
+// No 0/O or 1/I/L so codes survive being read off paper.
+const BACKUP_CODE_ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+
+pub fn check_totp(user_record: &user::Data, code: &str) -> bool {
+    user_record
+        .opt_base_32
+        .clone()
+        .and_then(|otp_base32| Secret::Encoded(otp_base32).to_bytes().ok())
+        .and_then(|secret| TOTP::new(Algorithm::SHA1, 6, 1, 30, secret).ok())
+        .and_then(|totp| totp.check_current(code).ok())
+        .unwrap_or(false)
+}
+
+fn normalize_backup_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn generate_backup_code() -> String {
+    let mut rng = rand::thread_rng();
+    let code: String = (0..10)
+        .map(|_| BACKUP_CODE_ALPHABET[rng.gen_range(0..BACKUP_CODE_ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// Replaces the user's backup codes with a fresh set and returns them in plaintext. This is
+/// the only time the codes are ever visible.
+pub async fn regenerate_backup_codes(
+    prisma_client: &PrismaClient,
+    user_id: &str,
+) -> Result<Vec<String>, prisma_client_rust::QueryError> {
+    let codes = (0..BACKUP_CODE_COUNT)
+        .map(|_| generate_backup_code())
+        .collect::<Vec<_>>();
+
+    prisma_client
+        .backup_code()
+        .delete_many(vec![backup_code::user_id::equals(user_id.to_string())])
+        .exec()
+        .await?;
+
+    prisma_client
+        .backup_code()
+        .create_many(
+            codes
+                .iter()
+                .map(|code| {
+                    backup_code::create_unchecked(
+                        user_id.to_string(),
+                        hash_token(&normalize_backup_code(code)),
+                        vec![],
+                    )
+                })
+                .collect(),
+        )
+        .exec()
+        .await?;
+
+    Ok(codes)
+}
+
+/// Marks a matching unused backup code as used. Returns false if no such code exists.
+pub async fn consume_backup_code(
+    prisma_client: &PrismaClient,
+    user_id: &str,
+    code: &str,
+) -> Result<bool, prisma_client_rust::QueryError> {
+    let used = prisma_client
+        .backup_code()
+        .update_many(
+            vec![
+                backup_code::user_id::equals(user_id.to_string()),
+                backup_code::code_hash::equals(hash_token(&normalize_backup_code(code))),
+                backup_code::used_at::equals(None),
+            ],
+            vec![backup_code::used_at::set(Some(Utc::now().fixed_offset()))],
+        )
+        .exec()
+        .await?;
+
+    Ok(used > 0)
+}
+
+/// Registers the current device as trusted and returns the cookie that identifies it.
+pub async fn trust_device(
+    prisma_client: &PrismaClient,
+    user_id: &str,
+    req: &HttpRequest,
+) -> Result<Cookie<'static>, prisma_client_rust::QueryError> {
+    let secret = generate_secret();
+    prisma_client
+        .trusted_device()
+        .create(
+            user::id::equals(user_id.to_string()),
+            hash_token(&secret),
+            (Utc::now() + Duration::days(TRUSTED_DEVICE_DAYS)).fixed_offset(),
+            vec![trusted_device::device_info::set(device_info(req))],
+        )
+        .exec()
+        .await?;
+
+    Ok(Cookie::build(TRUSTED_DEVICE_COOKIE, secret)
+        .path("/api/auth")
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Strict)
+        .max_age(time::Duration::days(TRUSTED_DEVICE_DAYS))
+        .finish())
+}
+
+/// Whether the request carries a live trusted-device cookie issued to this user.
+pub async fn is_trusted_device(
+    prisma_client: &PrismaClient,
+    user_id: &str,
+    req: &HttpRequest,
+) -> bool {
+    let secret = match req.cookie(TRUSTED_DEVICE_COOKIE) {
+        Some(cookie) => cookie.value().to_string(),
+        None => return false,
+    };
+
+    matches!(
+        prisma_client
+            .trusted_device()
+            .find_first(vec![
+                trusted_device::user_id::equals(user_id.to_string()),
+                trusted_device::token_hash::equals(hash_token(&secret)),
+                trusted_device::expires_at::gt(Utc::now().fixed_offset()),
+            ])
+            .exec()
+            .await,
+        Ok(Some(_))
+    )
+}
+
+/// Drops backup codes and trusted devices, used when 2FA is turned off.
+pub async fn clear_two_factor_state(
+    prisma_client: &PrismaClient,
+    user_id: &str,
+) -> Result<(), prisma_client_rust::QueryError> {
+    prisma_client
+        .backup_code()
+        .delete_many(vec![backup_code::user_id::equals(user_id.to_string())])
+        .exec()
+        .await?;
+
+    prisma_client
+        .trusted_device()
+        .delete_many(vec![trusted_device::user_id::equals(user_id.to_string())])
+        .exec()
+        .await?;
+
+    Ok(())
+}
@@ -1,5 +1,9 @@
 use super::model::*;
+use crate::audit;
 use crate::auth::model::Claims;
+use crate::inventory;
+use crate::notification::{outbox, templates::Template};
+use crate::utils::{reject_api_key, reject_impersonation, require_verified_email_for_orders};
 use crate::prisma::PrismaClient;
 use crate::prisma::*;
 use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
@@ -12,78 +16,139 @@ pub async fn place_order(
     payload: web::Json<PlaceOrderPayload>,
 ) -> impl Responder {
     let claims = req.extensions().get::<Claims>().unwrap().clone();
+    if let Some(response) = reject_api_key(&claims) {
+        return response;
+    }
+    if let Some(response) = reject_impersonation(&claims) {
+        return response;
+    }
     let order_items = payload.productlist.clone();
     let payment_method = payload.paymentmethod.clone();
     let user_id = claims.sub.clone();
 
+    if require_verified_email_for_orders() {
+        match prisma_client
+            .user()
+            .find_unique(user::id::equals(user_id.clone()))
+            .exec()
+            .await
+        {
+            Ok(Some(customer)) if customer.email_verified => {}
+            Ok(_) => {
+                return HttpResponse::Forbidden()
+                    .json(json!({"error": "Please verify your email address before ordering."}))
+            }
+            Err(_) => {
+                return HttpResponse::InternalServerError()
+                    .json(json!({"error": "Database error"}))
+            }
+        }
+    }
+
     let mut total_price = 0.0;
-    let mut create_order_items_query = vec![];
+    let mut ordered_products = vec![];
     for item in &order_items {
+        if item.quantity <= 0 {
+            return HttpResponse::BadRequest().json(json!({"error": "Invalid quantity."}));
+        }
         if let Ok(Some(product)) = prisma_client
             .product()
-            .find_unique(product::id::equals(item.productid.clone()))
+            .find_first(vec![
+                product::id::equals(item.productid.clone()),
+                product::deleted_at::equals(None),
+            ])
             .exec()
             .await
         {
-            if product.stock >= item.quantity {
-                total_price += product.price * item.quantity as f64;
-                create_order_items_query.push(item);
-            } else {
-                return HttpResponse::BadRequest().json(json!({"error": "Not sufficient Product Stock"}));
-            }
+            total_price += product.price * item.quantity as f64;
+            ordered_products.push((product.name, item.quantity));
         } else {
             return HttpResponse::BadRequest().json(json!({"error": "Invalid product ID."}));
         }
     }
 
-    let order = prisma_client
-    .order()
-    .create(
-        user::id::equals(user_id.clone()),
-        "pending".to_string(),
-        total_price,
-        payment_method.clone(),
-        vec![],
-    )
-    .exec()
-    .await;
-
-    match order {
-        Ok(order) => {
-            // Create the OrderItem records
-            let order_items_result = prisma_client
-                .order_item()
-                .create_many(
-                    create_order_items_query
-                        .iter()
-                        .map(|item| {
-                            order_item::create_unchecked(
-                                order.id.clone(),
-                                item.productid.clone(),
-                                item.quantity,
-                                vec![],
-                            )
-                        })
-                        .collect(),
+    let lines = order_items
+        .iter()
+        .map(|item| (item.productid.clone(), item.quantity))
+        .collect::<Vec<_>>();
+    let actor_id = audit::actor_of(&req);
+    let customer_id = user_id.clone();
+    // The stock is held in the warehouse that can ship the whole order, so it can't be sold
+    // twice while the order waits for approval.
+    let placed: Result<Option<order::Data>, prisma_client_rust::QueryError> = prisma_client
+        ._transaction()
+        .run(|client| {
+            Box::pin(async move {
+                let holding = match inventory::pick_warehouse(&client, &lines).await? {
+                    Some(holding) => holding,
+                    None => return Ok(None),
+                };
+                let order = client
+                    .order()
+                    .create(
+                        user::id::equals(customer_id),
+                        "pending".to_string(),
+                        total_price,
+                        payment_method,
+                        vec![],
+                    )
+                    .exec()
+                    .await?;
+                client
+                    .order_item()
+                    .create_many(
+                        lines
+                            .iter()
+                            .map(|(product_id, quantity)| {
+                                order_item::create_unchecked(
+                                    order.id.clone(),
+                                    product_id.clone(),
+                                    *quantity,
+                                    vec![],
+                                )
+                            })
+                            .collect(),
+                    )
+                    .exec()
+                    .await?;
+                inventory::reserve(
+                    &client,
+                    &holding.id,
+                    &lines,
+                    &format!("order:{}", order.id),
+                    actor_id,
                 )
-                .exec()
-                .await;
+                .await?;
+                Ok(Some(order))
+            })
+        })
+        .await;
 
-            match order_items_result {
-                Ok(_) => HttpResponse::Ok().json(json!({"message": "Order placed successfully"})),
-                Err(err) => {
-                    // Handle error and possibly rollback the order creation
-                    prisma_client
-                        .order()
-                        .delete(order::id::equals(order.id.clone()))
-                        .exec()
-                        .await
-                        .ok(); // Ignore the error from rollback
-                    HttpResponse::InternalServerError().json(json!({"error": format!("Failed to create order items: {:?}", err)}))
-                }
+    match placed {
+        Ok(Some(order)) => {
+            if let Ok(Some(customer)) = prisma_client
+                .user()
+                .find_unique(user::id::equals(user_id.clone()))
+                .exec()
+                .await
+            {
+                let template = Template::OrderPlaced {
+                    name: customer.first_name,
+                    order_id: order.id.clone(),
+                    total: total_price,
+                    items: ordered_products,
+                };
+                outbox::enqueue(&prisma_client, &customer.email, template)
+                    .await
+                    .ok();
             }
+            HttpResponse::Ok().json(json!({"message": "Order placed successfully"}))
         }
-        Err(err) => HttpResponse::InternalServerError().json(json!({"error": format!("Failed to create order: {:?}", err)})),
+        Ok(None) => {
+            HttpResponse::BadRequest().json(json!({"error": "Not sufficient Product Stock"}))
+        }
+        Err(err) => HttpResponse::InternalServerError()
+            .json(json!({"error": format!("Failed to create order: {:?}", err)})),
     }
 }
 
@@ -92,6 +157,9 @@ pub async fn get_orders(
     prisma_client: web::Data<Arc<PrismaClient>>,
 ) -> impl Responder {
     if let Some(claims) = req.extensions().get::<Claims>(){
+        if let Some(response) = reject_api_key(claims) {
+            return response;
+        }
         let client_id = claims.sub.clone();
         println!("app passed here successfully, {}", client_id);
 
@@ -1,11 +1,16 @@
 use crate::admin::model::{CategoryResponse, GetProductsPagniationQuery, ProductResponse};
+use crate::prisma::{category, stock_level, warehouse};
 use crate::{prisma::PrismaClient, product};
 use actix_web::{web, HttpResponse, Responder};
 use serde_json::json;
 use std::sync::Arc;
 
 pub async fn get_categories(prisma_client: web::Data<Arc<PrismaClient>>) -> impl Responder {
-    let categories = prisma_client.category().find_many(vec![]).exec().await;
+    let categories = prisma_client
+        .category()
+        .find_many(vec![category::deleted_at::equals(None)])
+        .exec()
+        .await;
 
     match categories {
         Ok(categories) => {
@@ -32,13 +37,14 @@ pub async fn get_products(
     let category = query.category.clone();
     let id = query.id.clone();
 
-    let mut filter_conditions = vec![];
+    let mut filter_conditions = vec![product::deleted_at::equals(None)];
     if let Some(id) = id {
         filter_conditions.push(product::id::equals(id));
     }
     if let Some(category_id) = category {
         filter_conditions.push(product::categories::some(vec![
             crate::prisma::category::id::equals(category_id),
+            crate::prisma::category::deleted_at::equals(None),
         ]));
     }
 
@@ -55,7 +61,10 @@ pub async fn get_products(
     let products = prisma_client
         .product()
         .find_many(filter_conditions)
-        .with(product::categories::fetch(vec![]))
+        .with(product::categories::fetch(vec![category::deleted_at::equals(None)]))
+        .with(product::stock_levels::fetch(vec![
+            stock_level::warehouse::is(vec![warehouse::active::equals(true)]),
+        ]))
         .skip(offset)
         .take(limit)
         .exec()
@@ -63,20 +72,29 @@ pub async fn get_products(
         .unwrap_or(vec![]);
     let product_response = products
         .into_iter()
-        .map(|product| ProductResponse {
-            id: product.id,
-            name: product.name,
-            description: product.description,
-            price: product.price,
-            stock: product.stock,
-            imageurl: product.image_url,
-            category: product
-            .categories
-            .map_or(vec![], |cats| {
-                cats.into_iter()
-                    .map(|cat| cat.name.clone())
-                    .collect::<Vec<String>>()
-            }),
+        .map(|product| {
+            // Only active warehouses can ship, so stock held elsewhere isn't available. Where
+            // it is held stays internal.
+            let stock = product
+                .stock_levels
+                .unwrap_or_default()
+                .iter()
+                .map(|level| level.quantity.max(0))
+                .sum();
+            ProductResponse {
+                id: product.id,
+                sku: product.sku,
+                name: product.name,
+                description: product.description,
+                price: product.price,
+                stock,
+                imageurl: product.image_url,
+                category: product.categories.map_or(vec![], |cats| {
+                    cats.into_iter()
+                        .map(|cat| cat.name.clone())
+                        .collect::<Vec<String>>()
+                }),
+            }
         })
         .collect::<Vec<_>>();
     let response = json!({
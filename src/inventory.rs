@@ -0,0 +1,531 @@
+use crate::prisma::{
+    inventory_movement, product, stock_level, warehouse, MovementType, PrismaClient,
+};
+use prisma_client_rust::{raw, Direction, PrismaValue, QueryError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Why stock changed. Build it with `Movement::new` and the setters, then pass it to `apply`,
+/// `adjust_to` or `open`.
+pub struct Movement {
+    kind: MovementType,
+    warehouse_id: Option<String>,
+    reference: Option<String>,
+    actor_id: Option<String>,
+    note: Option<String>,
+}
+
+impl Movement {
+    pub fn new(kind: MovementType) -> Self {
+        Movement {
+            kind,
+            warehouse_id: None,
+            reference: None,
+            actor_id: None,
+            note: None,
+        }
+    }
+
+    /// Where the stock moved. Without one the default warehouse is used.
+    pub fn warehouse(mut self, warehouse_id: &str) -> Self {
+        self.warehouse_id = Some(warehouse_id.to_string());
+        self
+    }
+
+    /// What caused the change, e.g. `order:<id>`.
+    pub fn reference(mut self, reference: &str) -> Self {
+        self.reference = Some(reference.to_string());
+        self
+    }
+
+    pub fn actor(mut self, actor_id: Option<String>) -> Self {
+        self.actor_id = actor_id;
+        self
+    }
+
+    pub fn note(mut self, note: &str) -> Self {
+        self.note = Some(note.to_string());
+        self
+    }
+}
+
+/// The warehouse a movement applies to: the one it names, or else the default warehouse.
+/// `None` only when no default is set, in which case just `Product.stock` changes.
+async fn warehouse_of(
+    client: &PrismaClient,
+    movement: &Movement,
+) -> Result<Option<String>, QueryError> {
+    if let Some(warehouse_id) = &movement.warehouse_id {
+        return Ok(Some(warehouse_id.clone()));
+    }
+    Ok(default_warehouse(client)
+        .await?
+        .map(|warehouse| warehouse.id))
+}
+
+/// The warehouse that takes stock changes which don't name one.
+pub async fn default_warehouse(
+    client: &PrismaClient,
+) -> Result<Option<warehouse::Data>, QueryError> {
+    client
+        .warehouse()
+        .find_first(vec![warehouse::is_default::equals(true)])
+        .exec()
+        .await
+}
+
+/// Adds `quantity` to the stock level of the product in `warehouse_id`.
+async fn move_level(
+    client: &PrismaClient,
+    product_id: &str,
+    warehouse_id: &str,
+    quantity: i32,
+) -> Result<stock_level::Data, QueryError> {
+    client
+        .stock_level()
+        .upsert(
+            stock_level::product_id_warehouse_id(product_id.to_string(), warehouse_id.to_string()),
+            (
+                product::id::equals(product_id.to_string()),
+                warehouse::id::equals(warehouse_id.to_string()),
+                vec![stock_level::quantity::set(quantity)],
+            ),
+            vec![stock_level::quantity::increment(quantity)],
+        )
+        .exec()
+        .await
+}
+
+async fn record(
+    client: &PrismaClient,
+    product_id: &str,
+    warehouse_id: Option<String>,
+    quantity: i32,
+    stock_after: i32,
+    movement: Movement,
+) -> Result<inventory_movement::Data, QueryError> {
+    let mut params = vec![
+        inventory_movement::reference::set(movement.reference),
+        inventory_movement::actor_id::set(movement.actor_id),
+        inventory_movement::note::set(movement.note),
+    ];
+    if let Some(warehouse_id) = warehouse_id {
+        params.push(inventory_movement::warehouse::connect(
+            warehouse::id::equals(warehouse_id),
+        ));
+    }
+    client
+        .inventory_movement()
+        .create(
+            product::id::equals(product_id.to_string()),
+            movement.kind,
+            quantity,
+            stock_after,
+            params,
+        )
+        .exec()
+        .await
+}
+
+/// Changes stock by `quantity` (negative takes stock out), both in total and in the movement's
+/// warehouse, and records the movement. Run it in the same transaction as whatever caused the
+/// change so they can't drift apart.
+pub async fn apply(
+    client: &PrismaClient,
+    product_id: &str,
+    quantity: i32,
+    movement: Movement,
+) -> Result<inventory_movement::Data, QueryError> {
+    let warehouse_id = warehouse_of(client, &movement).await?;
+    if let Some(warehouse_id) = &warehouse_id {
+        move_level(client, product_id, warehouse_id, quantity).await?;
+    }
+    let updated = client
+        .product()
+        .update(
+            product::id::equals(product_id.to_string()),
+            vec![product::stock::increment(quantity)],
+        )
+        .exec()
+        .await?;
+    record(
+        client,
+        product_id,
+        warehouse_id,
+        quantity,
+        updated.stock,
+        movement,
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct StockRow {
+    stock: i32,
+}
+
+/// Current stock of a product, locking its row until the transaction ends so nothing else
+/// can change it in between. `None` when the product doesn't exist.
+pub async fn lock_stock(
+    client: &PrismaClient,
+    product_id: &str,
+) -> Result<Option<i32>, QueryError> {
+    let rows: Vec<StockRow> = client
+        ._query_raw(raw!(
+            r#"SELECT "stock" FROM "Product" WHERE "id" = {} FOR UPDATE"#,
+            PrismaValue::String(product_id.to_string())
+        ))
+        .exec()
+        .await?;
+    Ok(rows.first().map(|row| row.stock))
+}
+
+/// Sets stock to `stock`, recording the difference as one movement in the movement's
+/// warehouse. The row is locked while the difference is worked out, so a concurrent sale can't
+/// be overwritten. `None` when the product doesn't exist or its stock already matches. The
+/// inner error is the warehouse's stock when a reduction would take it below zero; nothing is
+/// written then.
+pub async fn adjust_to(
+    client: &PrismaClient,
+    product_id: &str,
+    stock: i32,
+    movement: Movement,
+) -> Result<Result<Option<inventory_movement::Data>, i32>, QueryError> {
+    let change = match lock_stock(client, product_id).await? {
+        Some(current) if current != stock => stock - current,
+        _ => return Ok(Ok(None)),
+    };
+    if change < 0 {
+        if let Some(warehouse_id) = warehouse_of(client, &movement).await? {
+            let held = level(client, product_id, &warehouse_id).await?;
+            if held + change < 0 {
+                return Ok(Err(held));
+            }
+        }
+    }
+    apply(client, product_id, change, movement)
+        .await
+        .map(|movement| Ok(Some(movement)))
+}
+
+/// Stock of a product in one warehouse; zero when it has never been there. Lock the product
+/// first with `lock_stock` if the answer has to hold until the transaction ends.
+pub async fn level(
+    client: &PrismaClient,
+    product_id: &str,
+    warehouse_id: &str,
+) -> Result<i32, QueryError> {
+    Ok(client
+        .stock_level()
+        .find_unique(stock_level::product_id_warehouse_id(
+            product_id.to_string(),
+            warehouse_id.to_string(),
+        ))
+        .exec()
+        .await?
+        .map_or(0, |level| level.quantity))
+}
+
+/// Sets the stock of a product in one warehouse, recording the difference as a movement there.
+/// Locks the product like `adjust_to`. `None` when the product doesn't exist or the level
+/// already matches.
+pub async fn adjust_level_to(
+    client: &PrismaClient,
+    product_id: &str,
+    warehouse_id: &str,
+    quantity: i32,
+    movement: Movement,
+) -> Result<Option<inventory_movement::Data>, QueryError> {
+    if lock_stock(client, product_id).await?.is_none() {
+        return Ok(None);
+    }
+    let current = level(client, product_id, warehouse_id).await?;
+    if current == quantity {
+        return Ok(None);
+    }
+    apply(
+        client,
+        product_id,
+        quantity - current,
+        movement.warehouse(warehouse_id),
+    )
+    .await
+    .map(Some)
+}
+
+/// Records the opening balance of a product created with `stock` already set, placing it in
+/// the movement's warehouse.
+pub async fn open(
+    client: &PrismaClient,
+    product_id: &str,
+    stock: i32,
+    movement: Movement,
+) -> Result<Option<inventory_movement::Data>, QueryError> {
+    if stock == 0 {
+        return Ok(None);
+    }
+    let warehouse_id = warehouse_of(client, &movement).await?;
+    if let Some(warehouse_id) = &warehouse_id {
+        move_level(client, product_id, warehouse_id, stock).await?;
+    }
+    record(client, product_id, warehouse_id, stock, stock, movement)
+        .await
+        .map(Some)
+}
+
+/// How much of each product the lines need in total, for orders listing a product twice.
+fn needed_per_product(lines: &[(String, i32)]) -> BTreeMap<String, i32> {
+    let mut needed = BTreeMap::new();
+    for (product_id, quantity) in lines {
+        *needed.entry(product_id.clone()).or_insert(0) += quantity;
+    }
+    needed
+}
+
+/// Index of the first warehouse whose `(product_id, quantity)` levels cover everything
+/// `needed` on their own, trying default warehouses before the others.
+fn fulfilling(
+    needed: &BTreeMap<String, i32>,
+    warehouses: &[(bool, Vec<(&str, i32)>)],
+) -> Option<usize> {
+    let covers = |levels: &[(&str, i32)]| {
+        needed.iter().all(|(product_id, quantity)| {
+            levels.iter().any(|(level_product_id, level)| {
+                *level_product_id == product_id.as_str() && level >= quantity
+            })
+        })
+    };
+    let defaults = warehouses
+        .iter()
+        .enumerate()
+        .filter(|(_, (is_default, _))| *is_default);
+    let others = warehouses
+        .iter()
+        .enumerate()
+        .filter(|(_, (is_default, _))| !*is_default);
+    defaults
+        .chain(others)
+        .find(|(_, (_, levels))| covers(levels))
+        .map(|(index, _)| index)
+}
+
+/// The active warehouse that can ship every `(product_id, quantity)` line on its own, trying
+/// the default warehouse first and then the others by code. The products are locked so the
+/// choice still holds when the stock is taken out in the same transaction. `None` when no
+/// single warehouse has enough of everything.
+pub async fn pick_warehouse(
+    client: &PrismaClient,
+    lines: &[(String, i32)],
+) -> Result<Option<warehouse::Data>, QueryError> {
+    let needed = needed_per_product(lines);
+    // Always lock in the same order so two approvals can't deadlock.
+    for product_id in needed.keys() {
+        lock_stock(client, product_id).await?;
+    }
+
+    let mut warehouses = client
+        .warehouse()
+        .find_many(vec![warehouse::active::equals(true)])
+        .with(warehouse::stock_levels::fetch(vec![
+            stock_level::product_id::in_vec(needed.keys().cloned().collect()),
+        ]))
+        .order_by(warehouse::code::order(Direction::Asc))
+        .exec()
+        .await?;
+    let candidates = warehouses
+        .iter()
+        .map(|warehouse| {
+            let levels = warehouse.stock_levels.as_deref().unwrap_or_default();
+            let levels = levels
+                .iter()
+                .map(|level| (level.product_id.as_str(), level.quantity))
+                .collect();
+            (warehouse.is_default, levels)
+        })
+        .collect::<Vec<_>>();
+    let index = fulfilling(&needed, &candidates);
+    Ok(index.map(|index| warehouses.swap_remove(index)))
+}
+
+/// Holds stock for every `(product_id, quantity)` line in `warehouse_id`, taking it out of
+/// what can be sold until the reservation is released. Pick the warehouse with
+/// `pick_warehouse` in the same transaction.
+pub async fn reserve(
+    client: &PrismaClient,
+    warehouse_id: &str,
+    lines: &[(String, i32)],
+    reference: &str,
+    actor_id: Option<String>,
+) -> Result<(), QueryError> {
+    for (product_id, quantity) in lines {
+        apply(
+            client,
+            product_id,
+            -quantity,
+            Movement::new(MovementType::Reservation)
+                .warehouse(warehouse_id)
+                .reference(reference)
+                .actor(actor_id.clone()),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Stock still held by the reservations made for `reference`, by product and warehouse.
+/// Releasing a reservation records the opposite movement, so what is held is minus their sum.
+pub async fn reserved(
+    client: &PrismaClient,
+    reference: &str,
+) -> Result<BTreeMap<(String, Option<String>), i32>, QueryError> {
+    let movements = client
+        .inventory_movement()
+        .find_many(vec![
+            inventory_movement::reference::equals(Some(reference.to_string())),
+            inventory_movement::kind::equals(MovementType::Reservation),
+        ])
+        .exec()
+        .await?;
+    let mut held = BTreeMap::new();
+    for movement in movements {
+        *held
+            .entry((movement.product_id, movement.warehouse_id))
+            .or_insert(0) -= movement.quantity;
+    }
+    held.retain(|_, quantity| *quantity != 0);
+    Ok(held)
+}
+
+#[derive(Deserialize)]
+struct CountRow {
+    count: i64,
+}
+
+/// How many reservations still hold stock taken out of `warehouse_id`, counted per reference
+/// and product. That stock no longer shows in the warehouse's levels but goes back into them
+/// when the reservation is released.
+pub async fn reservations_in(client: &PrismaClient, warehouse_id: &str) -> Result<i64, QueryError> {
+    let rows: Vec<CountRow> = client
+        ._query_raw(raw!(
+            r#"SELECT COUNT(*)::BIGINT AS "count" FROM (
+                SELECT 1 FROM "InventoryMovement"
+                WHERE "warehouseId" = {} AND "kind" = 'reservation'
+                GROUP BY "reference", "productId"
+                HAVING SUM("quantity") <> 0
+            ) held"#,
+            PrismaValue::String(warehouse_id.to_string())
+        ))
+        .exec()
+        .await?;
+    Ok(rows.first().map_or(0, |row| row.count))
+}
+
+/// Puts back whatever the reservations for `reference` still hold. Lock whatever the
+/// reference belongs to first, so two releases can't both put the stock back.
+pub async fn release(
+    client: &PrismaClient,
+    reference: &str,
+    actor_id: Option<String>,
+) -> Result<(), QueryError> {
+    for ((product_id, warehouse_id), quantity) in reserved(client, reference).await? {
+        let mut movement = Movement::new(MovementType::Reservation)
+            .reference(reference)
+            .actor(actor_id.clone());
+        if let Some(warehouse_id) = &warehouse_id {
+            movement = movement.warehouse(warehouse_id);
+        }
+        apply(client, &product_id, quantity, movement).await?;
+    }
+    Ok(())
+}
+
+/// A product whose stock doesn't match the sum of its movements or of its warehouse levels.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Discrepancy {
+    pub id: String,
+    pub name: String,
+    pub stock: i32,
+    pub ledger_stock: i64,
+    pub warehouse_stock: i64,
+}
+
+/// Every product whose ledger or warehouse levels don't sum to its current stock. Empty when
+/// all is well.
+pub async fn reconcile(client: &PrismaClient) -> Result<Vec<Discrepancy>, QueryError> {
+    client
+        ._query_raw(raw!(
+            r#"SELECT * FROM (
+                SELECT p."id", p."name", p."stock",
+                    (SELECT COALESCE(SUM(m."quantity"), 0) FROM "InventoryMovement" m
+                        WHERE m."productId" = p."id")::BIGINT AS "ledgerStock",
+                    (SELECT COALESCE(SUM(l."quantity"), 0) FROM "StockLevel" l
+                        WHERE l."productId" = p."id")::BIGINT AS "warehouseStock"
+                FROM "Product" p
+            ) totals
+            WHERE "stock" <> "ledgerStock" OR "stock" <> "warehouseStock"
+            ORDER BY "name""#
+        ))
+        .exec()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn needed(lines: &[(&str, i32)]) -> BTreeMap<String, i32> {
+        needed_per_product(
+            &lines
+                .iter()
+                .map(|(product_id, quantity)| (product_id.to_string(), *quantity))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn sums_lines_for_the_same_product() {
+        let needed = needed(&[("a", 2), ("b", 1), ("a", 3)]);
+        assert_eq!(needed.get("a"), Some(&5));
+        assert_eq!(needed.get("b"), Some(&1));
+    }
+
+    #[test]
+    fn prefers_the_default_warehouse() {
+        let warehouses = vec![(false, vec![("a", 10)]), (true, vec![("a", 10)])];
+        assert_eq!(fulfilling(&needed(&[("a", 2)]), &warehouses), Some(1));
+    }
+
+    #[test]
+    fn falls_back_to_the_first_other_warehouse_that_has_everything() {
+        let warehouses = vec![
+            (true, vec![("a", 10)]),
+            (false, vec![("a", 10), ("b", 0)]),
+            (false, vec![("a", 2), ("b", 1)]),
+            (false, vec![("a", 5), ("b", 5)]),
+        ];
+        assert_eq!(
+            fulfilling(&needed(&[("a", 2), ("b", 1)]), &warehouses),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn counts_repeated_lines_against_one_level() {
+        let warehouses = vec![(true, vec![("a", 4)]), (false, vec![("a", 5)])];
+        assert_eq!(
+            fulfilling(&needed(&[("a", 2), ("a", 3)]), &warehouses),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn none_when_no_single_warehouse_has_everything() {
+        let warehouses = vec![(true, vec![("a", 5)]), (false, vec![("b", 5)])];
+        assert_eq!(
+            fulfilling(&needed(&[("a", 1), ("b", 1)]), &warehouses),
+            None
+        );
+        assert_eq!(fulfilling(&needed(&[("a", 1)]), &[]), None);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::prisma::{job, JobStatus, PrismaClient};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use dotenv::dotenv;
+use prisma_client_rust::prisma_errors::query_engine::UniqueKeyViolation;
+use prisma_client_rust::{raw, PrismaValue, QueryError};
+use serde::Deserialize;
+use serde_json::Value;
+use std::env;
+
+/// Running jobs locked longer than this are assumed to belong to a worker that died.
+fn lock_timeout_minutes() -> i64 {
+    dotenv().ok();
+    env::var("JOB_LOCK_TIMEOUT_MINUTES")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(30)
+}
+
+/// How often a worker renews the lock of a job it is running: three times per lock timeout,
+/// so a slow job is never mistaken for one whose worker died.
+pub fn heartbeat_interval() -> std::time::Duration {
+    std::time::Duration::from_secs((lock_timeout_minutes().max(1) * 60 / 3) as u64)
+}
+
+/// 10s, 20s, 40s, ... capped at an hour.
+fn backoff(attempts: i32) -> Duration {
+    let seconds = 10i64.saturating_mul(1i64 << (attempts - 1).clamp(0, 12));
+    Duration::seconds(seconds.min(3600))
+}
+
+/// Queues a job of `kind`, due at `run_at` or right away.
+pub async fn enqueue(
+    prisma_client: &PrismaClient,
+    kind: &str,
+    payload: Value,
+    run_at: Option<DateTime<FixedOffset>>,
+) -> Result<job::Data, QueryError> {
+    let mut params = vec![];
+    if let Some(run_at) = run_at {
+        params.push(job::run_at::set(run_at));
+    }
+    prisma_client
+        .job()
+        .create(kind.to_string(), payload, params)
+        .exec()
+        .await
+}
+
+/// Like `enqueue`, but at most one job ever exists per `unique_key`. Returns `None` when one
+/// already does, e.g. because another instance queued the same scheduled run first.
+pub async fn enqueue_unique(
+    prisma_client: &PrismaClient,
+    kind: &str,
+    payload: Value,
+    unique_key: &str,
+) -> Result<Option<job::Data>, QueryError> {
+    match prisma_client
+        .job()
+        .create(
+            kind.to_string(),
+            payload,
+            vec![job::unique_key::set(Some(unique_key.to_string()))],
+        )
+        .exec()
+        .await
+    {
+        Ok(job_record) => Ok(Some(job_record)),
+        Err(err) if err.is_prisma_error::<UniqueKeyViolation>() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[derive(Deserialize)]
+struct Claimed {
+    id: String,
+}
+
+/// Locks up to `limit` due jobs for `worker_id` and counts the attempt. `SKIP LOCKED` lets
+/// several workers poll the table at once without ever claiming the same job.
+pub async fn claim(
+    prisma_client: &PrismaClient,
+    worker_id: &str,
+    limit: i64,
+) -> Result<Vec<job::Data>, QueryError> {
+    let claimed: Vec<Claimed> = prisma_client
+        ._query_raw(raw!(
+            r#"UPDATE "Job" SET "status" = 'running', "attempts" = "attempts" + 1,
+                "lockedAt" = NOW(), "lockedBy" = {}, "updatedAt" = NOW()
+            WHERE "id" IN (
+                SELECT "id" FROM "Job"
+                WHERE "status" = 'queued' AND "runAt" <= NOW()
+                ORDER BY "runAt"
+                LIMIT {}
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING "id""#,
+            PrismaValue::String(worker_id.to_string()),
+            PrismaValue::Int(limit)
+        ))
+        .exec()
+        .await?;
+    if claimed.is_empty() {
+        return Ok(vec![]);
+    }
+
+    prisma_client
+        .job()
+        .find_many(vec![job::id::in_vec(
+            claimed.into_iter().map(|c| c.id).collect(),
+        )])
+        .order_by(job::run_at::order(prisma_client_rust::Direction::Asc))
+        .exec()
+        .await
+}
+
+/// Puts jobs whose worker stopped holding them back in the queue, or dead-letters them when
+/// they have used up their attempts.
+pub async fn release_stale(prisma_client: &PrismaClient) -> Result<i64, QueryError> {
+    prisma_client
+        ._execute_raw(raw!(
+            r#"UPDATE "Job" SET
+                "status" = CASE WHEN "attempts" >= "maxAttempts"
+                    THEN 'dead'::"JobStatus" ELSE 'queued'::"JobStatus" END,
+                "finishedAt" = CASE WHEN "attempts" >= "maxAttempts" THEN NOW() ELSE NULL END,
+                "lastError" = 'Worker lock expired',
+                "lockedAt" = NULL, "lockedBy" = NULL, "updatedAt" = NOW()
+            WHERE "status" = 'running' AND "lockedAt" < NOW() - make_interval(mins => {})"#,
+            PrismaValue::Int(lock_timeout_minutes())
+        ))
+        .exec()
+        .await
+}
+
+/// Only the worker still holding the job may finish it; a job whose lock expired and was
+/// claimed again belongs to the new worker.
+fn held_by(job_record: &job::Data, worker_id: &str) -> Vec<job::WhereParam> {
+    vec![
+        job::id::equals(job_record.id.clone()),
+        job::status::equals(JobStatus::Running),
+        job::locked_by::equals(Some(worker_id.to_string())),
+    ]
+}
+
+/// Renews the lock on a job the worker is still running. Returns `false` once the worker no
+/// longer holds it.
+pub async fn heartbeat(
+    prisma_client: &PrismaClient,
+    job_record: &job::Data,
+    worker_id: &str,
+) -> Result<bool, QueryError> {
+    prisma_client
+        .job()
+        .update_many(
+            held_by(job_record, worker_id),
+            vec![job::locked_at::set(Some(Utc::now().fixed_offset()))],
+        )
+        .exec()
+        .await
+        .map(|renewed| renewed > 0)
+}
+
+pub async fn complete(
+    prisma_client: &PrismaClient,
+    job_record: &job::Data,
+    worker_id: &str,
+    result: Value,
+) -> Result<(), QueryError> {
+    prisma_client
+        .job()
+        .update_many(
+            held_by(job_record, worker_id),
+            vec![
+                job::status::set(JobStatus::Succeeded),
+                job::result::set(Some(result)),
+                job::last_error::set(None),
+                job::finished_at::set(Some(Utc::now().fixed_offset())),
+                job::locked_at::set(None),
+                job::locked_by::set(None),
+            ],
+        )
+        .exec()
+        .await
+        .map(|_| ())
+}
+
+/// Schedules a retry with backoff, or dead-letters the job once it has no attempts left.
+pub async fn fail(
+    prisma_client: &PrismaClient,
+    job_record: &job::Data,
+    worker_id: &str,
+    error: String,
+) -> Result<(), QueryError> {
+    let now = Utc::now();
+    let update = if job_record.attempts >= job_record.max_attempts {
+        vec![
+            job::status::set(JobStatus::Dead),
+            job::last_error::set(Some(error)),
+            job::finished_at::set(Some(now.fixed_offset())),
+            job::locked_at::set(None),
+            job::locked_by::set(None),
+        ]
+    } else {
+        vec![
+            job::status::set(JobStatus::Queued),
+            job::last_error::set(Some(error)),
+            job::run_at::set((now + backoff(job_record.attempts)).fixed_offset()),
+            job::locked_at::set(None),
+            job::locked_by::set(None),
+        ]
+    };
+    prisma_client
+        .job()
+        .update_many(held_by(job_record, worker_id), update)
+        .exec()
+        .await
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_from_ten_seconds() {
+        assert_eq!(backoff(1), Duration::seconds(10));
+        assert_eq!(backoff(2), Duration::seconds(20));
+        assert_eq!(backoff(3), Duration::seconds(40));
+        assert_eq!(backoff(9), Duration::seconds(2560));
+    }
+
+    #[test]
+    fn backoff_is_capped_at_an_hour() {
+        assert_eq!(backoff(10), Duration::seconds(3600));
+        assert_eq!(backoff(i32::MAX), Duration::seconds(3600));
+    }
+
+    #[test]
+    fn backoff_treats_attempts_below_one_as_the_first() {
+        assert_eq!(backoff(0), Duration::seconds(10));
+        assert_eq!(backoff(-3), Duration::seconds(10));
+    }
+}
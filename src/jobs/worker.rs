@@ -0,0 +1,138 @@
+use super::queue;
+use crate::admin::handler::catalog;
+use crate::prisma::{job, PrismaClient};
+use crate::scheduler::tasks;
+use dotenv::dotenv;
+use serde_json::Value;
+use std::env;
+use std::sync::Arc;
+
+fn poll_interval() -> std::time::Duration {
+    dotenv().ok();
+    let seconds = env::var("JOB_POLL_SECONDS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(2);
+    std::time::Duration::from_secs(seconds)
+}
+
+fn batch_size() -> i64 {
+    dotenv().ok();
+    env::var("JOB_BATCH_SIZE")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(5)
+}
+
+/// Runs one job by kind. A kind nobody handles fails like any other error and ends up dead
+/// once its attempts run out.
+async fn run(prisma_client: &PrismaClient, job_record: &job::Data) -> Result<Value, String> {
+    match job_record.kind.as_str() {
+        catalog::IMPORT_JOB => catalog::run_import_job(prisma_client, &job_record.payload).await,
+        tasks::EXPIRE_ORDERS_JOB => tasks::expire_stale_orders(prisma_client).await,
+        tasks::LOW_STOCK_DIGEST_JOB => tasks::low_stock_digest(prisma_client).await,
+        other => Err(format!("No handler for job kind {}", other)),
+    }
+}
+
+/// Runs one job in its own task, renewing its lock until it finishes. A panic in the handler
+/// ends only that task and is reported as the job's error.
+async fn run_isolated(
+    prisma_client: &Arc<PrismaClient>,
+    job_record: &job::Data,
+    worker_id: &str,
+) -> Result<Value, String> {
+    let heartbeat = {
+        let (prisma_client, job_record, worker_id) = (
+            Arc::clone(prisma_client),
+            job_record.clone(),
+            worker_id.to_string(),
+        );
+        actix_web::rt::spawn(async move {
+            let interval = queue::heartbeat_interval();
+            loop {
+                actix_web::rt::time::sleep(interval).await;
+                match queue::heartbeat(&prisma_client, &job_record, &worker_id).await {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(err) => println!("job {} heartbeat failed: {:?}", job_record.id, err),
+                }
+            }
+        })
+    };
+
+    let task = {
+        let (prisma_client, job_record) = (Arc::clone(prisma_client), job_record.clone());
+        actix_web::rt::spawn(async move { run(&prisma_client, &job_record).await })
+    };
+    let outcome = task.await;
+    heartbeat.abort();
+
+    outcome.unwrap_or_else(|err| {
+        let reason = if err.is_panic() {
+            let panic = err.into_panic();
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown cause".to_string());
+            format!("Job panicked: {}", message)
+        } else {
+            "Job was cancelled".to_string()
+        };
+        println!(
+            "job {} ({}) failed: {}",
+            job_record.id, job_record.kind, reason
+        );
+        Err(reason)
+    })
+}
+
+/// Claims a batch of due jobs and runs them one after another. Returns how many were claimed.
+pub async fn run_due(
+    prisma_client: &Arc<PrismaClient>,
+    worker_id: &str,
+) -> Result<usize, prisma_client_rust::QueryError> {
+    queue::release_stale(prisma_client).await?;
+    let jobs = queue::claim(prisma_client, worker_id, batch_size()).await?;
+    for job_record in &jobs {
+        // The whole batch was locked when it was claimed. A job left waiting behind a slow one
+        // may have been released and claimed by another worker since, so renew the lock first
+        // and leave the job alone when it is no longer ours.
+        if !queue::heartbeat(prisma_client, job_record, worker_id).await? {
+            println!(
+                "job {} is no longer held by this worker; skipping",
+                job_record.id
+            );
+            continue;
+        }
+        match run_isolated(prisma_client, job_record, worker_id).await {
+            Ok(result) => queue::complete(prisma_client, job_record, worker_id, result).await?,
+            Err(err) => queue::fail(prisma_client, job_record, worker_id, err).await?,
+        }
+    }
+    Ok(jobs.len())
+}
+
+/// Works through the queue for the lifetime of the server. The worker only sleeps when the
+/// queue is empty, so a backlog drains without waiting a poll interval per batch.
+pub fn spawn_worker(prisma_client: Arc<PrismaClient>) {
+    let worker_id = format!(
+        "{}-{}",
+        env::var("HOSTNAME").unwrap_or_else(|_| "worker".to_string()),
+        std::process::id()
+    );
+    actix_web::rt::spawn(async move {
+        let interval = poll_interval();
+        loop {
+            match run_due(&prisma_client, &worker_id).await {
+                Ok(0) => actix_web::rt::time::sleep(interval).await,
+                Ok(_) => {}
+                Err(err) => {
+                    println!("job worker failed: {:?}", err);
+                    actix_web::rt::time::sleep(interval).await;
+                }
+            }
+        }
+    });
+}
@@ -1,13 +1,20 @@
 mod admin;
+mod audit;
 mod auth;
 mod client;
 mod general;
+mod inventory;
+mod jobs;
+mod notification;
 mod prisma;
+mod rate_limit;
+mod scheduler;
 mod utils;
 
 use actix_cors::Cors;
 use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
 use prisma::*;
+use rate_limit::{KeyBy, RateLimiter};
 use std::sync::Arc;
 use utils::Authentication;
 
@@ -20,25 +27,67 @@ async fn hello() -> impl Responder {
 async fn main() -> std::io::Result<()> {
     let prisma_client = PrismaClient::_builder().build().await.unwrap();
     let prisma_client = Arc::new(prisma_client);
+    // Load the signing keys up front so a bad key configuration fails at startup.
+    auth::keys::store();
+    notification::outbox::spawn_dispatcher(
+        Arc::clone(&prisma_client),
+        notification::notifier::from_env(),
+    );
+    jobs::worker::spawn_worker(Arc::clone(&prisma_client));
+    scheduler::spawn_scheduler(Arc::clone(&prisma_client));
+    let rate_limit_store = rate_limit::store_from_env(Arc::clone(&prisma_client));
     HttpServer::new(move || {
-        let cors = Cors::default().allowed_methods(vec!["GET", "POST", "PUT", "DELETE"]);
+        let cors = Cors::default().allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"]);
         App::new()
             .wrap(cors)
             .service(hello)
+            .service(
+                web::resource("/.well-known/jwks.json").route(web::get().to(auth::handler::jwks)),
+            )
             .app_data(web::Data::new(Arc::clone(&prisma_client)))
-            .service(web::scope("/api/auth").configure(auth::routes::auth_routes))
+            .service(
+                web::scope("/api/auth")
+                    .wrap(RateLimiter::new(
+                        "auth",
+                        "20/60",
+                        KeyBy::Ip,
+                        Arc::clone(&rate_limit_store),
+                    ))
+                    .configure(auth::routes::auth_routes),
+            )
             .service(
                 web::scope("api/admin")
                     .wrap(Authentication)
                     // .wrap(Authorization)
+                    .wrap(RateLimiter::new(
+                        "admin",
+                        "300/60",
+                        KeyBy::User,
+                        Arc::clone(&rate_limit_store),
+                    ))
                     .configure(admin::routes::admin_routes),
             )
             .service(
                 web::scope("api/client")
                 .wrap(Authentication)
+                .wrap(RateLimiter::new(
+                    "client",
+                    "120/60",
+                    KeyBy::User,
+                    Arc::clone(&rate_limit_store),
+                ))
                 .configure(client::routes::client_routes)
             )
-            .service(web::scope("api").configure(general::routes::general_routes))
+            .service(
+                web::scope("api")
+                    .wrap(RateLimiter::new(
+                        "public",
+                        "300/60",
+                        KeyBy::Ip,
+                        Arc::clone(&rate_limit_store),
+                    ))
+                    .configure(general::routes::general_routes),
+            )
     })
     .bind("127.0.0.1:8080")?
     .run()
@@ -0,0 +1,3 @@
+pub mod notifier;
+pub mod outbox;
+pub mod templates;
@@ -0,0 +1,134 @@
+use chrono::Utc;
+use dotenv::dotenv;
+use futures_util::future::BoxFuture;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait Notifier: Send + Sync {
+    fn send<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<(), String>>;
+}
+
+/// Delivers messages over SMTP with STARTTLS.
+pub struct SmtpNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpNotifier {
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, String)>,
+        from: &str,
+    ) -> Result<Self, String> {
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+            .map_err(|e| e.to_string())?
+            .port(port);
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(SmtpNotifier {
+            transport: builder.build(),
+            from: from
+                .parse()
+                .map_err(|e: lettre::address::AddressError| e.to_string())?,
+        })
+    }
+}
+
+impl Notifier for SmtpNotifier {
+    fn send<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let to: Mailbox = message
+                .to
+                .parse()
+                .map_err(|e: lettre::address::AddressError| e.to_string())?;
+            let email = lettre::Message::builder()
+                .from(self.from.clone())
+                .to(to)
+                .subject(message.subject.clone())
+                .body(message.body.clone())
+                .map_err(|e| e.to_string())?;
+
+            self.transport
+                .send(email)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+}
+
+/// Writes every outgoing message to a file in `MAIL_DIR` (or just prints it when unset)
+/// instead of delivering it. Meant for local development and tests.
+pub struct FileNotifier {
+    pub dir: Option<PathBuf>,
+}
+
+impl Notifier for FileNotifier {
+    fn send<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let contents = format!(
+                "To: {}\nSubject: {}\nDate: {}\n\n{}\n",
+                message.to,
+                message.subject,
+                Utc::now().to_rfc2822(),
+                message.body
+            );
+
+            match &self.dir {
+                Some(dir) => {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                    let file_name = format!(
+                        "{}-{:08x}.eml",
+                        Utc::now().format("%Y%m%d%H%M%S%3f"),
+                        rand::random::<u32>()
+                    );
+                    fs::write(dir.join(file_name), contents).map_err(|e| e.to_string())
+                }
+                None => {
+                    println!("outgoing email\n{}", contents);
+                    Ok(())
+                }
+            }
+        })
+    }
+}
+
+/// Picks the notifier from `NOTIFIER` (`smtp` or `file`, the default).
+pub fn from_env() -> Arc<dyn Notifier> {
+    dotenv().ok();
+    match env::var("NOTIFIER").as_deref() {
+        Ok("smtp") => {
+            let host = env::var("SMTP_HOST").expect("SMTP_HOST must be set");
+            let port = env::var("SMTP_PORT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(587);
+            let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+                (Ok(username), Ok(password)) => Some((username, password)),
+                _ => None,
+            };
+            let from = env::var("SMTP_FROM").expect("SMTP_FROM must be set");
+            Arc::new(
+                SmtpNotifier::new(&host, port, credentials, &from)
+                    .expect("invalid SMTP configuration"),
+            )
+        }
+        _ => Arc::new(FileNotifier {
+            dir: env::var("MAIL_DIR").ok().map(PathBuf::from),
+        }),
+    }
+}
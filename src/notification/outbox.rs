@@ -0,0 +1,175 @@
+use super::notifier::{Message, Notifier};
+use super::templates::Template;
+use crate::prisma::{outbox_message, OutboxStatus, PrismaClient};
+use chrono::{Duration, Utc};
+use dotenv::dotenv;
+use std::env;
+use std::sync::Arc;
+
+// Messages stuck in `sending` this long are assumed to belong to a crashed instance.
+const STALE_SENDING_MINUTES: i64 = 10;
+const BATCH_SIZE: i64 = 20;
+// Replaces the body of a message carrying a secret once it has been sent or given up on.
+const REDACTED_BODY: &str = "[redacted]";
+
+fn max_attempts() -> i32 {
+    dotenv().ok();
+    env::var("NOTIFICATION_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(8)
+}
+
+fn poll_interval() -> std::time::Duration {
+    dotenv().ok();
+    let seconds = env::var("NOTIFICATION_POLL_SECONDS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(5);
+    std::time::Duration::from_secs(seconds)
+}
+
+/// 30s, 1m, 2m, ... capped at an hour.
+fn backoff(attempts: i32) -> Duration {
+    let seconds = 30i64.saturating_mul(1i64 << attempts.clamp(0, 7));
+    Duration::seconds(seconds.min(3600))
+}
+
+/// Renders `template` and stores it in the outbox. Delivery happens in the background, so a
+/// mailer outage only delays the message. Bodies carrying a one-time link are blanked once the
+/// message is sent or has failed for good.
+pub async fn enqueue(
+    prisma_client: &PrismaClient,
+    recipient: &str,
+    template: Template,
+) -> Result<outbox_message::Data, prisma_client_rust::QueryError> {
+    let (subject, body) = template.render();
+    prisma_client
+        .outbox_message()
+        .create(
+            template.key().to_string(),
+            recipient.to_string(),
+            subject,
+            body,
+            vec![],
+        )
+        .exec()
+        .await
+}
+
+/// Sends every message that is due. Each message is claimed first so that several server
+/// instances can share the outbox without sending anything twice.
+pub async fn dispatch_due(
+    prisma_client: &PrismaClient,
+    notifier: &dyn Notifier,
+) -> Result<usize, prisma_client_rust::QueryError> {
+    let now = Utc::now().fixed_offset();
+
+    prisma_client
+        .outbox_message()
+        .update_many(
+            vec![
+                outbox_message::status::equals(OutboxStatus::Sending),
+                outbox_message::updated_at::lt(now - Duration::minutes(STALE_SENDING_MINUTES)),
+            ],
+            vec![outbox_message::status::set(OutboxStatus::Pending)],
+        )
+        .exec()
+        .await?;
+
+    let due = prisma_client
+        .outbox_message()
+        .find_many(vec![
+            outbox_message::status::equals(OutboxStatus::Pending),
+            outbox_message::next_attempt_at::lte(now),
+        ])
+        .order_by(outbox_message::next_attempt_at::order(
+            prisma_client_rust::Direction::Asc,
+        ))
+        .take(BATCH_SIZE)
+        .exec()
+        .await?;
+
+    let mut sent = 0;
+    for entry in due {
+        let claimed = prisma_client
+            .outbox_message()
+            .update_many(
+                vec![
+                    outbox_message::id::equals(entry.id.clone()),
+                    outbox_message::status::equals(OutboxStatus::Pending),
+                ],
+                vec![outbox_message::status::set(OutboxStatus::Sending)],
+            )
+            .exec()
+            .await?;
+        if claimed == 0 {
+            continue;
+        }
+
+        let message = Message {
+            to: entry.recipient.clone(),
+            subject: entry.subject.clone(),
+            body: entry.body.clone(),
+        };
+        let attempts = entry.attempts + 1;
+        // `finished` is set once the message won't be tried again.
+        let (finished, mut update) = match notifier.send(&message).await {
+            Ok(_) => {
+                sent += 1;
+                (
+                    true,
+                    vec![
+                        outbox_message::status::set(OutboxStatus::Sent),
+                        outbox_message::attempts::set(attempts),
+                        outbox_message::sent_at::set(Some(Utc::now().fixed_offset())),
+                        outbox_message::last_error::set(None),
+                    ],
+                )
+            }
+            Err(err) if attempts >= max_attempts() => (
+                true,
+                vec![
+                    outbox_message::status::set(OutboxStatus::Failed),
+                    outbox_message::attempts::set(attempts),
+                    outbox_message::last_error::set(Some(err)),
+                ],
+            ),
+            Err(err) => (
+                false,
+                vec![
+                    outbox_message::status::set(OutboxStatus::Pending),
+                    outbox_message::attempts::set(attempts),
+                    outbox_message::last_error::set(Some(err)),
+                    outbox_message::next_attempt_at::set(
+                        (Utc::now() + backoff(attempts)).fixed_offset(),
+                    ),
+                ],
+            ),
+        };
+        if finished && Template::carries_secret(&entry.template) {
+            update.push(outbox_message::body::set(REDACTED_BODY.to_string()));
+        }
+
+        prisma_client
+            .outbox_message()
+            .update(outbox_message::id::equals(entry.id), update)
+            .exec()
+            .await?;
+    }
+
+    Ok(sent)
+}
+
+/// Polls the outbox for the lifetime of the server.
+pub fn spawn_dispatcher(prisma_client: Arc<PrismaClient>, notifier: Arc<dyn Notifier>) {
+    actix_web::rt::spawn(async move {
+        let interval = poll_interval();
+        loop {
+            if let Err(err) = dispatch_due(&prisma_client, notifier.as_ref()).await {
+                println!("notification dispatch failed: {:?}", err);
+            }
+            actix_web::rt::time::sleep(interval).await;
+        }
+    });
+}
@@ -0,0 +1,195 @@
+use dotenv::dotenv;
+use std::env;
+
+/// Transactional messages the store sends. Each variant carries exactly what its text needs.
+pub enum Template {
+    Welcome {
+        name: String,
+        verification_token: String,
+    },
+    VerifyEmail {
+        name: String,
+        verification_token: String,
+    },
+    OrderPlaced {
+        name: String,
+        order_id: String,
+        total: f64,
+        items: Vec<(String, i32)>,
+    },
+    OrderApproved {
+        name: String,
+        order_id: String,
+    },
+    PasswordReset {
+        name: String,
+        token: String,
+        expires_in_minutes: i64,
+    },
+    TwoFactorEnabled {
+        name: String,
+    },
+    OrderExpired {
+        name: String,
+        order_id: String,
+    },
+    /// Sent to admins; `products` holds name, stock and threshold.
+    LowStockDigest {
+        name: String,
+        products: Vec<(String, i32, i32)>,
+    },
+}
+
+impl Template {
+    pub fn key(&self) -> &'static str {
+        match self {
+            Template::Welcome { .. } => "welcome",
+            Template::VerifyEmail { .. } => "verify_email",
+            Template::OrderPlaced { .. } => "order_placed",
+            Template::OrderApproved { .. } => "order_approved",
+            Template::PasswordReset { .. } => "password_reset",
+            Template::TwoFactorEnabled { .. } => "two_factor_enabled",
+            Template::OrderExpired { .. } => "order_expired",
+            Template::LowStockDigest { .. } => "low_stock_digest",
+        }
+    }
+
+    /// Whether messages of the template with `key` carry a one-time secret, such as a reset or
+    /// email verification link, that shouldn't outlive their delivery.
+    pub fn carries_secret(key: &str) -> bool {
+        matches!(key, "welcome" | "verify_email" | "password_reset")
+    }
+
+    /// Returns the subject and plain-text body.
+    pub fn render(&self) -> (String, String) {
+        let store = store_name();
+        let (name, subject, content) = match self {
+            Template::Welcome {
+                name,
+                verification_token,
+            } => (
+                name,
+                format!("Welcome to {}", store),
+                format!(
+                    "Your account is ready. Please confirm your email address by opening the \
+                     link below:\n\n{}\n\nThen sign in at {}/login and start shopping.",
+                    verification_link(verification_token),
+                    app_url()
+                ),
+            ),
+            Template::VerifyEmail {
+                name,
+                verification_token,
+            } => (
+                name,
+                "Confirm your email address".to_string(),
+                format!(
+                    "Please confirm this is your email address by opening the link below:\n\n\
+                     {}\n\nIf you didn't request this, you can ignore this email.",
+                    verification_link(verification_token)
+                ),
+            ),
+            Template::OrderPlaced {
+                name,
+                order_id,
+                total,
+                items,
+            } => {
+                let lines = items
+                    .iter()
+                    .map(|(product, quantity)| format!("  - {} x {}", quantity, product))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                (
+                    name,
+                    format!("We received your order {}", order_id),
+                    format!(
+                        "Thanks for your order. We'll let you know once it has been approved.\n\n\
+                         Order: {}\n{}\n\nTotal: {:.2}",
+                        order_id, lines, total
+                    ),
+                )
+            }
+            Template::OrderApproved { name, order_id } => (
+                name,
+                format!("Your order {} has been approved", order_id),
+                format!(
+                    "Good news: order {} has been approved and is being prepared.\n\n\
+                     You can follow it at {}/orders.",
+                    order_id,
+                    app_url()
+                ),
+            ),
+            Template::PasswordReset {
+                name,
+                token,
+                expires_in_minutes,
+            } => (
+                name,
+                "Reset your password".to_string(),
+                format!(
+                    "Use the link below to choose a new password. It expires in {} minutes and \
+                     can only be used once.\n\n{}/reset-password?token={}\n\n\
+                     If you didn't ask for this, you can ignore this email.",
+                    expires_in_minutes,
+                    app_url(),
+                    token
+                ),
+            ),
+            Template::TwoFactorEnabled { name } => (
+                name,
+                "Two-factor authentication enabled".to_string(),
+                "Two-factor authentication is now on for your account. Keep your backup codes \
+                 somewhere safe.\n\nIf this wasn't you, reset your password right away."
+                    .to_string(),
+            ),
+            Template::OrderExpired { name, order_id } => (
+                name,
+                format!("Your order {} has expired", order_id),
+                format!(
+                    "Order {} wasn't approved in time and has been cancelled. You haven't been \
+                     charged for it.\n\nYou can place it again at {}.",
+                    order_id,
+                    app_url()
+                ),
+            ),
+            Template::LowStockDigest { name, products } => {
+                let lines = products
+                    .iter()
+                    .map(|(product, stock, threshold)| {
+                        format!("  - {}: {} left (threshold {})", product, stock, threshold)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                (
+                    name,
+                    format!("{} products are running low", products.len()),
+                    format!(
+                        "These products are at or below their low-stock threshold:\n\n{}",
+                        lines
+                    ),
+                )
+            }
+        };
+
+        let body = format!("Hi {},\n\n{}\n\n-- \nThe {} team\n", name, content, store);
+        (subject, body)
+    }
+}
+
+/// Base URL of the storefront, used to build links in messages.
+pub fn app_url() -> String {
+    dotenv().ok();
+    env::var("APP_URL").unwrap_or_else(|_| "http://localhost:3000".to_string())
+}
+
+fn verification_link(token: &str) -> String {
+    dotenv().ok();
+    let api_url = env::var("API_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
+    format!("{}/api/auth/verify-email?token={}", api_url, token)
+}
+
+fn store_name() -> String {
+    dotenv().ok();
+    env::var("STORE_NAME").unwrap_or_else(|_| "eStore".to_string())
+}
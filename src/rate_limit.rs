@@ -0,0 +1,450 @@
+use crate::auth::token::client_ip;
+use crate::prisma::PrismaClient;
+use crate::utils::{bearer_token, decode_token};
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER},
+    Error, HttpResponse,
+};
+use dotenv::dotenv;
+use futures_util::future::{BoxFuture, LocalBoxFuture};
+use prisma_client_rust::{raw, PrismaValue};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often each limiter clears out buckets that have been idle long enough to be full again.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A token bucket: holds up to `capacity` requests and refills `capacity` tokens every
+/// `period_seconds`.
+#[derive(Clone, Copy)]
+pub struct Limit {
+    pub capacity: f64,
+    pub period_seconds: f64,
+}
+
+impl Limit {
+    fn refill_per_second(&self) -> f64 {
+        self.capacity / self.period_seconds
+    }
+
+    /// Tokens in a bucket that held `tokens` `elapsed_seconds` ago.
+    fn refill(&self, tokens: f64, elapsed_seconds: f64) -> f64 {
+        (tokens + elapsed_seconds * self.refill_per_second()).min(self.capacity)
+    }
+
+    /// Seconds until a bucket holding `tokens` has a whole token again.
+    fn retry_after(&self, tokens: f64) -> u64 {
+        (((1.0 - tokens) / self.refill_per_second()).ceil() as u64).max(1)
+    }
+
+    /// Parses `<requests>/<seconds>`, e.g. `20/60`.
+    fn parse(value: &str) -> Option<Limit> {
+        let (capacity, period) = value.trim().split_once('/')?;
+        let capacity: f64 = capacity.trim().parse().ok()?;
+        let period_seconds: f64 = period.trim().parse().ok()?;
+        if capacity < 1.0 || period_seconds <= 0.0 {
+            return None;
+        }
+        Some(Limit {
+            capacity,
+            period_seconds,
+        })
+    }
+}
+
+/// What identifies a client for a scope's bucket.
+#[derive(Clone, Copy, PartialEq)]
+pub enum KeyBy {
+    Ip,
+    /// The `sub` of a valid Bearer token, falling back to the IP for anonymous requests.
+    User,
+}
+
+pub struct Decision {
+    pub allowed: bool,
+    /// Tokens left in the bucket after this request.
+    pub tokens: f64,
+}
+
+impl Decision {
+    /// Takes one token from a bucket holding `tokens`, if it has a whole one.
+    fn take(tokens: f64) -> Decision {
+        let allowed = tokens >= 1.0;
+        Decision {
+            allowed,
+            tokens: if allowed { tokens - 1.0 } else { tokens },
+        }
+    }
+}
+
+pub trait RateLimitStore: Send + Sync {
+    /// Takes one token from the bucket under `key`, refilling it first for the time elapsed
+    /// since it was last touched.
+    fn take<'a>(&'a self, key: &'a str, limit: Limit) -> BoxFuture<'a, Result<Decision, String>>;
+
+    /// Deletes the buckets under `prefix` that weren't touched for `idle_seconds`. Once a bucket
+    /// has been idle for its limit's period it is full again, the same as a missing one.
+    fn prune<'a>(&'a self, prefix: &'a str, idle_seconds: f64)
+        -> BoxFuture<'a, Result<(), String>>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Keeps buckets in process memory. Each server instance enforces its own limits.
+#[derive(Default)]
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimitStore for MemoryStore {
+    fn take<'a>(&'a self, key: &'a str, limit: Limit) -> BoxFuture<'a, Result<Decision, String>> {
+        Box::pin(async move {
+            let now = Instant::now();
+            let mut buckets = self.buckets.lock().map_err(|e| e.to_string())?;
+
+            let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+                tokens: limit.capacity,
+                updated_at: now,
+            });
+            let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+            let decision = Decision::take(limit.refill(bucket.tokens, elapsed));
+            bucket.tokens = decision.tokens;
+            bucket.updated_at = now;
+            Ok(decision)
+        })
+    }
+
+    fn prune<'a>(
+        &'a self,
+        prefix: &'a str,
+        idle_seconds: f64,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let now = Instant::now();
+            let mut buckets = self.buckets.lock().map_err(|e| e.to_string())?;
+            buckets.retain(|key, bucket| {
+                !key.starts_with(prefix)
+                    || now.duration_since(bucket.updated_at).as_secs_f64() < idle_seconds
+            });
+            Ok(())
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct BucketRow {
+    tokens: f64,
+    allowed: bool,
+}
+
+/// Keeps buckets in the `RateLimitBucket` table so every instance shares them. Refill and
+/// take happen in a single upsert, which Postgres serialises per row.
+pub struct PostgresStore {
+    pub prisma_client: Arc<PrismaClient>,
+}
+
+impl RateLimitStore for PostgresStore {
+    fn take<'a>(&'a self, key: &'a str, limit: Limit) -> BoxFuture<'a, Result<Decision, String>> {
+        Box::pin(async move {
+            let rows: Vec<BucketRow> = self
+                .prisma_client
+                ._query_raw(raw!(
+                    r#"INSERT INTO "RateLimitBucket" ("key", "tokens", "allowed", "updatedAt")
+                    VALUES ({}, {} - 1, true, NOW())
+                    ON CONFLICT ("key") DO UPDATE SET
+                        "allowed" = LEAST({}, "RateLimitBucket"."tokens"
+                            + EXTRACT(EPOCH FROM (NOW() - "RateLimitBucket"."updatedAt")) * {}) >= 1,
+                        "tokens" = LEAST({}, "RateLimitBucket"."tokens"
+                            + EXTRACT(EPOCH FROM (NOW() - "RateLimitBucket"."updatedAt")) * {})
+                            - CASE WHEN LEAST({}, "RateLimitBucket"."tokens"
+                                + EXTRACT(EPOCH FROM (NOW() - "RateLimitBucket"."updatedAt")) * {}) >= 1
+                                THEN 1 ELSE 0 END,
+                        "updatedAt" = NOW()
+                    RETURNING "tokens", "allowed""#,
+                    PrismaValue::String(key.to_string()),
+                    PrismaValue::Float(limit.capacity),
+                    PrismaValue::Float(limit.capacity),
+                    PrismaValue::Float(limit.refill_per_second()),
+                    PrismaValue::Float(limit.capacity),
+                    PrismaValue::Float(limit.refill_per_second()),
+                    PrismaValue::Float(limit.capacity),
+                    PrismaValue::Float(limit.refill_per_second())
+                ))
+                .exec()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            rows.into_iter()
+                .next()
+                .map(|row| Decision {
+                    allowed: row.allowed,
+                    tokens: row.tokens,
+                })
+                .ok_or_else(|| "rate limit upsert returned no row".to_string())
+        })
+    }
+
+    fn prune<'a>(
+        &'a self,
+        prefix: &'a str,
+        idle_seconds: f64,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            self.prisma_client
+                ._execute_raw(raw!(
+                    r#"DELETE FROM "RateLimitBucket"
+                    WHERE starts_with("key", {})
+                        AND "updatedAt" < NOW() - make_interval(secs => {})"#,
+                    PrismaValue::String(prefix.to_string()),
+                    PrismaValue::Float(idle_seconds)
+                ))
+                .exec()
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+}
+
+/// Picks the store from `RATE_LIMIT_STORE` (`memory`, the default, or `postgres`).
+pub fn store_from_env(prisma_client: Arc<PrismaClient>) -> Arc<dyn RateLimitStore> {
+    dotenv().ok();
+    match env::var("RATE_LIMIT_STORE").as_deref() {
+        Ok("postgres") => Arc::new(PostgresStore { prisma_client }),
+        _ => Arc::new(MemoryStore::default()),
+    }
+}
+
+/// Rate limits one route scope. The limit comes from `RATE_LIMIT_<SCOPE>` (`<requests>/<seconds>`
+/// or `off`) and the key from `RATE_LIMIT_<SCOPE>_KEY` (`ip` or `user`).
+#[derive(Clone)]
+pub struct RateLimiter {
+    scope: &'static str,
+    limit: Option<Limit>,
+    key_by: KeyBy,
+    store: Arc<dyn RateLimitStore>,
+    last_pruned: Arc<Mutex<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        scope: &'static str,
+        default_limit: &str,
+        default_key: KeyBy,
+        store: Arc<dyn RateLimitStore>,
+    ) -> Self {
+        dotenv().ok();
+        let name = format!("RATE_LIMIT_{}", scope.to_ascii_uppercase());
+        let configured = env::var(&name).unwrap_or_else(|_| default_limit.to_string());
+        let limit = if configured.trim().eq_ignore_ascii_case("off") {
+            None
+        } else {
+            Some(
+                Limit::parse(&configured)
+                    .unwrap_or_else(|| panic!("{} must look like <requests>/<seconds>", name)),
+            )
+        };
+        let key_by = match env::var(format!("{}_KEY", name)).as_deref() {
+            Ok("ip") => KeyBy::Ip,
+            Ok("user") => KeyBy::User,
+            _ => default_key,
+        };
+
+        RateLimiter {
+            scope,
+            limit,
+            key_by,
+            store,
+            last_pruned: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Clears this scope's idle buckets in the background, at most once per `PRUNE_INTERVAL`.
+    fn prune_if_due(&self, limit: Limit) {
+        match self.last_pruned.lock() {
+            Ok(mut last_pruned) if last_pruned.elapsed() >= PRUNE_INTERVAL => {
+                *last_pruned = Instant::now();
+            }
+            _ => return,
+        }
+
+        let store = Arc::clone(&self.store);
+        let scope = self.scope;
+        actix_web::rt::spawn(async move {
+            let prefix = format!("{}:", scope);
+            if let Err(err) = store.prune(&prefix, limit.period_seconds).await {
+                println!("rate limit prune failed for {}: {}", scope, err);
+            }
+        });
+    }
+
+    /// Buckets are keyed by user or by client address. The address is the TCP peer unless a
+    /// trusted proxy forwarded it (see `client_ip`), so clients can't pick their own bucket.
+    fn key(&self, req: &ServiceRequest) -> String {
+        let user = match self.key_by {
+            KeyBy::User => bearer_token(req.request())
+                .and_then(|token| decode_token(token).ok())
+                .map(|claims| claims.sub),
+            KeyBy::Ip => None,
+        };
+        match user {
+            Some(user_id) => format!("{}:user:{}", self.scope, user_id),
+            None => format!(
+                "{}:ip:{}",
+                self.scope,
+                client_ip(req.request()).unwrap_or_default()
+            ),
+        }
+    }
+}
+
+fn set_headers(headers: &mut HeaderMap, limit: Limit, tokens: f64) {
+    let reset = ((limit.capacity - tokens) / limit.refill_per_second()).ceil() as u64;
+    headers.insert(
+        HeaderName::from_static("x-ratelimit-limit"),
+        HeaderValue::from(limit.capacity as u64),
+    );
+    headers.insert(
+        HeaderName::from_static("x-ratelimit-remaining"),
+        HeaderValue::from(tokens.max(0.0).floor() as u64),
+    );
+    headers.insert(
+        HeaderName::from_static("x-ratelimit-reset"),
+        HeaderValue::from(reset),
+    );
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimiterMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            limiter: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            let limit = match limiter.limit {
+                Some(limit) => limit,
+                None => return Ok(service.call(req).await?.map_into_left_body()),
+            };
+
+            limiter.prune_if_due(limit);
+            let key = limiter.key(&req);
+            let decision = match limiter.store.take(&key, limit).await {
+                Ok(decision) => decision,
+                // An unavailable store must not take the API down with it.
+                Err(err) => {
+                    println!("rate limit store error for {}: {}", limiter.scope, err);
+                    return Ok(service.call(req).await?.map_into_left_body());
+                }
+            };
+
+            if !decision.allowed {
+                let retry_after = limit.retry_after(decision.tokens);
+                let mut http_res = HttpResponse::TooManyRequests()
+                    .insert_header((RETRY_AFTER, retry_after.to_string()))
+                    .json(json!({
+                        "error": "Too many requests",
+                        "retry_after": retry_after,
+                    }));
+                set_headers(http_res.headers_mut(), limit, decision.tokens);
+                let (http_req, _) = req.into_parts();
+                return Ok(ServiceResponse::new(http_req, http_res).map_into_right_body());
+            }
+
+            let mut res = service.call(req).await?;
+            set_headers(res.headers_mut(), limit, decision.tokens);
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(value: &str) -> Limit {
+        Limit::parse(value).unwrap()
+    }
+
+    #[test]
+    fn parses_requests_per_seconds() {
+        let parsed = limit(" 20 / 60 ");
+        assert_eq!(parsed.capacity, 20.0);
+        assert_eq!(parsed.period_seconds, 60.0);
+        assert!(Limit::parse("0/60").is_none());
+        assert!(Limit::parse("20/0").is_none());
+        assert!(Limit::parse("20").is_none());
+    }
+
+    #[test]
+    fn refills_proportionally_up_to_capacity() {
+        let parsed = limit("20/60");
+        assert_eq!(parsed.refill(0.0, 30.0), 10.0);
+        assert_eq!(parsed.refill(5.0, 3.0), 6.0);
+        assert_eq!(parsed.refill(19.0, 600.0), 20.0);
+    }
+
+    #[test]
+    fn takes_only_whole_tokens() {
+        let decision = Decision::take(1.5);
+        assert!(decision.allowed);
+        assert_eq!(decision.tokens, 0.5);
+
+        let decision = Decision::take(0.5);
+        assert!(!decision.allowed);
+        assert_eq!(decision.tokens, 0.5);
+    }
+
+    #[test]
+    fn retry_after_waits_for_the_next_token() {
+        let parsed = limit("20/60");
+        assert_eq!(parsed.retry_after(0.0), 3);
+        assert_eq!(parsed.retry_after(0.5), 2);
+        assert_eq!(parsed.retry_after(0.99), 1);
+    }
+}
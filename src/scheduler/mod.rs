@@ -0,0 +1,97 @@
+pub mod tasks;
+
+use crate::jobs::queue;
+use crate::prisma::PrismaClient;
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use dotenv::dotenv;
+use serde_json::json;
+use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Longest the scheduler sleeps between checks, so a clock jump is noticed in time.
+const MAX_SLEEP_SECONDS: i64 = 60;
+
+struct ScheduledTask {
+    kind: &'static str,
+    schedule: Schedule,
+    next: Option<DateTime<Utc>>,
+}
+
+/// Reads `env_key` as a cron expression with seconds (`sec min hour day month weekday`), or
+/// `off` to disable the task. An invalid expression stops the server at startup.
+fn schedule(env_key: &str, default: &str) -> Option<Schedule> {
+    dotenv().ok();
+    let expression = env::var(env_key).unwrap_or_else(|_| default.to_string());
+    if expression.trim().eq_ignore_ascii_case("off") {
+        return None;
+    }
+    Some(
+        Schedule::from_str(expression.trim())
+            .unwrap_or_else(|e| panic!("invalid schedule {}={}: {}", env_key, expression, e)),
+    )
+}
+
+fn tasks_from_env() -> Vec<ScheduledTask> {
+    [
+        (
+            tasks::EXPIRE_ORDERS_JOB,
+            schedule("SCHEDULE_EXPIRE_ORDERS", "0 */15 * * * *"),
+        ),
+        (
+            tasks::LOW_STOCK_DIGEST_JOB,
+            schedule("SCHEDULE_LOW_STOCK_DIGEST", "0 0 8 * * *"),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(kind, schedule)| {
+        schedule.map(|schedule| ScheduledTask {
+            kind,
+            next: schedule.upcoming(Utc).next(),
+            schedule,
+        })
+    })
+    .collect()
+}
+
+/// Queues each task as a job when it falls due; the job worker does the actual work. Every
+/// instance runs a scheduler, and the job's unique key makes sure only one of them queues
+/// a given run.
+pub fn spawn_scheduler(prisma_client: Arc<PrismaClient>) {
+    let mut tasks = tasks_from_env();
+    if tasks.is_empty() {
+        return;
+    }
+    actix_web::rt::spawn(async move {
+        loop {
+            let now = Utc::now();
+            for task in tasks.iter_mut() {
+                let due = match task.next {
+                    Some(due) if due <= now => due,
+                    _ => continue,
+                };
+                let unique_key = format!("{}@{}", task.kind, due.to_rfc3339());
+                let payload = json!({"scheduledFor": due});
+                if let Err(err) =
+                    queue::enqueue_unique(&prisma_client, task.kind, payload, &unique_key).await
+                {
+                    println!("failed to schedule {}: {:?}", task.kind, err);
+                }
+                task.next = task.schedule.after(&now).next();
+            }
+
+            let sleep_seconds =
+                tasks
+                    .iter()
+                    .filter_map(|task| task.next)
+                    .min()
+                    .map_or(MAX_SLEEP_SECONDS, |next| {
+                        (next - Utc::now())
+                            .num_seconds()
+                            .clamp(1, MAX_SLEEP_SECONDS)
+                    });
+            actix_web::rt::time::sleep(std::time::Duration::from_secs(sleep_seconds as u64)).await;
+        }
+    });
+}
@@ -0,0 +1,154 @@
+use crate::audit::{self, AuditEvent};
+use crate::inventory;
+use crate::notification::{outbox, templates::Template};
+use crate::prisma::{order, product, user, PrismaClient, RoleType};
+use chrono::{Duration, Utc};
+use dotenv::dotenv;
+use prisma_client_rust::{raw, QueryError};
+use serde_json::{json, Value};
+use std::env;
+
+pub const EXPIRE_ORDERS_JOB: &str = "orders.expire_stale";
+pub const LOW_STOCK_DIGEST_JOB: &str = "inventory.low_stock_digest";
+
+fn pending_order_ttl_hours() -> i64 {
+    dotenv().ok();
+    env::var("ORDER_PENDING_TTL_HOURS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(72)
+}
+
+/// Expires orders left pending longer than `ORDER_PENDING_TTL_HOURS`, puts back the stock they
+/// held and tells the customer.
+pub async fn expire_stale_orders(prisma_client: &PrismaClient) -> Result<Value, String> {
+    let cutoff = (Utc::now() - Duration::hours(pending_order_ttl_hours())).fixed_offset();
+    let stale = prisma_client
+        .order()
+        .find_many(vec![
+            order::status::equals("pending".to_string()),
+            order::created_at::lt(cutoff),
+        ])
+        .with(order::user::fetch())
+        .exec()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut expired = 0;
+    for stale_order in stale {
+        let order_id = stale_order.id.clone();
+        let released: Result<bool, QueryError> = prisma_client
+            ._transaction()
+            .run(|client| {
+                Box::pin(async move {
+                    // Guarded on the status so an order approved in the meantime is left
+                    // alone. The update also locks the order until the stock is back.
+                    let updated = client
+                        .order()
+                        .update_many(
+                            vec![
+                                order::id::equals(order_id.clone()),
+                                order::status::equals("pending".to_string()),
+                            ],
+                            vec![order::status::set("expired".to_string())],
+                        )
+                        .exec()
+                        .await?;
+                    if updated == 0 {
+                        return Ok(false);
+                    }
+                    inventory::release(&client, &format!("order:{}", order_id), None).await?;
+                    Ok(true)
+                })
+            })
+            .await;
+        if !released.map_err(|e| e.to_string())? {
+            continue;
+        }
+        expired += 1;
+
+        let before = audit::order_snapshot(&stale_order);
+        let mut after = before.clone();
+        after["status"] = json!("expired");
+        AuditEvent::new("order.expired", "order")
+            .entity(&stale_order.id)
+            .before(before)
+            .after(after)
+            .record_or_log(prisma_client)
+            .await;
+
+        if let Some(customer) = stale_order.user.as_deref() {
+            let template = Template::OrderExpired {
+                name: customer.first_name.clone(),
+                order_id: stale_order.id.clone(),
+            };
+            outbox::enqueue(prisma_client, &customer.email, template)
+                .await
+                .ok();
+        }
+    }
+
+    Ok(json!({"expired": expired}))
+}
+
+/// Flags products at or below their own `lowStockThreshold`, clears the flag on restocked
+/// ones, and emails the flagged list to every admin.
+pub async fn low_stock_digest(prisma_client: &PrismaClient) -> Result<Value, String> {
+    prisma_client
+        ._execute_raw(raw!(
+            r#"UPDATE "Product" SET "lowStockSince" = NOW()
+            WHERE "lowStockSince" IS NULL AND "deletedAt" IS NULL
+                AND "stock" <= "lowStockThreshold""#
+        ))
+        .exec()
+        .await
+        .map_err(|e| e.to_string())?;
+    prisma_client
+        ._execute_raw(raw!(
+            r#"UPDATE "Product" SET "lowStockSince" = NULL
+            WHERE "lowStockSince" IS NOT NULL
+                AND ("stock" > "lowStockThreshold" OR "deletedAt" IS NOT NULL)"#
+        ))
+        .exec()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let low = prisma_client
+        .product()
+        .find_many(vec![
+            product::low_stock_since::not(None),
+            product::deleted_at::equals(None),
+        ])
+        .order_by(product::stock::order(prisma_client_rust::Direction::Asc))
+        .exec()
+        .await
+        .map_err(|e| e.to_string())?;
+    if low.is_empty() {
+        return Ok(json!({"lowStock": 0, "notified": 0}));
+    }
+
+    let admins = prisma_client
+        .user()
+        .find_many(vec![
+            user::role::equals(RoleType::Admin),
+            user::deleted_at::equals(None),
+        ])
+        .exec()
+        .await
+        .map_err(|e| e.to_string())?;
+    let products = low
+        .iter()
+        .map(|p| (p.name.clone(), p.stock, p.low_stock_threshold))
+        .collect::<Vec<_>>();
+    for admin in &admins {
+        let template = Template::LowStockDigest {
+            name: admin.first_name.clone(),
+            products: products.clone(),
+        };
+        outbox::enqueue(prisma_client, &admin.email, template)
+            .await
+            .ok();
+    }
+
+    Ok(json!({"lowStock": low.len(), "notified": admins.len()}))
+}
@@ -1,24 +1,57 @@
 use std::{
     future::{ready, Ready},
     rc::Rc,
+    sync::Arc,
 };
 use actix_web::{
     body::EitherBody,
     dev::{forward_ready,Service, ServiceRequest, ServiceResponse, Transform},
     http::header::AUTHORIZATION,
-    Error, HttpMessage, HttpResponse,
+    web, Error, HttpMessage, HttpRequest, HttpResponse,
 };
 use chrono::Utc;
 use futures_util::future::LocalBoxFuture;
 use dotenv::dotenv;
 use std::env;
-use jsonwebtoken::{decode, DecodingKey, Validation, Algorithm };
+use super::auth::api_key::{self, ApiKeyRejection, API_KEY_HEADER};
+use super::auth::keys;
 use super::auth::model::Claims;
+use super::prisma::{session, user, PrismaClient, RoleType};
 
-pub fn get_secret_key() -> String {
+/// Policy switch: when `REQUIRE_VERIFIED_EMAIL_FOR_ORDERS` is true, unverified users can't order.
+pub fn require_verified_email_for_orders() -> bool {
     dotenv().ok();
-    env::var("JWT_SECRET")
-        .expect("SECRET_KEY must be set")
+    env::var("REQUIRE_VERIFIED_EMAIL_FOR_ORDERS")
+        .map(|v| v.trim().eq_ignore_ascii_case("true") || v.trim() == "1")
+        .unwrap_or(false)
+}
+
+/// Password, 2FA, sign-in method and payment changes are off limits while an admin is
+/// impersonating the user. Returns the response to send back in that case.
+pub fn reject_impersonation(claims: &Claims) -> Option<HttpResponse> {
+    claims.impersonator.as_ref().map(|_| {
+        HttpResponse::Forbidden()
+            .json(serde_json::json!({"error": "Not allowed while impersonating a user"}))
+    })
+}
+
+/// Handlers that act on the caller's own account treat `sub` as a user id. An API key's `sub`
+/// is the key's id, so such handlers turn keys away. Returns the response to send back then.
+pub fn reject_api_key(claims: &Claims) -> Option<HttpResponse> {
+    claims.api_key_id.as_ref().map(|_| {
+        HttpResponse::Forbidden().json(serde_json::json!({"error": "Not available to API keys"}))
+    })
+}
+
+pub fn decode_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+    keys::store().verify::<Claims>(token)
+}
+
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
 }
 
 pub struct Authentication;
@@ -61,29 +94,101 @@ where
  
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let service = Rc::clone(&self.service);
-        let secret = get_secret_key();
  
         Box::pin(async move {
+            // Integrations authenticate with an API key instead of a user token. Keys only
+            // reach admin routes, limited to their scopes.
+            if let Some(key) = req
+                .headers()
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+            {
+                let required = api_key::required_scope(req.path(), req.method());
+                let result = match (req.app_data::<web::Data<Arc<PrismaClient>>>(), required) {
+                    (Some(prisma_client), Some(required)) => {
+                        api_key::authenticate(prisma_client, &key, &required).await
+                    }
+                    _ => Err(ApiKeyRejection::Invalid),
+                };
+                return match result {
+                    Ok(claims) => {
+                        req.extensions_mut().insert(claims);
+                        let res = service.call(req).await?;
+                        Ok(res.map_into_left_body())
+                    }
+                    Err(rejection) => {
+                        let http_res = match rejection {
+                            ApiKeyRejection::Invalid => HttpResponse::Unauthorized().finish(),
+                            ApiKeyRejection::MissingScope(scope) => HttpResponse::Forbidden()
+                                .json(serde_json::json!({
+                                    "error": format!("API key lacks the {} scope", scope)
+                                })),
+                        };
+                        let (http_req, _) = req.into_parts();
+                        let res = ServiceResponse::new(http_req, http_res);
+                        Ok(res.map_into_right_body())
+                    }
+                };
+            }
+
             if let Some(auth_header) = req.headers().get(AUTHORIZATION) {
                 if let Ok(auth_str)= auth_header.to_str() {
                     if auth_str.starts_with("Bearer ") {
                         let token = &auth_str[7..];
-                        let validation = Validation::new(Algorithm::HS256);
-                        let decoding_key = DecodingKey::from_secret(secret.as_bytes());
-                        match decode::<Claims>(token, &decoding_key, &validation) {
-                            Ok(token_data) => {
-                                // Store the claims in a variable
-                                let claims = token_data.claims;
-
+                        match decode_token(token) {
+                            Ok(mut claims) => {
                                 let current_time = Utc::now().timestamp() as usize;
 
-                                if claims.exp < current_time {
+                                // A 2FA-pending token only proves the password; it is good for
+                                // /otp/validate and nothing else.
+                                if claims.exp < current_time || claims.two_factor_pending {
                                     let http_res = HttpResponse::Unauthorized().finish();
                                     let (http_req, _) = req.into_parts();
                                     let res = ServiceResponse::new(http_req, http_res);
                                     return Ok(res.map_into_right_body());
                                 }
 
+                                // Tokens are only as good as the user's current token version; a
+                                // password, role or account change bumps it and cuts them all off.
+                                // A token tied to a session also ends with that session, so logging
+                                // out or revoking a session takes effect right away.
+                                let user_record = match req.app_data::<web::Data<Arc<PrismaClient>>>() {
+                                    Some(prisma_client) => {
+                                        let mut query = prisma_client
+                                            .user()
+                                            .find_unique(user::id::equals(claims.sub.clone()));
+                                        if let Some(session_id) = &claims.sid {
+                                            query = query.with(user::sessions::fetch(vec![
+                                                session::id::equals(session_id.clone()),
+                                                session::revoked_at::equals(None),
+                                                session::expires_at::gt(Utc::now().fixed_offset()),
+                                            ]));
+                                        }
+                                        query.exec().await.ok().flatten()
+                                    }
+                                    None => None,
+                                };
+                                match user_record {
+                                    Some(user_record)
+                                        if user_record.token_version == claims.ver
+                                            && user_record.deleted_at.is_none()
+                                            && (claims.sid.is_none()
+                                                || user_record
+                                                    .sessions
+                                                    .as_ref()
+                                                    .map_or(false, |sessions| !sessions.is_empty())) =>
+                                    {
+                                        claims.is_admin = user_record.role == RoleType::Admin;
+                                    }
+                                    _ => {
+                                        let http_res = HttpResponse::Unauthorized().finish();
+                                        let (http_req, _) = req.into_parts();
+                                        let res = ServiceResponse::new(http_req, http_res);
+                                        return Ok(res.map_into_right_body());
+                                    }
+                                }
+
                                 // Insert the claims into the request's extensions
                                 req.extensions_mut().insert(claims);
 